@@ -1,10 +1,10 @@
 use raftify::Result;
-use raftify_cli::cli_handler;
+use raftify_cli::cli_handler_with_fsm;
 
 use memstore_example_harness::state_machine::{HashStore, LogEntry, StorageType};
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    cli_handler::<LogEntry, StorageType, HashStore>(None).await?;
+    cli_handler_with_fsm::<LogEntry, StorageType, HashStore>(None, HashStore::default()).await?;
     Ok(())
 }
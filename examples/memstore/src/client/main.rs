@@ -17,6 +17,7 @@ async fn main() {
             }
             .encode()
             .unwrap(),
+            ..Default::default()
         })
         .await
         .unwrap();
@@ -34,6 +34,17 @@ impl AbstractLogEntry for LogEntry {
     }
 }
 
+// `apply` returns the applied entry, so `propose_entry` resolves with it.
+impl raftify::AbstractOutput for LogEntry {
+    fn encode(&self) -> Result<Vec<u8>> {
+        AbstractLogEntry::encode(self)
+    }
+
+    fn decode(bytes: &[u8]) -> Result<LogEntry> {
+        AbstractLogEntry::decode(bytes)
+    }
+}
+
 #[derive(Clone, Debug, Default)]
 pub struct HashStore(pub Arc<RwLock<HashMap<u64, String>>>);
 
@@ -49,6 +60,8 @@ impl HashStore {
 
 #[async_trait]
 impl AbstractStateMachine for HashStore {
+    type Output = LogEntry;
+
     async fn apply(&mut self, data: Vec<u8>) -> Result<Vec<u8>> {
         let log_entry: LogEntry = LogEntry::decode(&data)?;
         match log_entry {
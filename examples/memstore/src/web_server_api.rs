@@ -1,7 +1,6 @@
 use std::collections::HashMap;
 
 use actix_web::{get, post, put, web, HttpResponse, Responder};
-use raftify::{raft::Storage, AbstractLogEntry, StableStorage};
 use serde_json::Value;
 
 use crate::state_machine::{HashStore, LogEntry, Raft};
@@ -12,7 +11,7 @@ async fn put(data: web::Data<(HashStore, Raft)>, path: web::Path<(u64, String)>)
         key: path.0,
         value: path.1.clone(),
     };
-    data.1.propose(log_entry.encode().unwrap()).await.unwrap();
+    data.1.propose_entry(&log_entry).await.unwrap();
 
     "OK".to_string()
 }
@@ -21,6 +20,11 @@ async fn put(data: web::Data<(HashStore, Raft)>, path: web::Path<(u64, String)>)
 async fn get(data: web::Data<(HashStore, Raft)>, path: web::Path<u64>) -> impl Responder {
     let key = path.into_inner();
 
+    // Confirm the read index first so the lookup doesn't observe stale data.
+    if let Err(e) = data.1.read_index().await {
+        return HttpResponse::ServiceUnavailable().body(format!("Read index failed: {}", e));
+    }
+
     match data.0.get(key) {
         Some(value) => HttpResponse::Ok().body(value),
         None => HttpResponse::BadRequest().body("Bad Request: Item not found"),
@@ -71,24 +75,8 @@ async fn snapshot(data: web::Data<(HashStore, Raft)>) -> impl Responder {
     #[cfg(not(feature = "inmemory_storage"))]
     {
         let raft = data.clone();
-        let last_index = raft
-            .1
-            .storage()
-            .await
-            .expect("Failed to get storage")
-            .last_index()
-            .expect("Failed to get last index");
-
-        let hard_state = raft
-            .1
-            .storage()
-            .await
-            .expect("Failed to get storage")
-            .hard_state()
-            .expect("Failed to get hard state");
-
         raft.1
-            .make_snapshot(last_index, hard_state.term)
+            .create_snapshot()
             .await
             .expect("Failed to make snapshot");
         "OK".to_string()
@@ -0,0 +1,264 @@
+use raftify::{
+    async_trait, raft::eraftpb::Message as RaftMessage, ForwardedRequest, ForwardedResponse,
+    InboundMessages, Peer, Result, SendMessageError, Transport,
+};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::{sync::oneshot, time::sleep};
+
+/// Groups of node IDs that can only reach the nodes of their own group.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Partition {
+    groups: HashMap<u64, usize>,
+}
+
+impl Partition {
+    /// Nodes not listed in any group can't reach any other node.
+    pub(crate) fn new(groups: &[&[u64]]) -> Self {
+        let groups = groups
+            .iter()
+            .enumerate()
+            .flat_map(|(group, nodes)| nodes.iter().map(move |node_id| (*node_id, group)))
+            .collect();
+        Self { groups }
+    }
+
+    pub(crate) fn is_connected(&self, from: u64, to: u64) -> bool {
+        if self.groups.is_empty() || from == to {
+            return true;
+        }
+        matches!(
+            (self.groups.get(&from), self.groups.get(&to)),
+            (Some(a), Some(b)) if a == b
+        )
+    }
+}
+
+struct FaultState {
+    rng: StdRng,
+    partition: Partition,
+    drop_rate: f64,
+    latency: Duration,
+    /// Node served at each address, to tell who the forwarded requests are for.
+    nodes: HashMap<SocketAddr, u64>,
+}
+
+/// Injects network faults into a cluster whose nodes run on transports wrapped by
+/// `FaultInjector::wrap`.
+///
+/// Faults are applied to the messages a node receives, whichever node sent them.
+/// Senders are also told right away when they are partitioned from the receiver,
+/// and requests forwarded to the leader are subject to the same faults.
+#[derive(Clone)]
+pub struct FaultInjector {
+    state: Arc<Mutex<FaultState>>,
+}
+
+impl FaultInjector {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            state: Arc::new(Mutex::new(FaultState {
+                rng: StdRng::seed_from_u64(seed),
+                partition: Partition::default(),
+                drop_rate: 0.0,
+                latency: Duration::ZERO,
+                nodes: HashMap::new(),
+            })),
+        }
+    }
+
+    /// Wraps the transport of the given node.
+    pub fn wrap<T: Transport>(&self, node_id: u64, transport: T) -> FaultyTransport<T> {
+        FaultyTransport {
+            inner: transport,
+            injector: self.clone(),
+            node_id,
+        }
+    }
+
+    /// Splits the cluster into the given groups of node IDs.
+    pub fn partition(&self, groups: &[&[u64]]) {
+        self.state.lock().unwrap().partition = Partition::new(groups);
+    }
+
+    pub fn heal(&self) {
+        self.state.lock().unwrap().partition = Partition::default();
+    }
+
+    /// Drops the given fraction of the received messages.
+    pub fn drop_messages(&self, rate: f64) {
+        self.state.lock().unwrap().drop_rate = rate;
+    }
+
+    /// Delays every received message by the given latency.
+    pub fn add_latency(&self, latency: Duration) {
+        self.state.lock().unwrap().latency = latency;
+    }
+
+    /// Removes every injected fault.
+    pub fn reset(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.partition = Partition::default();
+        state.drop_rate = 0.0;
+        state.latency = Duration::ZERO;
+    }
+
+    fn check_link(&self, from: u64, to: u64) -> std::result::Result<(), SendMessageError> {
+        if self.state.lock().unwrap().partition.is_connected(from, to) {
+            Ok(())
+        } else {
+            Err(SendMessageError::ConnectionError(format!(
+                "Node {} is partitioned from node {}",
+                from, to
+            )))
+        }
+    }
+
+    fn should_drop(&self) -> bool {
+        let mut state = self.state.lock().unwrap();
+        let drop_rate = state.drop_rate;
+        drop_rate > 0.0 && state.rng.gen_bool(drop_rate)
+    }
+
+    /// Fails the request if it doesn't survive the injected faults.
+    fn check_request(
+        &self,
+        from: u64,
+        addr: SocketAddr,
+    ) -> std::result::Result<(), SendMessageError> {
+        let to = self.state.lock().unwrap().nodes.get(&addr).copied();
+        if let Some(to) = to {
+            self.check_link(from, to)?;
+        }
+
+        if self.should_drop() {
+            return Err(SendMessageError::TransmissionError(format!(
+                "Request to {} was dropped",
+                addr
+            )));
+        }
+        Ok(())
+    }
+
+    async fn delay(&self) {
+        let latency = self.state.lock().unwrap().latency;
+        if !latency.is_zero() {
+            sleep(latency).await;
+        }
+    }
+
+    /// Returns the message if it survives the injected faults.
+    async fn receive(&self, message: RaftMessage) -> Option<RaftMessage> {
+        if self
+            .check_link(message.get_from(), message.get_to())
+            .is_err()
+            || self.should_drop()
+        {
+            return None;
+        }
+
+        self.delay().await;
+        Some(message)
+    }
+}
+
+/// Transport that hands the messages surviving the injected faults over to the node.
+pub struct FaultyTransport<T> {
+    inner: T,
+    injector: FaultInjector,
+    node_id: u64,
+}
+
+#[async_trait]
+impl<T: Transport> Transport for FaultyTransport<T> {
+    async fn send_messages(
+        &self,
+        peer: &Peer,
+        messages: Vec<RaftMessage>,
+    ) -> std::result::Result<(), SendMessageError> {
+        for message in messages.iter() {
+            self.injector
+                .check_link(message.get_from(), message.get_to())?;
+        }
+        self.inner.send_messages(peer, messages).await
+    }
+
+    async fn send_snapshot(
+        &self,
+        peer: &Peer,
+        message: RaftMessage,
+    ) -> std::result::Result<(), SendMessageError> {
+        self.injector
+            .check_link(message.get_from(), message.get_to())?;
+        self.inner.send_snapshot(peer, message).await
+    }
+
+    async fn forward(
+        &self,
+        addr: SocketAddr,
+        request: ForwardedRequest,
+    ) -> std::result::Result<ForwardedResponse, SendMessageError> {
+        self.injector.check_request(self.node_id, addr)?;
+        self.injector.delay().await;
+        self.inner.forward(addr, request).await
+    }
+
+    async fn serve(
+        &self,
+        addr: SocketAddr,
+        inbound: InboundMessages,
+        rx_quit: oneshot::Receiver<()>,
+    ) -> Result<()> {
+        self.injector
+            .state
+            .lock()
+            .unwrap()
+            .nodes
+            .insert(addr, self.node_id);
+
+        let injector = self.injector.clone();
+        let inbound = inbound.intercept(move |message| {
+            let injector = injector.clone();
+            async move { injector.receive(message).await }
+        });
+        self.inner.serve(addr, inbound, rx_quit).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_partition_connectivity() {
+        let partition = Partition::new(&[&[1, 2], &[3]]);
+
+        assert!(partition.is_connected(1, 2));
+        assert!(!partition.is_connected(1, 3));
+        assert!(!partition.is_connected(3, 2));
+        // Nodes not listed in any group are isolated
+        assert!(!partition.is_connected(4, 1));
+        assert!(partition.is_connected(4, 4));
+
+        assert!(Partition::default().is_connected(1, 3));
+    }
+
+    #[test]
+    fn test_forwarded_requests_respect_partitions() {
+        let injector = FaultInjector::new(0);
+        let addr: SocketAddr = "127.0.0.1:60062".parse().unwrap();
+        injector.state.lock().unwrap().nodes.insert(addr, 2);
+        assert!(injector.check_request(1, addr).is_ok());
+
+        injector.partition(&[&[1], &[2]]);
+        assert!(matches!(
+            injector.check_request(1, addr),
+            Err(SendMessageError::ConnectionError(_))
+        ));
+    }
+}
@@ -1,7 +1,9 @@
 pub mod config;
 pub mod constant;
+pub mod fault_injector;
 pub mod logger;
 pub mod raft;
+pub mod simulator;
 pub mod state_machine;
 pub mod test_environment;
 pub mod utils;
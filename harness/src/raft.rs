@@ -1,17 +1,20 @@
 use futures::future;
 use raftify::{
     raft::{formatter::set_custom_formatter, logger::Slogger},
-    CustomFormatter, HeedStorage, Peers, Raft as Raft_, Result, StableStorage,
+    CustomFormatter, GrpcTransport, HeedStorage, InProcessTransport, InitialRole, Peers,
+    Raft as Raft_, Result, StableStorage,
 };
 use std::{
     collections::HashMap,
+    net::SocketAddr,
     sync::{mpsc, Arc},
     time::Duration,
 };
-use tokio::task::JoinHandle;
+use tokio::{net::TcpListener, task::JoinHandle};
 
 use crate::{
     config::build_config,
+    fault_injector::FaultInjector,
     logger::build_file_logger,
     state_machine::{HashStore, LogEntry},
     utils::{ensure_directory_exist, get_storage_path},
@@ -19,6 +22,14 @@ use crate::{
 
 pub type Raft = Raft_<LogEntry, HeedStorage, HashStore>;
 
+/// How the nodes of a harness cluster exchange raft messages.
+#[derive(Clone)]
+enum ClusterTransport {
+    Grpc,
+    InProcess(InProcessTransport),
+    Faulty(FaultInjector),
+}
+
 pub async fn wait_until_rafts_ready(
     rafts: Option<HashMap<u64, Raft>>,
     rx_initialized_raft: mpsc::Receiver<(u64, Raft)>,
@@ -59,6 +70,7 @@ fn run_raft(
     base_storage_path: &str,
     peers: Peers,
     should_be_leader: bool,
+    transport: ClusterTransport,
 ) -> Result<JoinHandle<Result<()>>> {
     let peer = peers.get(node_id).unwrap();
     let cfg = build_config(
@@ -82,16 +94,35 @@ fn run_raft(
         }),
     )?;
 
-    let raft = Raft::bootstrap(
-        *node_id,
-        peer.addr,
-        storage,
-        store,
-        cfg,
-        Arc::new(Slogger {
-            slog: logger.clone(),
-        }),
-    )
+    let raft_logger = Arc::new(Slogger {
+        slog: logger.clone(),
+    });
+    let raft = match transport {
+        ClusterTransport::Grpc => {
+            Raft::bootstrap(*node_id, peer.addr, storage, store, cfg, raft_logger)
+        }
+        ClusterTransport::InProcess(transport) => Raft::bootstrap_with_transport(
+            *node_id,
+            peer.addr,
+            storage,
+            store,
+            cfg,
+            raft_logger,
+            transport,
+        ),
+        ClusterTransport::Faulty(injector) => {
+            let transport = injector.wrap(*node_id, GrpcTransport::new(&cfg));
+            Raft::bootstrap_with_transport(
+                *node_id,
+                peer.addr,
+                storage,
+                store,
+                cfg,
+                raft_logger,
+                transport,
+            )
+        }
+    }
     .expect("Raft build failed!");
 
     tx_initialized_raft
@@ -107,42 +138,129 @@ pub async fn build_raft_cluster(
     tx_initialized_raft: mpsc::Sender<(u64, Raft)>,
     base_storage_path: String,
     peers: Peers,
+) -> Result<()> {
+    build_cluster(
+        tx_initialized_raft,
+        base_storage_path,
+        peers,
+        ClusterTransport::Grpc,
+    )
+    .await
+}
+
+/// Builds a cluster whose nodes exchange raft messages through channels instead of gRPC.
+pub async fn build_in_process_raft_cluster(
+    tx_initialized_raft: mpsc::Sender<(u64, Raft)>,
+    base_storage_path: String,
+    peers: Peers,
+) -> Result<()> {
+    let transport = ClusterTransport::InProcess(InProcessTransport::new());
+    build_cluster(tx_initialized_raft, base_storage_path, peers, transport).await
+}
+
+/// Starts a gRPC cluster whose nodes receive their messages through the given `FaultInjector`.
+/// Returns the task running each node, so that it can be passed to `crash_node`.
+pub fn build_raft_cluster_with_faults(
+    tx_initialized_raft: mpsc::Sender<(u64, Raft)>,
+    base_storage_path: String,
+    peers: Peers,
+    injector: FaultInjector,
+) -> Result<HashMap<u64, JoinHandle<Result<()>>>> {
+    let transport = ClusterTransport::Faulty(injector);
+    spawn_cluster(tx_initialized_raft, &base_storage_path, peers, transport)
+}
+
+async fn build_cluster(
+    tx_initialized_raft: mpsc::Sender<(u64, Raft)>,
+    base_storage_path: String,
+    peers: Peers,
+    transport: ClusterTransport,
 ) -> Result<()> {
     let logger = build_file_logger(&base_storage_path);
+    let raft_handles = spawn_cluster(tx_initialized_raft, &base_storage_path, peers, transport)?;
+
+    let (node_ids, raft_handles): (Vec<_>, Vec<_>) = raft_handles.into_iter().unzip();
+    let results = future::join_all(raft_handles).await;
+
+    for (node_id, result) in node_ids.iter().zip(results) {
+        match result {
+            Ok(_) => slog::info!(logger, "All tasks completed successfully"),
+            Err(e) => slog::error!(
+                logger,
+                "Error occurred while running node {}. Error: {:?}",
+                node_id,
+                e
+            ),
+        }
+    }
+
+    Ok(())
+}
+
+fn spawn_cluster(
+    tx_initialized_raft: mpsc::Sender<(u64, Raft)>,
+    base_storage_path: &str,
+    peers: Peers,
+    transport: ClusterTransport,
+) -> Result<HashMap<u64, JoinHandle<Result<()>>>> {
+    let logger = build_file_logger(base_storage_path);
 
     set_custom_formatter(CustomFormatter::<LogEntry, HashStore>::new());
 
-    let mut raft_handles = vec![];
+    let mut raft_handles = HashMap::new();
     let should_be_leader = peers.len() <= 1;
 
     for (node_id, _) in peers.iter() {
         let raft_handle = run_raft(
             tx_initialized_raft.clone(),
             &node_id,
-            &base_storage_path,
+            base_storage_path,
             peers.clone(),
             should_be_leader,
+            transport.clone(),
         )?;
-        raft_handles.push(raft_handle);
+        raft_handles.insert(node_id, raft_handle);
 
         slog::info!(logger, "Node {} starting...", node_id);
     }
 
-    let results = future::join_all(raft_handles).await;
+    Ok(raft_handles)
+}
 
-    for (result_idx, result) in results.iter().enumerate() {
-        match result {
-            Ok(_) => slog::info!(logger, "All tasks completed successfully"),
-            Err(e) => slog::error!(
-                logger,
-                "Error occurred while running node {}. Error: {:?}",
-                result_idx + 1,
-                e
-            ),
-        }
+/// Aborts the task running the node, as if its process had crashed, and waits until
+/// its port is released. The storage is only released once every clone of `raft` is dropped.
+pub async fn crash_node(raft: Raft, raft_task: JoinHandle<Result<()>>, raft_addr: SocketAddr) {
+    raft_task.abort();
+    let _ = raft_task.await;
+    std::mem::drop(raft);
+
+    while TcpListener::bind(raft_addr).await.is_err() {
+        tokio::time::sleep(Duration::from_millis(10)).await;
     }
+}
 
-    Ok(())
+/// Restarts a crashed node of a `build_raft_cluster_with_faults` cluster on its previous storage directory.
+pub fn restart_node(
+    tx_initialized_raft: mpsc::Sender<(u64, Raft)>,
+    node_id: u64,
+    base_storage_path: &str,
+    peers: Peers,
+    injector: FaultInjector,
+) -> Result<JoinHandle<Result<()>>> {
+    // A restarted node must not claim the leadership given to it by the initial roles.
+    let mut voters = Peers::with_empty();
+    for (id, peer) in peers.iter() {
+        voters.add_peer(id, peer.addr, Some(InitialRole::Voter));
+    }
+
+    run_raft(
+        tx_initialized_raft,
+        &node_id,
+        base_storage_path,
+        voters,
+        false,
+        ClusterTransport::Faulty(injector),
+    )
 }
 
 pub async fn spawn_extra_node(
@@ -0,0 +1,500 @@
+use raftify::{
+    async_trait,
+    raft::{
+        eraftpb::{Message as RaftMessage, MessageType},
+        logger::Slogger,
+    },
+    ConfigBuilder, ForwardedRequest, ForwardedResponse, HeedStorage, InboundMessages, InitialRole,
+    Peer, Peers, RaftConfig, Result, SendMessageError, Transport,
+};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use std::{
+    collections::{BTreeMap, HashMap},
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tempfile::TempDir;
+use tokio::{
+    sync::{mpsc, oneshot},
+    task::JoinHandle,
+    time::{sleep, sleep_until, Instant},
+};
+
+use crate::{
+    fault_injector::Partition,
+    logger::build_file_logger,
+    raft::Raft,
+    state_machine::HashStore,
+    utils::{ensure_directory_exist, get_storage_path},
+};
+
+/// How the simulated network treats the messages it carries.
+#[derive(Clone, Debug)]
+pub struct NetworkConfig {
+    /// Probability of silently losing a message.
+    pub drop_rate: f64,
+    pub min_delay: Duration,
+    pub max_delay: Duration,
+    /// Whether messages between two nodes may overtake each other.
+    pub reorder: bool,
+}
+
+impl Default for NetworkConfig {
+    fn default() -> Self {
+        Self {
+            drop_rate: 0.0,
+            min_delay: Duration::from_millis(1),
+            max_delay: Duration::from_millis(10),
+            reorder: false,
+        }
+    }
+}
+
+/// A message delivered by the simulated network, as recorded in its trace.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DeliveredMessage {
+    /// Virtual time elapsed since the network was created.
+    pub at: Duration,
+    pub from: u64,
+    pub to: u64,
+    pub msg_type: MessageType,
+    pub term: u64,
+    pub index: u64,
+    pub commit: u64,
+}
+
+struct NetworkState {
+    rng: StdRng,
+    started: Instant,
+    trace: Vec<DeliveredMessage>,
+    config: NetworkConfig,
+    nodes: HashMap<SocketAddr, (u64, InboundMessages)>,
+    partition: Partition,
+    links: HashMap<(u64, u64), mpsc::UnboundedSender<(Instant, RaftMessage)>>,
+    last_delivery: HashMap<(u64, u64), Instant>,
+}
+
+/// Virtual network connecting the nodes of a `SimulatedCluster`.
+/// Every drop and delay decision is drawn from a seeded RNG and delays are measured on
+/// tokio's clock, so pausing the clock makes runs reproducible.
+#[derive(Clone)]
+pub struct SimulatedNetwork {
+    state: Arc<Mutex<NetworkState>>,
+    /// Node using this handle, set by `for_node`.
+    node_id: Option<u64>,
+}
+
+impl SimulatedNetwork {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            state: Arc::new(Mutex::new(NetworkState {
+                rng: StdRng::seed_from_u64(seed),
+                started: Instant::now(),
+                trace: Vec::new(),
+                config: NetworkConfig::default(),
+                nodes: HashMap::new(),
+                partition: Partition::default(),
+                links: HashMap::new(),
+                last_delivery: HashMap::new(),
+            })),
+            node_id: None,
+        }
+    }
+
+    /// Handle of the same network through which the given node sends and is served,
+    /// so that the requests it forwards are cut off by partitions as well.
+    pub fn for_node(&self, node_id: u64) -> Self {
+        Self {
+            state: self.state.clone(),
+            node_id: Some(node_id),
+        }
+    }
+
+    pub fn set_config(&self, config: NetworkConfig) {
+        self.state.lock().unwrap().config = config;
+    }
+
+    /// Splits the network into the given groups. Nodes in different groups,
+    /// as well as nodes not listed in any group, can't reach each other.
+    pub fn partition(&self, groups: &[&[u64]]) {
+        self.state.lock().unwrap().partition = Partition::new(groups);
+    }
+
+    /// Cuts the node off from every other node.
+    pub fn isolate(&self, node_id: u64, all_nodes: &[u64]) {
+        let others = all_nodes
+            .iter()
+            .copied()
+            .filter(|id| *id != node_id)
+            .collect::<Vec<_>>();
+        self.partition(&[&[node_id], &others]);
+    }
+
+    pub fn heal(&self) {
+        self.state.lock().unwrap().partition = Partition::default();
+    }
+
+    /// Every message delivered so far, in delivery order.
+    pub fn trace(&self) -> Vec<DeliveredMessage> {
+        self.state.lock().unwrap().trace.clone()
+    }
+
+    fn route(
+        &self,
+        peer: &Peer,
+        message: RaftMessage,
+    ) -> std::result::Result<(), SendMessageError> {
+        let (from, to) = (message.get_from(), message.get_to());
+        let mut state = self.state.lock().unwrap();
+
+        if !state.partition.is_connected(from, to) {
+            return Err(SendMessageError::ConnectionError(format!(
+                "Node {} is partitioned from node {}",
+                from, to
+            )));
+        }
+
+        let (_, inbound) = state.nodes.get(&peer.addr).cloned().ok_or_else(|| {
+            SendMessageError::ConnectionError(format!("No node serves {}", peer.addr))
+        })?;
+
+        let drop_rate = state.config.drop_rate;
+        if drop_rate > 0.0 && state.rng.gen_bool(drop_rate) {
+            return Ok(());
+        }
+
+        let (min_delay, max_delay) = (state.config.min_delay, state.config.max_delay);
+        let delay = state.rng.gen_range(min_delay..=max_delay.max(min_delay));
+        let mut deliver_at = Instant::now() + delay;
+
+        if state.config.reorder {
+            let network = self.clone();
+            tokio::spawn(async move {
+                sleep_until(deliver_at).await;
+                network.deliver(&inbound, message).await;
+            });
+            return Ok(());
+        }
+
+        // Keep the messages of a link in order by never scheduling one before its predecessor.
+        if let Some(last) = state.last_delivery.get(&(from, to)) {
+            deliver_at = deliver_at.max(*last);
+        }
+        state.last_delivery.insert((from, to), deliver_at);
+
+        let is_link_open = matches!(state.links.get(&(from, to)), Some(link) if !link.is_closed());
+        if !is_link_open {
+            let link = self.spawn_link(inbound);
+            state.links.insert((from, to), link);
+        }
+
+        let _ = state.links[&(from, to)].send((deliver_at, message));
+        Ok(())
+    }
+
+    fn spawn_link(
+        &self,
+        inbound: InboundMessages,
+    ) -> mpsc::UnboundedSender<(Instant, RaftMessage)> {
+        let (tx, mut rx) = mpsc::unbounded_channel::<(Instant, RaftMessage)>();
+        let network = self.clone();
+        tokio::spawn(async move {
+            while let Some((deliver_at, message)) = rx.recv().await {
+                sleep_until(deliver_at).await;
+                network.deliver(&inbound, message).await;
+            }
+        });
+        tx
+    }
+
+    async fn deliver(&self, inbound: &InboundMessages, message: RaftMessage) {
+        // Messages in flight when a partition starts are lost as well
+        let is_connected = {
+            let mut state = self.state.lock().unwrap();
+            let is_connected = state
+                .partition
+                .is_connected(message.get_from(), message.get_to());
+            if is_connected {
+                let at = state.started.elapsed();
+                state.trace.push(DeliveredMessage {
+                    at,
+                    from: message.get_from(),
+                    to: message.get_to(),
+                    msg_type: message.get_msg_type(),
+                    term: message.get_term(),
+                    index: message.get_index(),
+                    commit: message.get_commit(),
+                });
+            }
+            is_connected
+        };
+
+        if is_connected {
+            let _ = inbound.deliver(message).await;
+        }
+    }
+}
+
+#[async_trait]
+impl Transport for SimulatedNetwork {
+    async fn send_messages(
+        &self,
+        peer: &Peer,
+        messages: Vec<RaftMessage>,
+    ) -> std::result::Result<(), SendMessageError> {
+        for message in messages {
+            self.route(peer, message)?;
+        }
+        Ok(())
+    }
+
+    async fn send_snapshot(
+        &self,
+        peer: &Peer,
+        message: RaftMessage,
+    ) -> std::result::Result<(), SendMessageError> {
+        self.route(peer, message)
+    }
+
+    /// Forwarded requests are served right away, regardless of the network's configuration,
+    /// unless the nodes are partitioned.
+    async fn forward(
+        &self,
+        addr: SocketAddr,
+        request: ForwardedRequest,
+    ) -> std::result::Result<ForwardedResponse, SendMessageError> {
+        let inbound = {
+            let state = self.state.lock().unwrap();
+            let (to, inbound) = state.nodes.get(&addr).cloned().ok_or_else(|| {
+                SendMessageError::ConnectionError(format!("No node serves {}", addr))
+            })?;
+
+            if let Some(from) = self.node_id {
+                if !state.partition.is_connected(from, to) {
+                    return Err(SendMessageError::ConnectionError(format!(
+                        "Node {} is partitioned from node {}",
+                        from, to
+                    )));
+                }
+            }
+            inbound
+        };
+        inbound.handle(request).await
+    }
+
+    async fn serve(
+        &self,
+        addr: SocketAddr,
+        inbound: InboundMessages,
+        rx_quit: oneshot::Receiver<()>,
+    ) -> Result<()> {
+        let node_id = self
+            .node_id
+            .expect("Nodes are served through the handle returned by for_node");
+        self.state
+            .lock()
+            .unwrap()
+            .nodes
+            .insert(addr, (node_id, inbound));
+        let _ = rx_quit.await;
+        self.state.lock().unwrap().nodes.remove(&addr);
+        Ok(())
+    }
+}
+
+/// Runs a whole cluster inside the current tokio runtime on top of a `SimulatedNetwork`.
+///
+/// Run it with a paused clock, e.g. `#[tokio::test(start_paused = true)]`, so that time only
+/// advances while every node is idle. The election timeouts and the network's behavior are
+/// derived from the seed, so the same seed replays the same scenario.
+pub struct SimulatedCluster {
+    pub rafts: BTreeMap<u64, Raft>,
+    network: SimulatedNetwork,
+    raft_handles: BTreeMap<u64, JoinHandle<Result<()>>>,
+    rng: StdRng,
+    logger: Arc<Slogger>,
+    storage_dir: TempDir,
+    configure: fn(ConfigBuilder) -> ConfigBuilder,
+}
+
+impl SimulatedCluster {
+    pub fn start(seed: u64, size: u64) -> Result<Self> {
+        Self::start_with_config(seed, size, |builder| builder)
+    }
+
+    /// Same as `start`, but `configure` adjusts the config of every node.
+    pub fn start_with_config(
+        seed: u64,
+        size: u64,
+        configure: fn(ConfigBuilder) -> ConfigBuilder,
+    ) -> Result<Self> {
+        let storage_dir = tempfile::tempdir()?;
+        let logger = Arc::new(Slogger {
+            slog: build_file_logger(storage_dir.path().to_str().unwrap()),
+        });
+
+        let mut rng = StdRng::seed_from_u64(seed);
+        let network = SimulatedNetwork::new(rng.gen());
+
+        let mut peers = Peers::with_empty();
+        for node_id in 1..=size {
+            peers.add_peer(node_id, Self::addr_of(node_id), Some(InitialRole::Voter));
+        }
+
+        let mut cluster = Self {
+            rafts: BTreeMap::new(),
+            network,
+            raft_handles: BTreeMap::new(),
+            rng,
+            logger,
+            storage_dir,
+            configure,
+        };
+
+        for node_id in 1..=size {
+            cluster.spawn(node_id, peers.clone())?;
+        }
+
+        Ok(cluster)
+    }
+
+    /// The addresses are only used as keys of the simulated network.
+    pub fn addr_of(node_id: u64) -> SocketAddr {
+        SocketAddr::from(([10, 0, 0, node_id as u8], 60061))
+    }
+
+    /// Starts a node that is not part of the cluster's configuration yet.
+    /// It only takes part once a configuration change adds it.
+    pub fn spawn_node(&mut self, node_id: u64) -> Result<&Raft> {
+        let mut peers = Peers::with_empty();
+        for id in self.node_ids() {
+            peers.add_peer(id, Self::addr_of(id), Some(InitialRole::Voter));
+        }
+        self.spawn(node_id, peers)?;
+        Ok(self.raft(node_id))
+    }
+
+    /// Stops the node and starts it again from its storage, with an empty state machine.
+    pub async fn restart_node(&mut self, node_id: u64) -> Result<&Raft> {
+        if let Some(raft) = self.rafts.remove(&node_id) {
+            let _ = raft.quit().await;
+        }
+        if let Some(handle) = self.raft_handles.remove(&node_id) {
+            let _ = handle.await;
+        }
+
+        let mut peers = Peers::with_empty();
+        for id in self.node_ids().into_iter().chain(std::iter::once(node_id)) {
+            peers.add_peer(id, Self::addr_of(id), Some(InitialRole::Voter));
+        }
+        self.spawn(node_id, peers)?;
+        Ok(self.raft(node_id))
+    }
+
+    fn spawn(&mut self, node_id: u64, peers: Peers) -> Result<()> {
+        // A fixed, per node election timeout replaces raft-rs's unseeded randomization.
+        let election_tick = self.rng.gen_range(10..20);
+        let raft_config = RaftConfig {
+            id: node_id,
+            election_tick: 10,
+            min_election_tick: election_tick,
+            max_election_tick: election_tick + 1,
+            heartbeat_tick: 3,
+            omit_heartbeat_log: true,
+            ..Default::default()
+        };
+
+        let storage_path = self.storage_path(node_id);
+        ensure_directory_exist(&storage_path)?;
+
+        let cfg = (self.configure)(
+            ConfigBuilder::new()
+                .log_dir(storage_path.clone())
+                .compacted_log_dir(storage_path.clone())
+                .raft_config(raft_config)
+                .initial_peers(peers),
+        )
+        .build();
+
+        let storage = HeedStorage::create(&storage_path, &cfg, self.logger.clone())?;
+        let raft = Raft::bootstrap_with_transport(
+            node_id,
+            Self::addr_of(node_id),
+            storage,
+            HashStore::new(),
+            cfg,
+            self.logger.clone(),
+            self.network.for_node(node_id),
+        )?;
+
+        self.raft_handles
+            .insert(node_id, tokio::spawn(raft.clone().run()));
+        self.rafts.insert(node_id, raft);
+        Ok(())
+    }
+
+    /// Directory holding the node's log, snapshots and compacted logs.
+    pub fn storage_path(&self, node_id: u64) -> String {
+        get_storage_path(self.storage_dir.path().to_str().unwrap(), node_id)
+    }
+
+    pub fn raft(&self, node_id: u64) -> &Raft {
+        &self.rafts[&node_id]
+    }
+
+    pub fn network(&self) -> &SimulatedNetwork {
+        &self.network
+    }
+
+    pub fn node_ids(&self) -> Vec<u64> {
+        self.rafts.keys().copied().collect()
+    }
+
+    /// Lets the cluster run for the given amount of virtual time.
+    pub async fn advance(&self, duration: Duration) {
+        sleep(duration).await;
+    }
+
+    /// Returns the leader all of the given nodes agree on, if any.
+    pub async fn leader_of(&self, node_ids: &[u64]) -> Option<u64> {
+        let mut leader_ids = vec![];
+        for node_id in node_ids {
+            leader_ids.push(self.raft(*node_id).get_leader_id().await.ok()?);
+        }
+
+        let leader_id = *leader_ids.first()?;
+        let is_agreed = leader_ids.iter().all(|id| *id == leader_id);
+
+        if leader_id != 0
+            && is_agreed
+            && node_ids.contains(&leader_id)
+            && self.raft(leader_id).is_leader().await.ok()?
+        {
+            Some(leader_id)
+        } else {
+            None
+        }
+    }
+
+    /// Advances the clock tick by tick until the given nodes agree on a leader.
+    pub async fn wait_for_leader(&self, node_ids: &[u64], timeout: Duration) -> Option<u64> {
+        let deadline = Instant::now() + timeout;
+        while Instant::now() < deadline {
+            if let Some(leader_id) = self.leader_of(node_ids).await {
+                return Some(leader_id);
+            }
+            self.advance(Duration::from_millis(100)).await;
+        }
+        None
+    }
+
+    pub async fn quit(self) {
+        for raft in self.rafts.values() {
+            let _ = raft.quit().await;
+        }
+        for handle in self.raft_handles.into_values() {
+            let _ = handle.await;
+        }
+    }
+}
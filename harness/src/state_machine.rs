@@ -4,11 +4,19 @@ use serde::{Deserialize, Serialize};
 use std::{
     collections::HashMap,
     sync::{Arc, RwLock},
+    time::Duration,
 };
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub enum LogEntry {
-    Insert { key: u64, value: String },
+    Insert {
+        key: u64,
+        value: String,
+    },
+    /// Makes applying the entry take the given time, to simulate a slow state machine.
+    Sleep {
+        millis: u64,
+    },
 }
 
 impl AbstractLogEntry for LogEntry {
@@ -22,6 +30,17 @@ impl AbstractLogEntry for LogEntry {
     }
 }
 
+// `apply` returns the applied entry, so `propose_entry` resolves with it.
+impl raftify::AbstractOutput for LogEntry {
+    fn encode(&self) -> Result<Vec<u8>> {
+        AbstractLogEntry::encode(self)
+    }
+
+    fn decode(bytes: &[u8]) -> Result<LogEntry> {
+        AbstractLogEntry::decode(bytes)
+    }
+}
+
 #[derive(Clone, Debug, Default)]
 pub struct HashStore(pub Arc<RwLock<HashMap<u64, String>>>);
 
@@ -37,6 +56,8 @@ impl HashStore {
 
 #[async_trait]
 impl AbstractStateMachine for HashStore {
+    type Output = LogEntry;
+
     async fn apply(&mut self, data: Vec<u8>) -> Result<Vec<u8>> {
         let log_entry: LogEntry = LogEntry::decode(&data)?;
         match log_entry {
@@ -45,6 +66,9 @@ impl AbstractStateMachine for HashStore {
                 log::info!("Inserted: ({}, {})", key, value);
                 db.insert(*key, value.clone());
             }
+            LogEntry::Sleep { millis } => {
+                tokio::time::sleep(Duration::from_millis(millis)).await;
+            }
         };
         Ok(data)
     }
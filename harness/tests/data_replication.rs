@@ -4,7 +4,10 @@ use tokio::time::sleep;
 
 use harness::{
     constant::{RAFT_PORTS, THREE_NODE_EXAMPLE},
-    raft::{build_raft_cluster, spawn_and_join_extra_node, wait_until_rafts_ready, Raft},
+    raft::{
+        build_in_process_raft_cluster, build_raft_cluster, spawn_and_join_extra_node,
+        wait_until_rafts_ready, Raft,
+    },
     state_machine::LogEntry,
     test_environment::prepare_test_environment,
     utils::load_peers,
@@ -92,3 +95,86 @@ pub async fn test_data_replication() {
         raft.quit().await.expect("Failed to quit the raft node");
     }
 }
+
+#[tokio::test]
+pub async fn test_read_index() {
+    let test_environment = prepare_test_environment(stringify!(test_read_index));
+
+    let peers = load_peers(&test_environment.loopback_address, THREE_NODE_EXAMPLE)
+        .await
+        .unwrap();
+    let (tx_raft, rx_raft) = mpsc::channel::<(u64, Raft)>();
+
+    let _raft_tasks = tokio::spawn(build_raft_cluster(
+        tx_raft,
+        test_environment.base_storage_path.clone(),
+        peers.clone(),
+    ));
+    sleep(Duration::from_secs(1)).await;
+
+    let mut rafts = wait_until_rafts_ready(None, rx_raft, 3).await;
+
+    let raft_1 = rafts.get(&1).unwrap();
+
+    let entry = LogEntry::Insert {
+        key: 1,
+        value: "test".to_string(),
+    }
+    .encode()
+    .unwrap();
+
+    raft_1.propose(entry).await.unwrap();
+
+    // Reads confirmed through the read index should observe the committed entry on every node.
+    for (_, raft) in rafts.iter() {
+        let value = raft.linearizable_read(|store| store.get(1)).await.unwrap();
+        assert_eq!(value.unwrap(), "test");
+    }
+
+    for (_, raft) in rafts.iter_mut() {
+        raft.quit().await.expect("Failed to quit the raft node");
+    }
+}
+
+#[tokio::test]
+pub async fn test_in_process_data_replication() {
+    let test_environment = prepare_test_environment(stringify!(test_in_process_data_replication));
+
+    let peers = load_peers(&test_environment.loopback_address, THREE_NODE_EXAMPLE)
+        .await
+        .unwrap();
+    let (tx_raft, rx_raft) = mpsc::channel::<(u64, Raft)>();
+
+    let _raft_tasks = tokio::spawn(build_in_process_raft_cluster(
+        tx_raft,
+        test_environment.base_storage_path.clone(),
+        peers.clone(),
+    ));
+    sleep(Duration::from_secs(1)).await;
+
+    let mut rafts = wait_until_rafts_ready(None, rx_raft, 3).await;
+
+    let raft_1 = rafts.get(&1).unwrap();
+
+    let entry = LogEntry::Insert {
+        key: 1,
+        value: "test".to_string(),
+    }
+    .encode()
+    .unwrap();
+
+    raft_1.propose(entry).await.unwrap();
+
+    sleep(Duration::from_secs(1)).await;
+
+    // Data should be replicated to all nodes without going through the network.
+    for (_, raft) in rafts.iter() {
+        let store = raft.state_machine().await.unwrap();
+        let store_lk = store.0.read().unwrap();
+        assert_eq!(store_lk.get(&1).unwrap(), "test");
+    }
+
+    for (_, raft) in rafts.iter_mut() {
+        raft.quit().await.expect("Failed to quit the raft node");
+    }
+}
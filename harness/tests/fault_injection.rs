@@ -0,0 +1,211 @@
+use raftify::{AbstractLogEntry, RaftEvent};
+use std::{future::Future, sync::mpsc, time::Duration};
+use tokio::time::{sleep, timeout};
+
+use harness::{
+    constant::THREE_NODE_EXAMPLE,
+    fault_injector::FaultInjector,
+    raft::{
+        build_raft_cluster_with_faults, crash_node, restart_node, wait_until_rafts_ready, Raft,
+    },
+    state_machine::LogEntry,
+    test_environment::prepare_test_environment,
+    utils::load_peers,
+};
+
+fn insert(key: u64, value: &str) -> Vec<u8> {
+    LogEntry::Insert {
+        key,
+        value: value.to_string(),
+    }
+    .encode()
+    .unwrap()
+}
+
+/// Polls `condition` until it holds, failing the test if it doesn't within 10 seconds.
+async fn wait_until<F, Fut>(mut condition: F)
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = bool>,
+{
+    timeout(Duration::from_secs(10), async {
+        while !condition().await {
+            sleep(Duration::from_millis(50)).await;
+        }
+    })
+    .await
+    .expect("The condition didn't hold in time");
+}
+
+async fn has_value(raft: &Raft, key: u64, value: &str) -> bool {
+    let store = raft.state_machine().await.unwrap();
+    let store_lk = store.0.read().unwrap();
+    store_lk.get(&key).is_some_and(|stored| stored == value)
+}
+
+#[tokio::test]
+pub async fn test_partitioned_leader_does_not_split_brain() {
+    let test_environment =
+        prepare_test_environment(stringify!(test_partitioned_leader_does_not_split_brain));
+
+    let peers = load_peers(&test_environment.loopback_address, THREE_NODE_EXAMPLE)
+        .await
+        .unwrap();
+    let injector = FaultInjector::new(1);
+    let (tx_raft, rx_raft) = mpsc::channel::<(u64, Raft)>();
+
+    let _raft_tasks = build_raft_cluster_with_faults(
+        tx_raft,
+        test_environment.base_storage_path.clone(),
+        peers.clone(),
+        injector.clone(),
+    )
+    .unwrap();
+
+    let mut rafts = wait_until_rafts_ready(None, rx_raft, 3).await;
+    let raft_1 = rafts.get(&1).unwrap();
+    assert!(raft_1.is_leader().await.unwrap());
+
+    let mut raft_2_events = rafts.get(&2).unwrap().subscribe();
+    injector.partition(&[&[1], &[2, 3]]);
+
+    // The stale leader can't commit anything without a quorum.
+    let stale_proposal = timeout(Duration::from_secs(3), raft_1.propose(insert(1, "stale"))).await;
+    assert!(stale_proposal.is_err());
+
+    let new_leader_id = timeout(Duration::from_secs(10), async {
+        loop {
+            if let Ok(RaftEvent::LeaderChanged { leader_id, .. }) = raft_2_events.recv().await {
+                if leader_id == 2 || leader_id == 3 {
+                    return leader_id;
+                }
+            }
+        }
+    })
+    .await
+    .expect("Nodes 2 and 3 didn't elect a new leader");
+
+    let new_leader = rafts.get(&new_leader_id).unwrap();
+    new_leader.propose(insert(2, "fresh")).await.unwrap();
+
+    injector.heal();
+
+    // The old leader should step down and discard its uncommitted entry.
+    wait_until(|| async move { raft_1.get_leader_id().await.unwrap() == new_leader_id }).await;
+    assert!(!raft_1.is_leader().await.unwrap());
+
+    for (_, raft) in rafts.iter() {
+        wait_until(|| has_value(raft, 2, "fresh")).await;
+        let store = raft.state_machine().await.unwrap();
+        let store_lk = store.0.read().unwrap();
+        assert!(store_lk.get(&1).is_none());
+    }
+
+    for (_, raft) in rafts.iter_mut() {
+        raft.quit().await.expect("Failed to quit the raft node");
+    }
+}
+
+#[tokio::test]
+pub async fn test_replication_over_lossy_network() {
+    let test_environment =
+        prepare_test_environment(stringify!(test_replication_over_lossy_network));
+
+    let peers = load_peers(&test_environment.loopback_address, THREE_NODE_EXAMPLE)
+        .await
+        .unwrap();
+    let injector = FaultInjector::new(2);
+    let (tx_raft, rx_raft) = mpsc::channel::<(u64, Raft)>();
+
+    let _raft_tasks = build_raft_cluster_with_faults(
+        tx_raft,
+        test_environment.base_storage_path.clone(),
+        peers.clone(),
+        injector.clone(),
+    )
+    .unwrap();
+
+    let mut rafts = wait_until_rafts_ready(None, rx_raft, 3).await;
+
+    injector.drop_messages(0.3);
+    injector.add_latency(Duration::from_millis(20));
+
+    let raft_1 = rafts.get(&1).unwrap();
+    raft_1.propose(insert(1, "test")).await.unwrap();
+
+    // Data should be replicated to all nodes despite the lost messages.
+    for (_, raft) in rafts.iter() {
+        wait_until(|| has_value(raft, 1, "test")).await;
+    }
+
+    injector.reset();
+    for (_, raft) in rafts.iter_mut() {
+        raft.quit().await.expect("Failed to quit the raft node");
+    }
+}
+
+#[tokio::test]
+pub async fn test_restarted_node_recovers_from_storage() {
+    let test_environment =
+        prepare_test_environment(stringify!(test_restarted_node_recovers_from_storage));
+
+    let peers = load_peers(&test_environment.loopback_address, THREE_NODE_EXAMPLE)
+        .await
+        .unwrap();
+    let injector = FaultInjector::new(3);
+    let (tx_raft, rx_raft) = mpsc::channel::<(u64, Raft)>();
+
+    let mut raft_tasks = build_raft_cluster_with_faults(
+        tx_raft,
+        test_environment.base_storage_path.clone(),
+        peers.clone(),
+        injector.clone(),
+    )
+    .unwrap();
+
+    let mut rafts = wait_until_rafts_ready(None, rx_raft, 3).await;
+
+    rafts
+        .get(&1)
+        .unwrap()
+        .propose(insert(1, "before crash"))
+        .await
+        .unwrap();
+    wait_until(|| has_value(rafts.get(&3).unwrap(), 1, "before crash")).await;
+
+    crash_node(
+        rafts.remove(&3).unwrap(),
+        raft_tasks.remove(&3).unwrap(),
+        peers.get(&3).unwrap().addr,
+    )
+    .await;
+
+    // The remaining majority keeps committing while node 3 is down.
+    rafts
+        .get(&1)
+        .unwrap()
+        .propose(insert(2, "while down"))
+        .await
+        .unwrap();
+
+    let (tx_raft, rx_raft) = mpsc::channel::<(u64, Raft)>();
+    let _raft_3_task = restart_node(
+        tx_raft,
+        3,
+        &test_environment.base_storage_path,
+        peers.clone(),
+        injector.clone(),
+    )
+    .unwrap();
+
+    let mut rafts = wait_until_rafts_ready(Some(rafts), rx_raft, 3).await;
+
+    // The restarted node replays its own log and catches up with the entries it missed.
+    let raft_3 = rafts.get(&3).unwrap();
+    wait_until(|| has_value(raft_3, 2, "while down")).await;
+    assert!(has_value(raft_3, 1, "before crash").await);
+
+    for (_, raft) in rafts.iter_mut() {
+        raft.quit().await.expect("Failed to quit the raft node");
+    }
+}
@@ -0,0 +1,1093 @@
+use raftify::{
+    raft::{
+        eraftpb::{ConfChangeSingle, ConfChangeType},
+        StateRole, Storage,
+    },
+    replay_log, AbstractLogEntry, ClientRequest, ConfChangeRequest, ConfChangeResponseResult,
+    ConfigBuilder, Error, LogArchive, RaftEvent, ReplayTarget, StableStorage,
+};
+use std::{path::Path, time::Duration};
+
+use harness::{
+    simulator::{DeliveredMessage, NetworkConfig, SimulatedCluster},
+    state_machine::{HashStore, LogEntry},
+};
+
+async fn elect_leader(seed: u64) -> u64 {
+    let cluster = SimulatedCluster::start(seed, 3).unwrap();
+    let leader_id = cluster
+        .wait_for_leader(&cluster.node_ids(), Duration::from_secs(10))
+        .await
+        .expect("No leader elected");
+    cluster.quit().await;
+    leader_id
+}
+
+#[tokio::test(start_paused = true)]
+pub async fn test_simulated_leader_election_is_reproducible() {
+    let leader_id = elect_leader(7).await;
+
+    // The same seed should elect the same leader again.
+    assert_eq!(elect_leader(7).await, leader_id);
+}
+
+/// Replicates a few entries over a lossy, reordering network, and returns the messages
+/// the network delivered along with the commit index of every node.
+async fn lossy_replication_trace(seed: u64) -> (Vec<DeliveredMessage>, Vec<u64>) {
+    let cluster = SimulatedCluster::start(seed, 3).unwrap();
+    cluster.network().set_config(NetworkConfig {
+        drop_rate: 0.2,
+        reorder: true,
+        ..Default::default()
+    });
+
+    let leader_id = cluster
+        .wait_for_leader(&cluster.node_ids(), Duration::from_secs(10))
+        .await
+        .expect("No leader elected");
+    for key in 1..=5 {
+        cluster
+            .raft(leader_id)
+            .propose_entry(&insert(key))
+            .await
+            .unwrap();
+    }
+    cluster.advance(Duration::from_secs(5)).await;
+
+    let mut commits = vec![];
+    for raft in cluster.rafts.values() {
+        let storage = raft.storage().await.unwrap();
+        commits.push(storage.hard_state().unwrap().commit);
+    }
+    let trace = cluster.network().trace();
+    cluster.quit().await;
+    (trace, commits)
+}
+
+#[tokio::test(start_paused = true)]
+pub async fn test_simulated_lossy_run_is_reproducible() {
+    let (trace, commits) = lossy_replication_trace(17).await;
+    assert!(!trace.is_empty());
+
+    // Drops, delays and reorders are all drawn from the seed, so the messages are
+    // delivered in the same order, at the same time, with the same commit indexes.
+    assert_eq!(lossy_replication_trace(17).await, (trace, commits));
+}
+
+#[tokio::test(start_paused = true)]
+pub async fn test_simulated_replication_over_lossy_network() {
+    let cluster = SimulatedCluster::start(11, 3).unwrap();
+    cluster.network().set_config(NetworkConfig {
+        drop_rate: 0.2,
+        reorder: true,
+        ..Default::default()
+    });
+
+    let leader_id = cluster
+        .wait_for_leader(&cluster.node_ids(), Duration::from_secs(10))
+        .await
+        .expect("No leader elected");
+
+    let entry = LogEntry::Insert {
+        key: 1,
+        value: "test".to_string(),
+    }
+    .encode()
+    .unwrap();
+    cluster.raft(leader_id).propose(entry).await.unwrap();
+
+    cluster.advance(Duration::from_secs(5)).await;
+
+    // Data should be replicated to all nodes despite the lost and reordered messages.
+    for (_, raft) in cluster.rafts.iter() {
+        let store = raft.state_machine().await.unwrap();
+        let store_lk = store.0.read().unwrap();
+        assert_eq!(store_lk.get(&1).unwrap(), "test");
+    }
+
+    cluster.quit().await;
+}
+
+#[tokio::test(start_paused = true)]
+pub async fn test_simulated_partitioned_leader_is_replaced() {
+    let cluster = SimulatedCluster::start(23, 3).unwrap();
+    let node_ids = cluster.node_ids();
+
+    let old_leader_id = cluster
+        .wait_for_leader(&node_ids, Duration::from_secs(10))
+        .await
+        .expect("No leader elected");
+
+    cluster.network().isolate(old_leader_id, &node_ids);
+    let majority = node_ids
+        .iter()
+        .copied()
+        .filter(|id| *id != old_leader_id)
+        .collect::<Vec<_>>();
+
+    // The remaining majority should elect a new leader and keep accepting proposals.
+    let new_leader_id = cluster
+        .wait_for_leader(&majority, Duration::from_secs(10))
+        .await
+        .expect("No leader elected in the majority");
+    assert_ne!(new_leader_id, old_leader_id);
+
+    let entry = LogEntry::Insert {
+        key: 1,
+        value: "test".to_string(),
+    }
+    .encode()
+    .unwrap();
+    cluster.raft(new_leader_id).propose(entry).await.unwrap();
+
+    // Once healed, the old leader should catch up with the new leader.
+    cluster.network().heal();
+    cluster.advance(Duration::from_secs(5)).await;
+
+    assert_eq!(cluster.leader_of(&node_ids).await, Some(new_leader_id));
+    let store = cluster.raft(old_leader_id).state_machine().await.unwrap();
+    let store_lk = store.0.read().unwrap();
+    assert_eq!(store_lk.get(&1).unwrap(), "test");
+    std::mem::drop(store_lk);
+
+    cluster.quit().await;
+}
+
+#[tokio::test(start_paused = true)]
+pub async fn test_simulated_leadership_events() {
+    let cluster = SimulatedCluster::start(31, 3).unwrap();
+    let mut subscriptions = cluster
+        .rafts
+        .iter()
+        .map(|(node_id, raft)| (*node_id, raft.subscribe()))
+        .collect::<Vec<_>>();
+
+    let leader_id = cluster
+        .wait_for_leader(&cluster.node_ids(), Duration::from_secs(10))
+        .await
+        .expect("No leader elected");
+    cluster.quit().await;
+
+    // Every node should learn the leader without polling, and the leader should see its own promotion.
+    for (node_id, rx_events) in subscriptions.iter_mut() {
+        let mut events = vec![];
+        while let Ok(event) = rx_events.try_recv() {
+            events.push(event);
+        }
+
+        let is_leader_known = events.iter().any(|event| {
+            matches!(event, RaftEvent::LeaderChanged { leader_id: id, .. } if *id == leader_id)
+        });
+        assert!(is_leader_known);
+        if *node_id == leader_id {
+            assert!(events.iter().any(|event| matches!(
+                event,
+                RaftEvent::RoleChanged {
+                    role: StateRole::Leader,
+                    ..
+                }
+            )));
+        }
+        assert_eq!(events.last(), Some(&RaftEvent::Quitting));
+    }
+}
+
+#[tokio::test(start_paused = true)]
+pub async fn test_simulated_follower_forwards_requests() {
+    let cluster = SimulatedCluster::start(37, 3).unwrap();
+    let leader_id = cluster
+        .wait_for_leader(&cluster.node_ids(), Duration::from_secs(10))
+        .await
+        .expect("No leader elected");
+    let follower_id = cluster
+        .node_ids()
+        .into_iter()
+        .find(|node_id| *node_id != leader_id)
+        .unwrap();
+    let follower = cluster.raft(follower_id);
+
+    // Requests made on a follower reach the leader through the simulated transport.
+    let entry = insert(1).encode().unwrap();
+    assert_eq!(follower.propose(entry.clone()).await.unwrap(), entry);
+    follower.read_index().await.unwrap();
+
+    let store = follower.state_machine().await.unwrap();
+    assert_eq!(store.0.read().unwrap().get(&1).unwrap(), "test");
+
+    // Unless a partition cuts the follower off from the leader.
+    cluster.network().isolate(follower_id, &cluster.node_ids());
+    assert!(follower.propose(entry).await.is_err());
+    assert!(follower.read_index().await.is_err());
+
+    cluster.quit().await;
+}
+
+#[tokio::test(start_paused = true)]
+pub async fn test_simulated_lease_read() {
+    let cluster =
+        SimulatedCluster::start_with_config(39, 3, |builder| builder.check_quorum(true)).unwrap();
+    let leader_id = cluster
+        .wait_for_leader(&cluster.node_ids(), Duration::from_secs(10))
+        .await
+        .expect("No leader elected");
+    let leader = cluster.raft(leader_id);
+
+    leader.propose(insert(1).encode().unwrap()).await.unwrap();
+    let committed = leader.storage().await.unwrap().hard_state().unwrap().commit;
+    assert!(leader.lease_read().await.unwrap() >= committed);
+
+    // A follower's lease read is served by the leader, then waits for the follower to apply it.
+    let follower_id = cluster
+        .node_ids()
+        .into_iter()
+        .find(|node_id| *node_id != leader_id)
+        .unwrap();
+    let follower = cluster.raft(follower_id);
+    assert!(follower.lease_read().await.unwrap() >= committed);
+
+    let store = follower.state_machine().await.unwrap();
+    assert_eq!(store.0.read().unwrap().get(&1).unwrap(), "test");
+
+    cluster.quit().await;
+}
+
+#[tokio::test(start_paused = true)]
+pub async fn test_simulated_lease_read_requires_check_quorum() {
+    let cluster = SimulatedCluster::start(40, 3).unwrap();
+    let leader_id = cluster
+        .wait_for_leader(&cluster.node_ids(), Duration::from_secs(10))
+        .await
+        .expect("No leader elected");
+
+    // Without check_quorum a deposed leader may still believe it holds the lease.
+    let result = cluster.raft(leader_id).lease_read().await;
+    assert!(matches!(result, Err(Error::Rejected(_))));
+
+    cluster.quit().await;
+}
+
+#[tokio::test(start_paused = true)]
+pub async fn test_simulated_node_replacement() {
+    let mut cluster = SimulatedCluster::start(41, 3).unwrap();
+    let leader_id = cluster
+        .wait_for_leader(&cluster.node_ids(), Duration::from_secs(10))
+        .await
+        .expect("No leader elected");
+    let removed_id = cluster
+        .node_ids()
+        .into_iter()
+        .find(|node_id| *node_id != leader_id)
+        .unwrap();
+
+    cluster.spawn_node(4).unwrap();
+
+    // Adding 4 and removing a follower in one change goes through joint consensus.
+    let conf_change = ConfChangeRequest {
+        changes: vec![
+            ConfChangeSingle {
+                node_id: 4,
+                change_type: ConfChangeType::AddNode as i32,
+            },
+            ConfChangeSingle {
+                node_id: removed_id,
+                change_type: ConfChangeType::RemoveNode as i32,
+            },
+        ],
+        addrs: vec![
+            SimulatedCluster::addr_of(4),
+            SimulatedCluster::addr_of(removed_id),
+        ],
+    };
+    let result = cluster
+        .raft(leader_id)
+        .change_config(conf_change.into())
+        .await
+        .unwrap();
+
+    match result {
+        ConfChangeResponseResult::ReplaceSuccess {
+            assigned_ids,
+            removed_ids,
+            ..
+        } => {
+            assert_eq!(assigned_ids, vec![4]);
+            assert_eq!(removed_ids, vec![removed_id]);
+        }
+        _ => panic!("Unexpected result: {:?}", result),
+    }
+
+    // The leader should leave the joint configuration on its own.
+    cluster.advance(Duration::from_secs(3)).await;
+    let conf_state = cluster
+        .raft(leader_id)
+        .storage()
+        .await
+        .unwrap()
+        .conf_state()
+        .unwrap();
+    assert!(conf_state.voters.contains(&4));
+    assert!(!conf_state.voters.contains(&removed_id));
+    assert!(conf_state.voters_outgoing.is_empty());
+
+    let peers = cluster.raft(leader_id).get_peers().await.unwrap();
+    assert!(peers.get(&4).is_some());
+    assert!(peers.get(&removed_id).is_none());
+
+    cluster.quit().await;
+}
+
+#[tokio::test(start_paused = true)]
+pub async fn test_simulated_replace_two_voters() {
+    let mut cluster = SimulatedCluster::start(43, 3).unwrap();
+    let leader_id = cluster
+        .wait_for_leader(&cluster.node_ids(), Duration::from_secs(10))
+        .await
+        .expect("No leader elected");
+    let removed = cluster
+        .node_ids()
+        .into_iter()
+        .filter(|node_id| *node_id != leader_id)
+        .collect::<Vec<_>>();
+    let mut rx_removed_events = removed
+        .iter()
+        .map(|node_id| cluster.raft(*node_id).subscribe())
+        .collect::<Vec<_>>();
+
+    cluster.spawn_node(4).unwrap();
+    cluster.spawn_node(5).unwrap();
+
+    // Replacing a majority of the voters only works if the outgoing voters
+    // keep voting until the joint configuration is left.
+    let conf_change = ConfChangeRequest {
+        changes: vec![
+            ConfChangeSingle {
+                node_id: 4,
+                change_type: ConfChangeType::AddNode as i32,
+            },
+            ConfChangeSingle {
+                node_id: 5,
+                change_type: ConfChangeType::AddNode as i32,
+            },
+            ConfChangeSingle {
+                node_id: removed[0],
+                change_type: ConfChangeType::RemoveNode as i32,
+            },
+            ConfChangeSingle {
+                node_id: removed[1],
+                change_type: ConfChangeType::RemoveNode as i32,
+            },
+        ],
+        addrs: vec![
+            SimulatedCluster::addr_of(4),
+            SimulatedCluster::addr_of(5),
+            SimulatedCluster::addr_of(removed[0]),
+            SimulatedCluster::addr_of(removed[1]),
+        ],
+    };
+    let result = cluster
+        .raft(leader_id)
+        .change_config(conf_change.into())
+        .await
+        .unwrap();
+
+    match result {
+        ConfChangeResponseResult::ReplaceSuccess {
+            assigned_ids,
+            removed_ids,
+            peers,
+        } => {
+            assert_eq!(assigned_ids, vec![4, 5]);
+            assert_eq!(removed_ids, removed);
+            assert!(removed.iter().all(|node_id| peers.get(node_id).is_none()));
+        }
+        _ => panic!("Unexpected result: {:?}", result),
+    }
+
+    // The requester is only answered once the joint configuration is left.
+    let conf_state = cluster
+        .raft(leader_id)
+        .storage()
+        .await
+        .unwrap()
+        .conf_state()
+        .unwrap();
+    let mut voters = conf_state.voters.clone();
+    voters.sort_unstable();
+    assert_eq!(voters, vec![leader_id, 4, 5]);
+    assert!(conf_state.voters_outgoing.is_empty());
+
+    // The new configuration can still commit.
+    let entry = insert(1).encode().unwrap();
+    cluster.raft(leader_id).propose(entry).await.unwrap();
+
+    // The removed nodes learn that the joint configuration was left and shut down.
+    cluster.advance(Duration::from_secs(3)).await;
+    for rx_events in rx_removed_events.iter_mut() {
+        let mut events = vec![];
+        while let Ok(event) = rx_events.try_recv() {
+            events.push(event);
+        }
+        assert_eq!(events.last(), Some(&RaftEvent::Quitting));
+    }
+
+    cluster.quit().await;
+}
+
+#[tokio::test(start_paused = true)]
+pub async fn test_simulated_learner_promotion() {
+    let mut cluster = SimulatedCluster::start(53, 3).unwrap();
+    let leader_id = cluster
+        .wait_for_leader(&cluster.node_ids(), Duration::from_secs(10))
+        .await
+        .expect("No leader elected");
+
+    cluster.spawn_node(4).unwrap();
+    let conf_change = ConfChangeRequest {
+        changes: vec![ConfChangeSingle {
+            node_id: 4,
+            change_type: ConfChangeType::AddLearnerNode as i32,
+        }],
+        addrs: vec![SimulatedCluster::addr_of(4)],
+    };
+    let leader = cluster.raft(leader_id);
+    leader.change_config(conf_change.into()).await.unwrap();
+
+    for key in 1..=10 {
+        let entry = LogEntry::Insert {
+            key,
+            value: "test".to_string(),
+        }
+        .encode()
+        .unwrap();
+        leader.propose(entry).await.unwrap();
+    }
+
+    // Voters can't be promoted.
+    let voter_id = if leader_id == 1 { 2 } else { 1 };
+    let result = leader.promote_learner(voter_id).await.unwrap();
+    assert!(matches!(result, ConfChangeResponseResult::Error(_)));
+
+    let result = leader.promote_learner(4).await.unwrap();
+    match result {
+        ConfChangeResponseResult::JoinSuccess { assigned_ids, .. } => {
+            assert_eq!(assigned_ids, vec![4]);
+        }
+        _ => panic!("Unexpected result: {:?}", result),
+    }
+
+    let conf_state = leader.storage().await.unwrap().conf_state().unwrap();
+    assert!(conf_state.voters.contains(&4));
+    assert!(conf_state.learners.is_empty());
+
+    // The promoted node has the whole log.
+    cluster.advance(Duration::from_secs(1)).await;
+    let store = cluster.raft(4).state_machine().await.unwrap();
+    assert_eq!(store.0.read().unwrap().get(&10).unwrap(), "test");
+
+    cluster.quit().await;
+}
+
+#[tokio::test(start_paused = true)]
+pub async fn test_simulated_proposal_without_leader_fails() {
+    let cluster = SimulatedCluster::start(61, 3).unwrap();
+    // No node can win an election.
+    cluster.network().partition(&[&[1], &[2], &[3]]);
+
+    let entry = LogEntry::Insert {
+        key: 1,
+        value: "test".to_string(),
+    }
+    .encode()
+    .unwrap();
+    let result = cluster.raft(1).propose(entry).await;
+    assert!(matches!(result, Err(Error::NoLeader)));
+
+    cluster.quit().await;
+}
+
+#[tokio::test(start_paused = true)]
+pub async fn test_simulated_proposal_waits_for_leader() {
+    let cluster = SimulatedCluster::start(67, 3).unwrap();
+
+    // Propose on every node before any leader is elected.
+    let proposals = cluster
+        .rafts
+        .iter()
+        .map(|(node_id, raft)| {
+            let raft = raft.clone();
+            let entry = LogEntry::Insert {
+                key: *node_id,
+                value: "test".to_string(),
+            }
+            .encode()
+            .unwrap();
+            (
+                *node_id,
+                tokio::spawn(async move { raft.propose(entry).await }),
+            )
+        })
+        .collect::<Vec<_>>();
+
+    let leader_id = cluster
+        .wait_for_leader(&cluster.node_ids(), Duration::from_secs(10))
+        .await
+        .expect("No leader elected");
+
+    // The leader's own proposal is proposed as soon as it is elected.
+    for (node_id, proposal) in proposals {
+        if node_id == leader_id {
+            proposal.await.unwrap().unwrap();
+        }
+    }
+
+    let store = cluster.raft(leader_id).state_machine().await.unwrap();
+    assert_eq!(store.0.read().unwrap().get(&leader_id).unwrap(), "test");
+
+    cluster.quit().await;
+}
+
+#[tokio::test(start_paused = true)]
+pub async fn test_simulated_proposal_timeout_and_step_down() {
+    let cluster = SimulatedCluster::start(71, 3).unwrap();
+    let node_ids = cluster.node_ids();
+    let leader_id = cluster
+        .wait_for_leader(&node_ids, Duration::from_secs(10))
+        .await
+        .expect("No leader elected");
+
+    // An isolated leader can't commit anything.
+    cluster.network().isolate(leader_id, &node_ids);
+    let entry = |key| {
+        LogEntry::Insert {
+            key,
+            value: "test".to_string(),
+        }
+        .encode()
+        .unwrap()
+    };
+
+    let result = cluster
+        .raft(leader_id)
+        .propose_with_timeout(entry(1), Duration::from_secs(1))
+        .await;
+    assert!(matches!(result, Err(Error::Timeout)));
+
+    let raft = cluster.raft(leader_id).clone();
+    let pending = tokio::spawn(async move { raft.propose(entry(2)).await });
+
+    // Once healed, the old leader learns about the new term and steps down.
+    cluster.advance(Duration::from_secs(4)).await;
+    cluster.network().heal();
+    let result = pending.await.unwrap();
+    assert!(matches!(result, Err(Error::LeadershipLost)));
+
+    cluster.quit().await;
+}
+
+#[tokio::test(start_paused = true)]
+pub async fn test_simulated_forwarded_proposal_timeout() {
+    let cluster = SimulatedCluster::start(72, 5).unwrap();
+    let node_ids = cluster.node_ids();
+    let leader_id = cluster
+        .wait_for_leader(&node_ids, Duration::from_secs(10))
+        .await
+        .expect("No leader elected");
+    let mut others = node_ids
+        .iter()
+        .copied()
+        .filter(|node_id| *node_id != leader_id)
+        .collect::<Vec<_>>();
+    let follower_id = others.remove(0);
+
+    // The follower still reaches the leader, which has lost its quorum.
+    cluster
+        .network()
+        .partition(&[&[leader_id, follower_id], &others]);
+    let entry = insert(1).encode().unwrap();
+    let result = cluster
+        .raft(follower_id)
+        .propose_with_timeout(entry, Duration::from_secs(1))
+        .await;
+
+    // The leader's error is reported as is, not as an opaque one.
+    assert!(matches!(result, Err(Error::Timeout)));
+
+    cluster.quit().await;
+}
+
+#[tokio::test(start_paused = true)]
+pub async fn test_simulated_read_index_timeout() {
+    let cluster =
+        SimulatedCluster::start_with_config(73, 3, |builder| builder.proposal_timeout(1.0))
+            .unwrap();
+    let node_ids = cluster.node_ids();
+    let leader_id = cluster
+        .wait_for_leader(&node_ids, Duration::from_secs(10))
+        .await
+        .expect("No leader elected");
+
+    // An isolated leader can't confirm its leadership, so the read fails instead of waiting forever.
+    cluster.network().isolate(leader_id, &node_ids);
+    let result = cluster.raft(leader_id).read_index().await;
+    assert!(matches!(result, Err(Error::Timeout)));
+
+    cluster.network().heal();
+    cluster.quit().await;
+}
+
+#[tokio::test(start_paused = true)]
+pub async fn test_simulated_session_applies_retry_once() {
+    let cluster = SimulatedCluster::start(79, 3).unwrap();
+    let leader_id = cluster
+        .wait_for_leader(&cluster.node_ids(), Duration::from_secs(10))
+        .await
+        .expect("No leader elected");
+    let leader = cluster.raft(leader_id);
+
+    let entry = |value: &str| {
+        LogEntry::Insert {
+            key: 1,
+            value: value.to_string(),
+        }
+        .encode()
+        .unwrap()
+    };
+    let request = ClientRequest {
+        client_id: 1,
+        seq: 1,
+    };
+
+    let first = leader
+        .propose_in_session(request, entry("first"))
+        .await
+        .unwrap();
+    // The retry isn't applied, and resolves with the result of the first attempt.
+    let retry = leader
+        .propose_in_session(request, entry("second"))
+        .await
+        .unwrap();
+    assert_eq!(retry, first);
+
+    cluster.advance(Duration::from_secs(1)).await;
+    for (_, raft) in cluster.rafts.iter() {
+        let store = raft.state_machine().await.unwrap();
+        assert_eq!(store.0.read().unwrap().get(&1).unwrap(), "first");
+    }
+
+    cluster.quit().await;
+}
+
+#[tokio::test(start_paused = true)]
+pub async fn test_simulated_session_survives_restart() {
+    let mut cluster =
+        SimulatedCluster::start_with_config(81, 3, |builder| builder.snapshot_entries_threshold(1))
+            .unwrap();
+    let leader_id = cluster
+        .wait_for_leader(&cluster.node_ids(), Duration::from_secs(10))
+        .await
+        .expect("No leader elected");
+    let mut rx_events = cluster
+        .rafts
+        .values()
+        .map(|raft| raft.subscribe())
+        .collect::<Vec<_>>();
+
+    let entry = |value: &str| {
+        LogEntry::Insert {
+            key: 1,
+            value: value.to_string(),
+        }
+        .encode()
+        .unwrap()
+    };
+    let request = ClientRequest {
+        client_id: 1,
+        seq: 1,
+    };
+
+    let first = cluster
+        .raft(leader_id)
+        .propose_in_session(request, entry("first"))
+        .await
+        .unwrap();
+    let applied_index = cluster.raft(leader_id).metrics().applied_index.get();
+    for rx_events in rx_events.iter_mut() {
+        wait_for_snapshot(rx_events, applied_index).await;
+    }
+
+    // Every node restarts from its snapshot, which holds the session table.
+    for node_id in cluster.node_ids() {
+        cluster.restart_node(node_id).await.unwrap();
+    }
+    let leader_id = cluster
+        .wait_for_leader(&cluster.node_ids(), Duration::from_secs(10))
+        .await
+        .expect("No leader elected");
+
+    // The retry is recognized as already applied, and resolves with the first result.
+    let retry = cluster
+        .raft(leader_id)
+        .propose_in_session(request, entry("second"))
+        .await
+        .unwrap();
+    assert_eq!(retry, first);
+
+    cluster.advance(Duration::from_secs(1)).await;
+    for (_, raft) in cluster.rafts.iter() {
+        let store = raft.state_machine().await.unwrap();
+        assert_ne!(
+            store.0.read().unwrap().get(&1).map(String::as_str),
+            Some("second")
+        );
+    }
+
+    cluster.quit().await;
+}
+
+#[tokio::test(start_paused = true)]
+pub async fn test_simulated_proposal_batching() {
+    let cluster = SimulatedCluster::start_with_config(83, 3, |builder| {
+        builder
+            .max_proposal_batch_size(16)
+            .proposal_batch_delay(0.01)
+    })
+    .unwrap();
+    let leader_id = cluster
+        .wait_for_leader(&cluster.node_ids(), Duration::from_secs(10))
+        .await
+        .expect("No leader elected");
+
+    let proposals = (1..=50)
+        .map(|key| {
+            let raft = cluster.raft(leader_id).clone();
+            let entry = LogEntry::Insert {
+                key,
+                value: key.to_string(),
+            }
+            .encode()
+            .unwrap();
+            tokio::spawn(async move { (entry.clone(), raft.propose(entry).await) })
+        })
+        .collect::<Vec<_>>();
+
+    // Each proposal is still answered with its own result.
+    for proposal in proposals {
+        let (entry, result) = proposal.await.unwrap();
+        assert_eq!(result.unwrap(), entry);
+    }
+
+    // The proposals were proposed in fewer batches than there are proposals.
+    let batch_size = &cluster.raft(leader_id).metrics().proposal_batch_size;
+    assert_eq!(batch_size.sum(), 50.0);
+    assert!(batch_size.count() < 50);
+
+    cluster.advance(Duration::from_secs(1)).await;
+    for (_, raft) in cluster.rafts.iter() {
+        let store = raft.state_machine().await.unwrap();
+        let store_lk = store.0.read().unwrap();
+        for key in 1..=50 {
+            assert_eq!(store_lk.get(&key).unwrap(), &key.to_string());
+        }
+    }
+
+    cluster.quit().await;
+}
+
+#[tokio::test(start_paused = true)]
+pub async fn test_simulated_typed_proposal() {
+    let cluster = SimulatedCluster::start(89, 3).unwrap();
+    let leader_id = cluster
+        .wait_for_leader(&cluster.node_ids(), Duration::from_secs(10))
+        .await
+        .expect("No leader elected");
+
+    let entry = LogEntry::Insert {
+        key: 1,
+        value: "test".to_string(),
+    };
+    // HashStore's output is the applied entry.
+    let output = cluster.raft(leader_id).propose_entry(&entry).await.unwrap();
+    assert!(matches!(output, LogEntry::Insert { key: 1, ref value } if value == "test"));
+
+    let store = cluster.raft(leader_id).state_machine().await.unwrap();
+    assert_eq!(store.0.read().unwrap().get(&1).unwrap(), "test");
+
+    cluster.quit().await;
+}
+
+#[tokio::test(start_paused = true)]
+pub async fn test_simulated_slow_apply_does_not_block_the_node() {
+    let cluster = SimulatedCluster::start(97, 3).unwrap();
+    let leader_id = cluster
+        .wait_for_leader(&cluster.node_ids(), Duration::from_secs(10))
+        .await
+        .expect("No leader elected");
+
+    let raft = cluster.raft(leader_id).clone();
+    let entry = LogEntry::Sleep { millis: 5000 };
+    let slow_proposal = tokio::spawn(async move { raft.propose_entry(&entry).await });
+
+    // The node keeps serving requests while the state machine is busy.
+    cluster.advance(Duration::from_secs(1)).await;
+    let leader = tokio::time::timeout(
+        Duration::from_millis(100),
+        cluster.raft(leader_id).get_leader_id(),
+    )
+    .await
+    .expect("The node is blocked by the state machine")
+    .unwrap();
+    assert_eq!(leader, leader_id);
+    assert!(!slow_proposal.is_finished());
+
+    slow_proposal.await.unwrap().unwrap();
+    assert_eq!(
+        cluster.leader_of(&cluster.node_ids()).await,
+        Some(leader_id)
+    );
+
+    cluster.quit().await;
+}
+
+async fn wait_for_snapshot(
+    rx_events: &mut tokio::sync::broadcast::Receiver<RaftEvent>,
+    min_index: u64,
+) -> u64 {
+    loop {
+        match rx_events.recv().await.unwrap() {
+            RaftEvent::SnapshotCreated { index, .. } if index >= min_index => return index,
+            _ => {}
+        }
+    }
+}
+
+fn insert(key: u64) -> LogEntry {
+    LogEntry::Insert {
+        key,
+        value: "test".to_string(),
+    }
+}
+
+#[tokio::test(start_paused = true)]
+pub async fn test_simulated_snapshot_entries_threshold() {
+    let cluster = SimulatedCluster::start_with_config(101, 3, |builder| {
+        builder.snapshot_entries_threshold(5)
+    })
+    .unwrap();
+    let leader_id = cluster
+        .wait_for_leader(&cluster.node_ids(), Duration::from_secs(10))
+        .await
+        .expect("No leader elected");
+    let leader = cluster.raft(leader_id);
+    let mut rx_events = leader.subscribe();
+
+    for key in 1..=5 {
+        leader.propose_entry(&insert(key)).await.unwrap();
+    }
+
+    // Enough entries were applied since the last snapshot.
+    tokio::time::timeout(Duration::from_secs(1), wait_for_snapshot(&mut rx_events, 5))
+        .await
+        .expect("No snapshot after the entries threshold");
+
+    cluster.quit().await;
+}
+
+#[tokio::test(start_paused = true)]
+pub async fn test_simulated_snapshot_log_size_threshold() {
+    let cluster = SimulatedCluster::start_with_config(102, 3, |builder| {
+        builder
+            .compaction_max_retained_bytes(1024)
+            .snapshot_log_size_threshold(8 * 1024)
+    })
+    .unwrap();
+    let leader_id = cluster
+        .wait_for_leader(&cluster.node_ids(), Duration::from_secs(10))
+        .await
+        .expect("No leader elected");
+    let leader = cluster.raft(leader_id);
+    let mut rx_events = leader.subscribe();
+    let insert_kib = |key| LogEntry::Insert {
+        key,
+        value: "x".repeat(1024),
+    };
+
+    // The log is still below the threshold.
+    for key in 1..=4 {
+        leader.propose_entry(&insert_kib(key)).await.unwrap();
+    }
+    tokio::time::sleep(Duration::from_secs(1)).await;
+    while let Ok(event) = rx_events.try_recv() {
+        assert!(!matches!(event, RaftEvent::SnapshotCreated { .. }));
+    }
+
+    for key in 5..=10 {
+        leader.propose_entry(&insert_kib(key)).await.unwrap();
+    }
+    let index = tokio::time::timeout(Duration::from_secs(1), wait_for_snapshot(&mut rx_events, 1))
+        .await
+        .expect("No snapshot after the log size threshold");
+
+    // Compaction only kept what fits in `compaction_max_retained_bytes`.
+    let storage = leader.storage().await.unwrap();
+    let first_index = storage.first_index().unwrap();
+    assert!(first_index >= index);
+
+    cluster.quit().await;
+}
+
+#[tokio::test(start_paused = true)]
+pub async fn test_simulated_snapshot_interval_on_idle_node() {
+    let cluster =
+        SimulatedCluster::start_with_config(103, 3, |builder| builder.snapshot_interval(2.0))
+            .unwrap();
+    let leader_id = cluster
+        .wait_for_leader(&cluster.node_ids(), Duration::from_secs(10))
+        .await
+        .expect("No leader elected");
+    let leader = cluster.raft(leader_id);
+    let mut rx_events = leader.subscribe();
+
+    leader.propose_entry(&insert(1)).await.unwrap();
+    let last_index = leader.storage().await.unwrap().last_index().unwrap();
+
+    // Nothing else is proposed, the snapshot is taken once the interval passes.
+    tokio::time::timeout(
+        Duration::from_secs(5),
+        wait_for_snapshot(&mut rx_events, last_index),
+    )
+    .await
+    .expect("No snapshot after the interval");
+
+    cluster.quit().await;
+}
+
+/// Lets a follower fall behind while the leader snapshots, then returns whether it had to
+/// be sent a snapshot to catch up.
+async fn catch_up_lagging_follower(
+    seed: u64,
+    configure: fn(ConfigBuilder) -> ConfigBuilder,
+) -> bool {
+    let cluster = SimulatedCluster::start_with_config(seed, 3, configure).unwrap();
+    let node_ids = cluster.node_ids();
+    let leader_id = cluster
+        .wait_for_leader(&node_ids, Duration::from_secs(10))
+        .await
+        .expect("No leader elected");
+    let leader = cluster.raft(leader_id);
+    let follower_id = *node_ids.iter().find(|id| **id != leader_id).unwrap();
+
+    leader.propose_entry(&insert(1)).await.unwrap();
+    cluster.advance(Duration::from_secs(1)).await;
+
+    let follower = cluster.raft(follower_id);
+    let follower_last_index = follower.storage().await.unwrap().last_index().unwrap();
+    cluster.network().isolate(follower_id, &node_ids);
+
+    // The leader snapshots past the entries the follower has.
+    let mut rx_leader_events = leader.subscribe();
+    for key in 2..=20 {
+        leader.propose_entry(&insert(key)).await.unwrap();
+    }
+    tokio::time::timeout(
+        Duration::from_secs(1),
+        wait_for_snapshot(&mut rx_leader_events, follower_last_index + 1),
+    )
+    .await
+    .expect("No snapshot after the entries threshold");
+
+    let mut rx_follower_events = follower.subscribe();
+    cluster.network().heal();
+    cluster.advance(Duration::from_secs(5)).await;
+
+    let store = follower.state_machine().await.unwrap();
+    assert_eq!(store.0.read().unwrap().get(&20).unwrap(), "test");
+
+    let mut is_restored = false;
+    while let Ok(event) = rx_follower_events.try_recv() {
+        is_restored |= matches!(event, RaftEvent::SnapshotRestored { .. });
+    }
+
+    cluster.quit().await;
+    is_restored
+}
+
+#[tokio::test(start_paused = true)]
+pub async fn test_simulated_compaction_keeps_entries_for_lagging_follower() {
+    // The leader keeps the entries the follower is missing, so it catches up from the log.
+    let is_restored =
+        catch_up_lagging_follower(107, |builder| builder.snapshot_entries_threshold(10)).await;
+    assert!(!is_restored);
+}
+
+#[tokio::test(start_paused = true)]
+pub async fn test_simulated_compaction_bounds_retained_bytes() {
+    // Nothing may be kept for the follower, so it has to catch up from a snapshot.
+    let is_restored = catch_up_lagging_follower(109, |builder| {
+        builder
+            .snapshot_entries_threshold(10)
+            .compaction_max_retained_bytes(0)
+    })
+    .await;
+    assert!(is_restored);
+}
+
+#[tokio::test(start_paused = true)]
+pub async fn test_simulated_replay_log() {
+    let cluster = SimulatedCluster::start_with_config(113, 3, |builder| {
+        builder
+            .snapshot_entries_threshold(5)
+            .save_compacted_logs(true)
+    })
+    .unwrap();
+    let leader_id = cluster
+        .wait_for_leader(&cluster.node_ids(), Duration::from_secs(10))
+        .await
+        .expect("No leader elected");
+    let leader = cluster.raft(leader_id);
+    let mut rx_events = leader.subscribe();
+
+    for key in 1..=12 {
+        leader.propose_entry(&insert(key)).await.unwrap();
+    }
+    tokio::time::timeout(
+        Duration::from_secs(1),
+        wait_for_snapshot(&mut rx_events, 10),
+    )
+    .await
+    .expect("No snapshot after the entries threshold");
+
+    let storage = leader.storage().await.unwrap();
+    let archive = LogArchive::open(Path::new(&cluster.storage_path(leader_id)));
+    let config = ConfigBuilder::new().build();
+
+    // Up to the latest entry, starting from the snapshot.
+    let mut fsm = HashStore::new();
+    let latest =
+        replay_log::<LogEntry, _, _>(&storage, None, &mut fsm, ReplayTarget::Latest, &config)
+            .await
+            .unwrap();
+    assert!(latest.snapshot_index >= 10);
+    let store = leader.state_machine().await.unwrap();
+    assert_eq!(*fsm.0.read().unwrap(), *store.0.read().unwrap());
+
+    // Before the snapshot, the compacted entries have to be replayed from the archive.
+    let target = ReplayTarget::Index(latest.snapshot_index - 3);
+    let result =
+        replay_log::<LogEntry, _, _>(&storage, None, &mut HashStore::new(), target, &config).await;
+    assert!(result.is_err());
+
+    let mut fsm = HashStore::new();
+    let outcome = replay_log::<LogEntry, _, _>(&storage, Some(&archive), &mut fsm, target, &config)
+        .await
+        .unwrap();
+    assert_eq!(outcome.snapshot_index, 0);
+    assert_eq!(outcome.applied_index, latest.snapshot_index - 3);
+
+    // The keys were inserted in order, one per replayed entry.
+    let mut keys = fsm.0.read().unwrap().keys().copied().collect::<Vec<_>>();
+    keys.sort_unstable();
+    assert_eq!(keys, (1..=outcome.applied_entries).collect::<Vec<_>>());
+    assert!(outcome.applied_entries > 0 && outcome.applied_entries < 12);
+
+    cluster.quit().await;
+}
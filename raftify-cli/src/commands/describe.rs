@@ -3,18 +3,20 @@ use core::panic;
 use serde_json::Value;
 use std::{
     collections::{BTreeSet, HashMap},
+    path::Path,
     sync::Arc,
 };
 
 use raftify::{
     create_client,
     raft::{
+        eraftpb::Entry,
         formatter::{format_entry, format_snapshot, CUSTOM_FORMATTER},
         logger::Slogger,
         Storage,
     },
     raft_node::utils::format_debugging_info,
-    raft_service, ConfigBuilder, HeedStorage, Result, StableStorage, StorageType,
+    raft_service, ConfigBuilder, HeedStorage, LogArchive, Result, StableStorage, StorageType,
 };
 
 pub fn describe_entries<LogStorage: StableStorage>(
@@ -49,6 +51,23 @@ pub fn describe_entries<LogStorage: StableStorage>(
         );
     }
 
+    print_entries(&entries, print_raw_format);
+    Ok(())
+}
+
+/// Reads the entries in `[from, to)` from the compacted log archive in `path`.
+pub fn describe_archived_entries(
+    path: &str,
+    from: u64,
+    to: u64,
+    print_raw_format: bool,
+) -> Result<()> {
+    let entries = LogArchive::open(Path::new(path)).read(from, to)?;
+    print_entries(&entries, print_raw_format);
+    Ok(())
+}
+
+fn print_entries(entries: &[Entry], print_raw_format: bool) {
     if print_raw_format {
         for entry in entries.iter() {
             println!("{}", format_entry(entry));
@@ -88,8 +107,6 @@ pub fn describe_entries<LogStorage: StableStorage>(
     }
 
     println!();
-
-    Ok(())
 }
 
 pub fn describe_metadata<LogStorage: StableStorage>(
@@ -131,11 +148,7 @@ pub fn describe_metadata<LogStorage: StableStorage>(
             .load_preset(UTF8_FULL)
             .set_content_arrangement(ContentArrangement::Dynamic);
 
-        table.set_header(vec![
-            Cell::new(""),
-            Cell::new("Field"),
-            Cell::new("Value"),
-        ]);
+        table.set_header(vec![Cell::new(""), Cell::new("Field"), Cell::new("Value")]);
 
         table.add_row(vec![
             Cell::new("HardState"),
@@ -1 +1,2 @@
 pub mod describe;
+pub mod replay;
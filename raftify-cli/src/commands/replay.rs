@@ -0,0 +1,54 @@
+use std::{fmt::Debug, path::Path, sync::Arc};
+
+use raftify::{
+    raft::logger::Slogger, replay_log, AbstractLogEntry, AbstractStateMachine, ConfigBuilder,
+    HeedStorage, LogArchive, ReplayTarget, Result, RocksDBStorage, StableStorage, StorageType,
+};
+
+pub async fn replay<
+    LogEntry: AbstractLogEntry,
+    LogStorage: StableStorage,
+    FSM: AbstractStateMachine + Debug,
+>(
+    path: &str,
+    archive_path: Option<&str>,
+    target: ReplayTarget,
+    mut fsm: FSM,
+    logger: slog::Logger,
+) -> Result<()> {
+    let config = ConfigBuilder::new().log_dir(path.to_string()).build();
+    let logger = Arc::new(Slogger { slog: logger });
+    let archive = archive_path.map(|archive_path| LogArchive::open(Path::new(archive_path)));
+
+    let outcome = match LogStorage::STORAGE_TYPE {
+        StorageType::Heed => {
+            let storage = HeedStorage::open_readonly(config.get_log_dir(), &config, logger)?;
+            replay_log::<LogEntry, _, _>(&storage, archive.as_ref(), &mut fsm, target, &config)
+                .await?
+        }
+        StorageType::RocksDB => {
+            let storage = RocksDBStorage::open_readonly(config.get_log_dir(), logger)?;
+            replay_log::<LogEntry, _, _>(&storage, archive.as_ref(), &mut fsm, target, &config)
+                .await?
+        }
+        StorageType::InMemory => {
+            panic!("InMemory storage does not support this feature");
+        }
+        _ => {
+            panic!("Unsupported storage type");
+        }
+    };
+
+    if outcome.snapshot_index > 0 {
+        println!("Restored the snapshot at index {}", outcome.snapshot_index);
+    } else {
+        println!("Started from an empty state machine");
+    }
+    println!(
+        "Replayed {} entries up to index {} (term {})",
+        outcome.applied_entries, outcome.applied_index, outcome.applied_term
+    );
+    println!("{:?}", fsm);
+
+    Ok(())
+}
@@ -3,12 +3,16 @@ include!(concat!(env!("OUT_DIR"), "/built.rs"));
 mod commands;
 
 use clap::{Parser, Subcommand};
-use commands::describe::{describe_entries, describe_metadata, describe_node};
+use commands::{
+    describe::{describe_archived_entries, describe_entries, describe_metadata, describe_node},
+    replay::replay,
+};
 use std::fmt::Debug;
 
 use raftify::{
     raft::{default_logger, formatter::set_custom_formatter},
-    AbstractLogEntry, AbstractStateMachine, CustomFormatter, Result, StableStorage,
+    AbstractLogEntry, AbstractStateMachine, CustomFormatter, Error, ReplayTarget, Result,
+    StableStorage,
 };
 
 use cfmt::formatcp;
@@ -38,6 +42,20 @@ enum Commands {
     /// Describe logs, metadata, and raft node information
     #[command(subcommand)]
     Describe(DescribeSubcommands),
+    /// Rebuild the state machine from the persisted snapshot and logs, and print it
+    Replay {
+        /// The log directory path
+        path: String,
+        /// The compacted log directory path, to replay archived entries from
+        #[arg(long)]
+        archive: Option<String>,
+        /// Stop after the entry at this index
+        #[arg(long, conflicts_with = "term")]
+        index: Option<u64>,
+        /// Stop after the last entry of this term
+        #[arg(long)]
+        term: Option<u64>,
+    },
 }
 
 #[derive(Subcommand)]
@@ -50,6 +68,20 @@ enum DescribeSubcommands {
         #[arg(long, default_value_t = false)]
         raw: bool,
     },
+    /// List archived compacted log entries
+    ArchivedLogs {
+        /// The compacted log directory path
+        path: String,
+        /// The first index to list
+        #[arg(long, default_value_t = 0)]
+        from: u64,
+        /// The index to stop listing at, exclusive
+        #[arg(long, default_value_t = u64::MAX)]
+        to: u64,
+        /// Print the output in raw format
+        #[arg(long, default_value_t = false)]
+        raw: bool,
+    },
     /// List persisted metadata
     Metadata {
         /// The log directory path
@@ -71,6 +103,30 @@ pub async fn cli_handler<
     FSM: AbstractStateMachine + Debug + Clone + Send + Sync + 'static,
 >(
     args: Option<Vec<String>>,
+) -> Result<()> {
+    run_cli::<LogEntry, LogStorage, FSM>(args, None).await
+}
+
+/// Same as `cli_handler`, but also supports the `replay` command, which rebuilds `fsm` from the log.
+/// `fsm` should be empty, as it's only restored if the snapshot doesn't go past the replay target.
+pub async fn cli_handler_with_fsm<
+    LogEntry: AbstractLogEntry + Debug + Send + 'static,
+    LogStorage: StableStorage + Send + Sync + Clone + 'static,
+    FSM: AbstractStateMachine + Debug + Clone + Send + Sync + 'static,
+>(
+    args: Option<Vec<String>>,
+    fsm: FSM,
+) -> Result<()> {
+    run_cli::<LogEntry, LogStorage, FSM>(args, Some(fsm)).await
+}
+
+async fn run_cli<
+    LogEntry: AbstractLogEntry + Debug + Send + 'static,
+    LogStorage: StableStorage + Send + Sync + Clone + 'static,
+    FSM: AbstractStateMachine + Debug + Clone + Send + Sync + 'static,
+>(
+    args: Option<Vec<String>>,
+    fsm: Option<FSM>,
 ) -> Result<()> {
     let app: App = match args {
         Some(args) => App::parse_from(args),
@@ -87,6 +143,14 @@ pub async fn cli_handler<
             } => {
                 describe_entries::<LogStorage>(path.as_str(), logger.clone(), print_raw_format)?;
             }
+            DescribeSubcommands::ArchivedLogs {
+                path,
+                from,
+                to,
+                raw: print_raw_format,
+            } => {
+                describe_archived_entries(path.as_str(), from, to, print_raw_format)?;
+            }
             DescribeSubcommands::Metadata {
                 path,
                 raw: print_raw_format,
@@ -97,6 +161,34 @@ pub async fn cli_handler<
                 describe_node(address.as_str()).await?;
             }
         },
+        Commands::Replay {
+            path,
+            archive,
+            index,
+            term,
+        } => {
+            let target = match (index, term) {
+                (Some(index), _) => ReplayTarget::Index(index),
+                (None, Some(term)) => ReplayTarget::Term(term),
+                (None, None) => ReplayTarget::Latest,
+            };
+            let fsm = match fsm {
+                Some(fsm) => fsm,
+                None => {
+                    return Err(Error::Other(
+                        "The replay command needs a state machine, see cli_handler_with_fsm".into(),
+                    ));
+                }
+            };
+            replay::<LogEntry, LogStorage, FSM>(
+                path.as_str(),
+                archive.as_deref(),
+                target,
+                fsm,
+                logger.clone(),
+            )
+            .await?;
+        }
     }
 
     Ok(())
@@ -1,5 +1,6 @@
-use raft::Config as RaftConfig;
+use raft::{Config as RaftConfig, ReadOnlyOption};
 use serde::{Deserialize, Serialize};
+use std::net::SocketAddr;
 
 use crate::Peers;
 
@@ -41,16 +42,33 @@ impl ConfigBuilder {
         self
     }
 
+    /// Maximum size of the archived compacted logs. The oldest segments are removed beyond it.
     pub fn compacted_log_size_threshold(mut self, threshold: u64) -> Self {
         self.config.compacted_log_size_threshold = threshold;
         self
     }
 
+    /// Size at which a new segment of the compacted log archive is started.
+    pub fn compacted_log_segment_size(mut self, size: u64) -> Self {
+        self.config.compacted_log_segment_size = size;
+        self
+    }
+
     pub fn raft_config(mut self, raft_config: RaftConfig) -> Self {
         self.config.raft_config = raft_config;
         self
     }
 
+    pub fn read_only_option(mut self, option: ReadOnlyOption) -> Self {
+        self.config.raft_config.read_only_option = option;
+        self
+    }
+
+    pub fn check_quorum(mut self, check_quorum: bool) -> Self {
+        self.config.raft_config.check_quorum = check_quorum;
+        self
+    }
+
     pub fn set_node_id(mut self, node_id: u64) -> Self {
         self.config.raft_config.id = node_id;
         self
@@ -76,16 +94,113 @@ impl ConfigBuilder {
         self
     }
 
+    /// How long, in seconds, requests wait for a leader to be elected before failing with
+    /// `Error::NoLeader`. 0 fails them right away.
+    pub fn leaderless_request_timeout(mut self, timeout: f32) -> Self {
+        self.config.leaderless_request_timeout = timeout;
+        self
+    }
+
+    /// How long, in seconds, the leader waits for a proposal to be applied, or for a config change
+    /// or read index request to complete, before failing it with `Error::Timeout`. 0 waits forever.
+    /// Overridden for proposals by `RaftNode::propose_with_timeout`.
+    pub fn proposal_timeout(mut self, timeout: f32) -> Self {
+        self.config.proposal_timeout = timeout;
+        self
+    }
+
+    /// Number of client sessions kept for deduplicating proposals.
+    /// Beyond it, the session idle for the longest is forgotten.
+    pub fn max_client_sessions(mut self, max_sessions: u64) -> Self {
+        self.config.max_client_sessions = max_sessions;
+        self
+    }
+
+    /// Maximum number of proposals the leader appends and replicates together.
+    /// 1, the default, proposes each proposal on its own.
+    pub fn max_proposal_batch_size(mut self, batch_size: u64) -> Self {
+        self.config.max_proposal_batch_size = batch_size;
+        self
+    }
+
+    /// How long, in seconds, the leader waits for more proposals to fill a batch.
+    /// 0 only batches the proposals already queued.
+    pub fn proposal_batch_delay(mut self, delay: f32) -> Self {
+        self.config.proposal_batch_delay = delay;
+        self
+    }
+
     pub fn initial_peers(mut self, peers: Peers) -> Self {
         self.config.initial_peers = Some(peers);
         self
     }
 
+    /// Snapshots once `interval` seconds have passed since the last snapshot.
+    /// Like the other snapshot policies, only applies if entries were applied since then.
     pub fn snapshot_interval(mut self, interval: f32) -> Self {
         self.config.snapshot_interval = Some(interval);
         self
     }
 
+    /// Snapshots once `threshold` entries have been applied since the last snapshot.
+    pub fn snapshot_entries_threshold(mut self, threshold: u64) -> Self {
+        self.config.snapshot_entries_threshold = Some(threshold);
+        self
+    }
+
+    /// Snapshots once the entries in the log take `threshold` bytes.
+    /// Must be greater than `compaction_max_retained_bytes`.
+    pub fn snapshot_log_size_threshold(mut self, threshold: u64) -> Self {
+        self.config.snapshot_log_size_threshold = Some(threshold);
+        self
+    }
+
+    /// Number of entries before a new snapshot to keep in the log when compacting it.
+    pub fn compaction_retained_entries(mut self, entries: u64) -> Self {
+        self.config.compaction_retained_entries = entries;
+        self
+    }
+
+    /// Upper bound on the bytes of entries kept in the log after compacting it,
+    /// including those kept for followers lagging behind the snapshot.
+    pub fn compaction_max_retained_bytes(mut self, bytes: u64) -> Self {
+        self.config.compaction_max_retained_bytes = bytes;
+        self
+    }
+
+    pub fn snapshot_chunk_size(mut self, size: u64) -> Self {
+        self.config.snapshot_chunk_size = size;
+        self
+    }
+
+    /// Largest snapshot accepted from a peer, in bytes.
+    pub fn max_snapshot_size(mut self, size: u64) -> Self {
+        self.config.max_snapshot_size = size;
+        self
+    }
+
+    pub fn peer_message_queue_size(mut self, size: u64) -> Self {
+        self.config.peer_message_queue_size = size;
+        self
+    }
+
+    pub fn max_message_batch_size(mut self, size: u64) -> Self {
+        self.config.max_message_batch_size = size;
+        self
+    }
+
+    /// How many entries a learner may lag behind the leader's last index to be promoted.
+    pub fn learner_catch_up_threshold(mut self, threshold: u64) -> Self {
+        self.config.learner_catch_up_threshold = threshold;
+        self
+    }
+
+    /// Address to serve the Prometheus metrics on. Requires the `prometheus` feature.
+    pub fn metrics_addr(mut self, addr: SocketAddr) -> Self {
+        self.config.metrics_addr = Some(addr);
+        self
+    }
+
     pub fn server_tls_config(mut self, config: TlsConfig) -> Self {
         self.config.server_tls_config = Some(config);
         self
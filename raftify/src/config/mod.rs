@@ -1,6 +1,7 @@
 use config::{Config as BaseConfig, ConfigError, File};
-use raft::Config as RaftConfig;
+use raft::{Config as RaftConfig, ReadOnlyOption};
 use serde::{Deserialize, Serialize};
+use std::net::SocketAddr;
 
 use crate::{error::Error, error::Result, peers::Peers, InitialRole};
 pub mod config_builder;
@@ -23,13 +24,29 @@ pub struct Config {
     pub(crate) save_compacted_logs: bool,
     pub(crate) compacted_log_dir: String,
     pub(crate) compacted_log_size_threshold: u64,
+    pub(crate) compacted_log_segment_size: u64,
     pub(crate) tick_interval: f32,
     pub(crate) lmdb_map_size: u64,
     pub(crate) bootstrap_from_snapshot: bool,
     pub(crate) cluster_id: String,
     pub(crate) conf_change_request_timeout: f32,
+    pub(crate) leaderless_request_timeout: f32,
+    pub(crate) proposal_timeout: f32,
+    pub(crate) max_client_sessions: u64,
+    pub(crate) max_proposal_batch_size: u64,
+    pub(crate) proposal_batch_delay: f32,
     pub(crate) initial_peers: Option<Peers>,
     pub(crate) snapshot_interval: Option<f32>,
+    pub(crate) snapshot_entries_threshold: Option<u64>,
+    pub(crate) snapshot_log_size_threshold: Option<u64>,
+    pub(crate) compaction_retained_entries: u64,
+    pub(crate) compaction_max_retained_bytes: u64,
+    pub(crate) snapshot_chunk_size: u64,
+    pub(crate) max_snapshot_size: u64,
+    pub(crate) peer_message_queue_size: u64,
+    pub(crate) max_message_batch_size: u64,
+    pub(crate) learner_catch_up_threshold: u64,
+    pub(crate) metrics_addr: Option<SocketAddr>,
     pub(crate) client_tls_config: Option<TlsConfig>,
     pub(crate) server_tls_config: Option<TlsConfig>,
 }
@@ -42,12 +59,28 @@ impl Default for Config {
             save_compacted_logs: false,
             compacted_log_dir: String::from("./"),
             compacted_log_size_threshold: 1024 * 1024 * 1024,
+            compacted_log_segment_size: 64 * 1024 * 1024,
             tick_interval: 0.1,
             lmdb_map_size: 1024 * 1024 * 1024,
             cluster_id: String::from("default"),
             conf_change_request_timeout: 2.0,
+            leaderless_request_timeout: 2.0,
+            proposal_timeout: 10.0,
+            max_client_sessions: 10000,
+            max_proposal_batch_size: 1,
+            proposal_batch_delay: 0.0,
             initial_peers: None,
             snapshot_interval: None,
+            snapshot_entries_threshold: None,
+            snapshot_log_size_threshold: None,
+            compaction_retained_entries: 0,
+            compaction_max_retained_bytes: 64 * 1024 * 1024,
+            snapshot_chunk_size: 1024 * 1024,
+            max_snapshot_size: 1024 * 1024 * 1024,
+            peer_message_queue_size: 4096,
+            max_message_batch_size: 64,
+            learner_catch_up_threshold: 100,
+            metrics_addr: None,
             bootstrap_from_snapshot: false,
             client_tls_config: None,
             server_tls_config: None,
@@ -75,6 +108,84 @@ impl Config {
             }
         }
 
+        if self.raft_config.read_only_option == ReadOnlyOption::LeaseBased
+            && !self.raft_config.check_quorum
+        {
+            return Err(Error::ConfigInvalid(
+                "Lease based reads require check_quorum, otherwise the leader lease is not safe"
+                    .to_owned(),
+            ));
+        }
+
+        if self.snapshot_entries_threshold == Some(0) || self.snapshot_log_size_threshold == Some(0)
+        {
+            return Err(Error::ConfigInvalid(
+                "snapshot_entries_threshold and snapshot_log_size_threshold must be greater than 0"
+                    .to_owned(),
+            ));
+        }
+
+        // Compaction keeps up to `compaction_max_retained_bytes` in the log, so a lower threshold
+        // would snapshot after every applied entry.
+        if self
+            .snapshot_log_size_threshold
+            .is_some_and(|threshold| threshold <= self.compaction_max_retained_bytes)
+        {
+            return Err(Error::ConfigInvalid(
+                "snapshot_log_size_threshold must be greater than compaction_max_retained_bytes"
+                    .to_owned(),
+            ));
+        }
+
+        if self.compacted_log_segment_size == 0 {
+            return Err(Error::ConfigInvalid(
+                "compacted_log_segment_size must be greater than 0".to_owned(),
+            ));
+        }
+
+        if self.snapshot_chunk_size == 0 {
+            return Err(Error::ConfigInvalid(
+                "snapshot_chunk_size must be greater than 0".to_owned(),
+            ));
+        }
+
+        if self.peer_message_queue_size == 0 || self.max_message_batch_size == 0 {
+            return Err(Error::ConfigInvalid(
+                "peer_message_queue_size and max_message_batch_size must be greater than 0"
+                    .to_owned(),
+            ));
+        }
+
+        if self.max_client_sessions == 0 {
+            return Err(Error::ConfigInvalid(
+                "max_client_sessions must be greater than 0".to_owned(),
+            ));
+        }
+
+        if self.max_proposal_batch_size == 0 {
+            return Err(Error::ConfigInvalid(
+                "max_proposal_batch_size must be greater than 0".to_owned(),
+            ));
+        }
+
+        if !(self.proposal_batch_delay >= 0.0 && self.proposal_batch_delay.is_finite()) {
+            return Err(Error::ConfigInvalid(
+                "proposal_batch_delay must be a non-negative number of seconds".to_owned(),
+            ));
+        }
+
+        if self.proposal_batch_delay >= self.tick_interval {
+            return Err(Error::ConfigInvalid(
+                "proposal_batch_delay must be less than tick_interval".to_owned(),
+            ));
+        }
+
+        if cfg!(not(feature = "prometheus")) && self.metrics_addr.is_some() {
+            return Err(Error::ConfigInvalid(
+                "metrics_addr requires the prometheus feature".to_owned(),
+            ));
+        }
+
         self.raft_config.validate()?;
         Ok(())
     }
@@ -1,6 +1,8 @@
 use config::ConfigError;
 use thiserror::Error as ThisError;
 
+use crate::raft_service;
+
 pub type Result<T> = std::result::Result<T, Error>;
 
 #[derive(Debug, ThisError)]
@@ -13,11 +15,18 @@ pub enum Error {
     JoinError,
     #[error("Request rejected, cause: `{0}`")]
     Rejected(String),
+    /// No leader was elected in time to handle the request. Safe to retry.
+    #[error("There is no leader in the cluster")]
+    NoLeader,
     #[error("Invalid config error. cause: `{0}`")]
     ConfigInvalid(String),
 
     #[error("Request timeout")]
     Timeout,
+    /// The leader stepped down before the request was committed.
+    /// The request may or may not be committed by the next leader.
+    #[error("Leadership lost before the request was committed")]
+    LeadershipLost,
     #[error("gRPC error: `{0}`")]
     Grpc(#[from] tonic::transport::Error),
     #[error("Error calling remote procedure: `{0}`")]
@@ -26,7 +35,7 @@ pub enum Error {
     #[error("IO error: {0}")]
     Io(#[from] tokio::io::Error),
     #[error("Storage error: `{0}`")]
-    Database(#[from] heed::Error),
+    Database(#[from] DatabaseError),
     #[error("Unexpected error")]
     Other(#[source] Box<dyn std::error::Error + Sync + Send + 'static>),
     #[error("Unknown error")]
@@ -43,10 +52,30 @@ pub enum Error {
     #[error("Decoding error")]
     DecodingError(String),
 
+    #[error("Snapshot transfer error: {0}")]
+    SnapshotTransferError(String),
+
+    #[error("Snapshot integrity error: {0}")]
+    SnapshotIntegrityError(String),
+
     #[error("Config file format invalid")]
     ConfigFileParsingError(#[from] ConfigError),
 }
 
+/// Error raised by the database behind a `StableStorage`, such as a full disk or corrupted data.
+#[derive(Debug, ThisError)]
+pub enum DatabaseError {
+    #[cfg(feature = "heed_storage")]
+    #[error("Heed error: {0}")]
+    Heed(#[from] heed::Error),
+    #[cfg(feature = "rocksdb_storage")]
+    #[error("RocksDB error: {0}")]
+    RocksDB(#[from] rocksdb::Error),
+    /// The database holds data that can't be read back.
+    #[error("Corrupted data: {0}")]
+    Corrupted(String),
+}
+
 #[derive(Debug, ThisError)]
 pub enum SendMessageError {
     #[error("Failed to connect to node. {0}")]
@@ -55,6 +84,42 @@ pub enum SendMessageError {
     PeerNotFound(String),
     #[error("Failed to send message. {0}")]
     TransmissionError(String),
+    #[error("Message queue of node {0} is full")]
+    QueueFull(String),
+}
+
+impl Error {
+    /// Error type reported to the node that forwarded a Propose or ReadIndex request.
+    pub(crate) fn request_error_type(&self) -> raft_service::RequestErrorType {
+        match self {
+            Error::Rejected(_) => raft_service::RequestErrorType::RequestRejected,
+            Error::NoLeader => raft_service::RequestErrorType::RequestNoLeader,
+            Error::Timeout => raft_service::RequestErrorType::RequestTimeout,
+            Error::LeadershipLost => raft_service::RequestErrorType::RequestLeadershipLost,
+            _ => raft_service::RequestErrorType::RequestUnknownError,
+        }
+    }
+
+    /// Rebuilds the error a forwarded Propose or ReadIndex request failed with on the leader.
+    pub(crate) fn from_request_error(
+        error_type: raft_service::RequestErrorType,
+        message: &[u8],
+    ) -> Self {
+        let message = String::from_utf8_lossy(message).into_owned();
+        match error_type {
+            raft_service::RequestErrorType::RequestRejected => Error::Rejected(message),
+            raft_service::RequestErrorType::RequestNoLeader => Error::NoLeader,
+            raft_service::RequestErrorType::RequestTimeout => Error::Timeout,
+            raft_service::RequestErrorType::RequestLeadershipLost => Error::LeadershipLost,
+            raft_service::RequestErrorType::RequestUnknownError => Error::Other(message.into()),
+        }
+    }
+}
+
+impl From<SendMessageError> for Error {
+    fn from(e: SendMessageError) -> Self {
+        Self::Other(Box::new(e))
+    }
 }
 
 impl From<prost::DecodeError> for Error {
@@ -68,3 +133,29 @@ impl From<bincode::Error> for Error {
         Self::Other(e)
     }
 }
+
+#[cfg(feature = "heed_storage")]
+impl From<heed::Error> for Error {
+    fn from(e: heed::Error) -> Self {
+        Self::Database(DatabaseError::Heed(e))
+    }
+}
+
+#[cfg(feature = "rocksdb_storage")]
+impl From<rocksdb::Error> for Error {
+    fn from(e: rocksdb::Error) -> Self {
+        Self::Database(DatabaseError::RocksDB(e))
+    }
+}
+
+/// Lets `raft::Storage` implementations propagate errors with `?`.
+/// Raft errors are passed through, so that raft-rs still sees e.g. `StorageError::Compacted`.
+impl From<Error> for crate::raft::Error {
+    fn from(e: Error) -> Self {
+        match e {
+            Error::RaftError(e) => e,
+            Error::RaftStorageError(e) => crate::raft::Error::Store(e),
+            e => crate::raft::Error::Store(crate::raft::StorageError::Other(Box::new(e))),
+        }
+    }
+}
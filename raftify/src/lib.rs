@@ -7,14 +7,17 @@ mod peers;
 mod raft_bootstrapper;
 mod raft_client;
 mod raft_server;
+mod replay;
 mod state_machine;
 mod storage;
+mod transport;
 mod utils;
 
 mod request;
 mod response;
 
 pub mod cluster_join_ticket;
+pub mod metrics;
 pub mod raft_node;
 pub mod raft_service;
 
@@ -26,17 +29,27 @@ pub use {
 pub use crate::{
     cluster_join_ticket::ClusterJoinTicket,
     config::{config_builder::ConfigBuilder, load_configs, Config, TlsConfig},
-    error::{Error, Result},
+    error::{DatabaseError, Error, Result, SendMessageError},
     log_entry::AbstractLogEntry,
+    metrics::RaftMetrics,
     peer::Peer,
     peers::Peers,
     raft_bootstrapper::Raft,
     raft_client::create_client,
-    raft_node::{role::InitialRole, utils::format_debugging_info, RaftNode},
+    raft_node::{
+        event::RaftEvent, role::InitialRole, session::ClientRequest, utils::format_debugging_info,
+        RaftNode,
+    },
     raft_service::raft_service_client::RaftServiceClient,
+    replay::{replay_log, ReplayOutcome, ReplayTarget},
     request::common::confchange_request::ConfChangeRequest,
-    state_machine::AbstractStateMachine,
-    storage::{StableStorage, StorageType},
+    response::server_response_message::ConfChangeResponseResult,
+    state_machine::{AbstractOutput, AbstractStateMachine, SnapshotReader, SnapshotWriter},
+    storage::{log_archive::LogArchive, StableStorage, StorageType},
+    transport::{
+        ForwardedRequest, ForwardedResponse, GrpcTransport, InProcessTransport, InboundMessages,
+        Transport,
+    },
 };
 
 #[cfg(feature = "heed_storage")]
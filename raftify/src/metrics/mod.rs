@@ -0,0 +1,228 @@
+#[cfg(feature = "prometheus")]
+mod prometheus;
+
+#[cfg(feature = "prometheus")]
+pub(crate) use prometheus::bind_metrics;
+#[cfg(feature = "prometheus")]
+pub use prometheus::serve_metrics;
+
+use parking_lot::Mutex;
+use std::{
+    collections::BTreeMap,
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+// Bucket bounds in seconds
+const LATENCY_BUCKETS: &[f64] = &[
+    0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+// Bucket bounds in bytes
+const SIZE_BUCKETS: &[f64] = &[
+    1024.0,
+    16.0 * 1024.0,
+    256.0 * 1024.0,
+    1024.0 * 1024.0,
+    16.0 * 1024.0 * 1024.0,
+    256.0 * 1024.0 * 1024.0,
+    1024.0 * 1024.0 * 1024.0,
+];
+
+// Bucket bounds in number of proposals
+const BATCH_SIZE_BUCKETS: &[f64] = &[1.0, 2.0, 4.0, 8.0, 16.0, 32.0, 64.0, 128.0, 256.0];
+
+#[derive(Debug, Default)]
+pub struct Counter(AtomicU64);
+
+impl Counter {
+    pub fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn inc(&self) {
+        self.0.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Gauge(AtomicU64);
+
+impl Gauge {
+    pub fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn set(&self, value: u64) {
+        self.0.store(value, Ordering::Relaxed);
+    }
+}
+
+#[derive(Debug)]
+pub struct Histogram {
+    bounds: &'static [f64],
+    // One counter per bound plus the +Inf bucket, not cumulative
+    buckets: Vec<AtomicU64>,
+    // f64 bits
+    sum: AtomicU64,
+    count: AtomicU64,
+}
+
+impl Histogram {
+    fn new(bounds: &'static [f64]) -> Self {
+        Self {
+            bounds,
+            buckets: (0..=bounds.len()).map(|_| AtomicU64::new(0)).collect(),
+            sum: AtomicU64::new(0f64.to_bits()),
+            count: AtomicU64::new(0),
+        }
+    }
+
+    pub(crate) fn observe(&self, value: f64) {
+        let bucket = self
+            .bounds
+            .iter()
+            .position(|bound| value <= *bound)
+            .unwrap_or(self.bounds.len());
+        self.buckets[bucket].fetch_add(1, Ordering::Relaxed);
+
+        let _ = self
+            .sum
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |sum| {
+                Some((f64::from_bits(sum) + value).to_bits())
+            });
+        self.count.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn observe_duration(&self, duration: Duration) {
+        self.observe(duration.as_secs_f64());
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count.load(Ordering::Relaxed)
+    }
+
+    pub fn sum(&self) -> f64 {
+        f64::from_bits(self.sum.load(Ordering::Relaxed))
+    }
+
+    /// Returns the cumulative count of every bucket by its upper bound, ending with +Inf.
+    pub fn buckets(&self) -> Vec<(f64, u64)> {
+        let mut cumulative = 0;
+        self.bounds
+            .iter()
+            .copied()
+            .chain(std::iter::once(f64::INFINITY))
+            .zip(self.buckets.iter())
+            .map(|(bound, count)| {
+                cumulative += count.load(Ordering::Relaxed);
+                (bound, cumulative)
+            })
+            .collect()
+    }
+}
+
+/// Metrics of a RaftNode, updated by the node as it runs.
+/// Latencies and durations are in seconds, sizes in bytes.
+#[derive(Debug)]
+pub struct RaftMetrics {
+    pub node_id: u64,
+    pub term: Gauge,
+    pub leader_id: Gauge,
+    pub leader_changes: Counter,
+    pub commit_index: Gauge,
+    pub applied_index: Gauge,
+    pub persisted_index: Gauge,
+    /// Time from receiving a proposal on the leader until it is applied.
+    pub proposal_latency: Histogram,
+    /// Time the state machine takes to apply a committed entry.
+    pub apply_latency: Histogram,
+    /// Number of proposals the leader proposed in each batch.
+    pub proposal_batch_size: Histogram,
+    pub snapshot_size: Histogram,
+    /// Time taken to create a snapshot.
+    pub snapshot_duration: Histogram,
+    /// Time taken to restore the state machine from a received snapshot.
+    pub snapshot_restore_duration: Histogram,
+    /// Number of stored snapshots found corrupted.
+    pub snapshot_corruptions: Counter,
+    /// Number of requests waiting in the RaftNode's `tx_local` queue.
+    pub local_queue_depth: Gauge,
+    /// Number of requests waiting in the RaftServer's `tx_server` queue.
+    pub server_queue_depth: Gauge,
+    peer_send_failures: Mutex<BTreeMap<u64, u64>>,
+}
+
+impl RaftMetrics {
+    pub fn new(node_id: u64) -> Self {
+        Self {
+            node_id,
+            term: Gauge::default(),
+            leader_id: Gauge::default(),
+            leader_changes: Counter::default(),
+            commit_index: Gauge::default(),
+            applied_index: Gauge::default(),
+            persisted_index: Gauge::default(),
+            proposal_latency: Histogram::new(LATENCY_BUCKETS),
+            apply_latency: Histogram::new(LATENCY_BUCKETS),
+            proposal_batch_size: Histogram::new(BATCH_SIZE_BUCKETS),
+            snapshot_size: Histogram::new(SIZE_BUCKETS),
+            snapshot_duration: Histogram::new(LATENCY_BUCKETS),
+            snapshot_restore_duration: Histogram::new(LATENCY_BUCKETS),
+            snapshot_corruptions: Counter::default(),
+            local_queue_depth: Gauge::default(),
+            server_queue_depth: Gauge::default(),
+            peer_send_failures: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// Number of failed sends to each peer.
+    pub fn peer_send_failures(&self) -> BTreeMap<u64, u64> {
+        self.peer_send_failures.lock().clone()
+    }
+
+    pub(crate) fn record_peer_send_failure(&self, node_id: u64) {
+        *self.peer_send_failures.lock().entry(node_id).or_insert(0) += 1;
+    }
+
+    pub(crate) fn set_leader_id(&self, leader_id: u64) {
+        let previous = self.leader_id.0.swap(leader_id, Ordering::Relaxed);
+        if previous != leader_id && leader_id != 0 {
+            self.leader_changes.inc();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_histogram_buckets_are_cumulative() {
+        let histogram = Histogram::new(&[1.0, 2.0]);
+        histogram.observe(0.5);
+        histogram.observe(1.5);
+        histogram.observe(1.5);
+        histogram.observe(3.0);
+
+        assert_eq!(histogram.count(), 4);
+        assert_eq!(histogram.sum(), 6.5);
+        assert_eq!(
+            histogram.buckets(),
+            vec![(1.0, 1), (2.0, 3), (f64::INFINITY, 4)]
+        );
+    }
+
+    #[test]
+    fn test_leader_changes() {
+        let metrics = RaftMetrics::new(1);
+        metrics.set_leader_id(1);
+        metrics.set_leader_id(1);
+        // Losing the leader is not a change until a new one is known
+        metrics.set_leader_id(0);
+        metrics.set_leader_id(2);
+
+        assert_eq!(metrics.leader_id.get(), 2);
+        assert_eq!(metrics.leader_changes.get(), 2);
+    }
+}
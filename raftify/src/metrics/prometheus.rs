@@ -0,0 +1,221 @@
+use hyper::{
+    header::CONTENT_TYPE,
+    service::{make_service_fn, service_fn},
+    Body, Response, Server,
+};
+use std::{convert::Infallible, fmt::Write, future::Future, net::SocketAddr, sync::Arc};
+
+use super::{Histogram, RaftMetrics};
+use crate::error::{Error, Result};
+
+const CONTENT_TYPE_TEXT: &str = "text/plain; version=0.0.4";
+
+impl RaftMetrics {
+    /// Renders the metrics in the Prometheus text exposition format.
+    pub fn encode_prometheus(&self) -> String {
+        let mut out = String::new();
+        let labels = format!("node_id=\"{}\"", self.node_id);
+
+        let gauges = [
+            ("raftify_term", "Current term", self.term.get()),
+            (
+                "raftify_leader_id",
+                "Known leader, 0 if none",
+                self.leader_id.get(),
+            ),
+            (
+                "raftify_commit_index",
+                "Commit index",
+                self.commit_index.get(),
+            ),
+            (
+                "raftify_applied_index",
+                "Applied index",
+                self.applied_index.get(),
+            ),
+            (
+                "raftify_persisted_index",
+                "Persisted index",
+                self.persisted_index.get(),
+            ),
+            (
+                "raftify_local_queue_depth",
+                "Requests waiting in the RaftNode queue",
+                self.local_queue_depth.get(),
+            ),
+            (
+                "raftify_server_queue_depth",
+                "Requests waiting in the RaftServer queue",
+                self.server_queue_depth.get(),
+            ),
+        ];
+        for (name, help, value) in gauges {
+            write_header(&mut out, name, help, "gauge");
+            let _ = writeln!(out, "{}{{{}}} {}", name, labels, value);
+        }
+
+        write_header(
+            &mut out,
+            "raftify_leader_changes_total",
+            "Number of observed leader changes",
+            "counter",
+        );
+        let _ = writeln!(
+            out,
+            "raftify_leader_changes_total{{{}}} {}",
+            labels,
+            self.leader_changes.get()
+        );
+
+        write_header(
+            &mut out,
+            "raftify_snapshot_corruptions_total",
+            "Number of stored snapshots found corrupted",
+            "counter",
+        );
+        let _ = writeln!(
+            out,
+            "raftify_snapshot_corruptions_total{{{}}} {}",
+            labels,
+            self.snapshot_corruptions.get()
+        );
+
+        write_header(
+            &mut out,
+            "raftify_peer_send_failures_total",
+            "Failed sends to each peer",
+            "counter",
+        );
+        for (peer_id, failures) in self.peer_send_failures() {
+            let _ = writeln!(
+                out,
+                "raftify_peer_send_failures_total{{{},peer_id=\"{}\"}} {}",
+                labels, peer_id, failures
+            );
+        }
+
+        let histograms = [
+            (
+                "raftify_proposal_latency_seconds",
+                "Time from receiving a proposal until it is applied",
+                &self.proposal_latency,
+            ),
+            (
+                "raftify_apply_latency_seconds",
+                "Time taken to apply a committed entry",
+                &self.apply_latency,
+            ),
+            (
+                "raftify_proposal_batch_size",
+                "Number of proposals proposed in each batch",
+                &self.proposal_batch_size,
+            ),
+            (
+                "raftify_snapshot_size_bytes",
+                "Size of the created snapshots",
+                &self.snapshot_size,
+            ),
+            (
+                "raftify_snapshot_duration_seconds",
+                "Time taken to create a snapshot",
+                &self.snapshot_duration,
+            ),
+            (
+                "raftify_snapshot_restore_duration_seconds",
+                "Time taken to restore a received snapshot",
+                &self.snapshot_restore_duration,
+            ),
+        ];
+        for (name, help, histogram) in histograms {
+            write_header(&mut out, name, help, "histogram");
+            write_histogram(&mut out, name, &labels, histogram);
+        }
+
+        out
+    }
+}
+
+fn write_header(out: &mut String, name: &str, help: &str, typ: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, typ);
+}
+
+fn write_histogram(out: &mut String, name: &str, labels: &str, histogram: &Histogram) {
+    for (bound, count) in histogram.buckets() {
+        let bound = if bound.is_infinite() {
+            "+Inf".to_owned()
+        } else {
+            bound.to_string()
+        };
+        let _ = writeln!(
+            out,
+            "{}_bucket{{{},le=\"{}\"}} {}",
+            name, labels, bound, count
+        );
+    }
+    let _ = writeln!(out, "{}_sum{{{}}} {}", name, labels, histogram.sum());
+    let _ = writeln!(out, "{}_count{{{}}} {}", name, labels, histogram.count());
+}
+
+/// Serves the metrics in the Prometheus text format on every path of `addr`.
+pub async fn serve_metrics(metrics: Arc<RaftMetrics>, addr: SocketAddr) -> Result<()> {
+    bind_metrics(metrics, addr)?.await
+}
+
+/// Binds `addr` right away, so that an unusable address is reported before serving starts.
+pub(crate) fn bind_metrics(
+    metrics: Arc<RaftMetrics>,
+    addr: SocketAddr,
+) -> Result<impl Future<Output = Result<()>> + Send> {
+    let make_service = make_service_fn(move |_| {
+        let metrics = metrics.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |_| {
+                let body = metrics.encode_prometheus();
+                async move {
+                    Ok::<_, Infallible>(
+                        Response::builder()
+                            .header(CONTENT_TYPE, CONTENT_TYPE_TEXT)
+                            .body(Body::from(body))
+                            .unwrap(),
+                    )
+                }
+            }))
+        }
+    });
+
+    let server = Server::try_bind(&addr)
+        .map_err(|e| Error::Other(Box::new(e)))?
+        .serve(make_service);
+    Ok(async move { server.await.map_err(|e| Error::Other(Box::new(e))) })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_encode_prometheus() {
+        let metrics = RaftMetrics::new(3);
+        metrics.term.set(7);
+        metrics.record_peer_send_failure(2);
+        metrics.apply_latency.observe(0.002);
+
+        let text = metrics.encode_prometheus();
+        assert!(text.contains("raftify_term{node_id=\"3\"} 7"));
+        assert!(text.contains("raftify_peer_send_failures_total{node_id=\"3\",peer_id=\"2\"} 1"));
+        assert!(
+            text.contains("raftify_apply_latency_seconds_bucket{node_id=\"3\",le=\"0.0025\"} 1")
+        );
+        assert!(text.contains("raftify_apply_latency_seconds_count{node_id=\"3\"} 1"));
+        assert!(text.contains("# TYPE raftify_snapshot_size_bytes histogram"));
+    }
+
+    #[tokio::test]
+    async fn test_bind_metrics_fails_on_used_address() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        assert!(bind_metrics(Arc::new(RaftMetrics::new(1)), addr).is_err());
+    }
+}
@@ -1,12 +1,22 @@
 use crate::{
-    config::TlsConfig, raft::logger::Logger, request::server_request_message::ServerRequestMsg,
+    config::TlsConfig,
+    error::SendMessageError,
+    raft::logger::Logger,
+    raft_service::raft_service_server::RaftServiceServer,
+    request::server_request_message::ServerRequestMsg,
+    transport::{GrpcTransport, InboundMessages, RaftGrpcService, Transport},
     ClusterJoinTicket, InitialRole, Peers, StableStorage,
 };
 use bincode::deserialize;
-use std::{net::ToSocketAddrs, ops::Deref, sync::Arc};
+use std::{
+    net::{SocketAddr, ToSocketAddrs},
+    ops::Deref,
+    sync::Arc,
+};
 use tokio::{
     signal,
     sync::{mpsc, oneshot},
+    task::AbortHandle,
 };
 
 use super::{
@@ -31,6 +41,10 @@ pub struct Raft<
     pub raft_server: RaftServer<LogEntry, LogStorage, FSM>,
     pub tx_server: mpsc::Sender<ServerRequestMsg<LogEntry, LogStorage, FSM>>,
     pub logger: Arc<dyn Logger>,
+    raft_addr: SocketAddr,
+    transport: Arc<dyn Transport>,
+    #[cfg(feature = "prometheus")]
+    metrics_addr: Option<SocketAddr>,
 }
 
 impl<
@@ -62,6 +76,29 @@ impl<
         fsm: FSM,
         config: Config,
         logger: Arc<dyn Logger>,
+    ) -> Result<Self> {
+        let transport = GrpcTransport::new(&config);
+        Self::bootstrap_with_transport(
+            node_id,
+            raft_addr,
+            log_storage,
+            fsm,
+            config,
+            logger,
+            transport,
+        )
+    }
+
+    /// Creates a new Raft instance that exchanges raft messages and forwarded requests
+    /// through the given transport instead of gRPC.
+    pub fn bootstrap_with_transport<A: ToSocketAddrs, T: Transport>(
+        node_id: u64,
+        raft_addr: A,
+        log_storage: LogStorage,
+        fsm: FSM,
+        config: Config,
+        logger: Arc<dyn Logger>,
+        transport: T,
     ) -> Result<Self> {
         logger.info(&format!("RaftNode bootstrapped. {:?}", config));
 
@@ -83,6 +120,7 @@ impl<
             should_be_leader = leaders.contains(&node_id);
         }
 
+        let transport: Arc<dyn Transport> = Arc::new(transport);
         let (tx_server, rx_server) = mpsc::channel(100);
         let raft_node = RaftNode::bootstrap(
             node_id,
@@ -92,31 +130,63 @@ impl<
             config.clone(),
             raft_addr,
             logger.clone(),
+            transport.clone(),
             tx_server.clone(),
             rx_server,
         )?;
 
-        let raft_server =
-            RaftServer::new(tx_server.clone(), raft_addr, config.clone(), logger.clone());
+        #[cfg(feature = "prometheus")]
+        let metrics_addr = config.metrics_addr;
+
+        let raft_server = RaftServer::new(
+            tx_server.clone(),
+            raft_addr,
+            config.clone(),
+            logger.clone(),
+            transport.clone(),
+        );
 
         Ok(Self {
             tx_server: tx_server.clone(),
             raft_node,
             raft_server,
             logger,
+            raft_addr,
+            transport,
+            #[cfg(feature = "prometheus")]
+            metrics_addr,
         })
     }
 
     /// Starts the RaftNode and RaftServer.
     pub async fn run(self) -> Result<()> {
+        // Binds before anything starts, so that an unusable metrics_addr fails the node.
+        #[cfg(feature = "prometheus")]
+        let metrics_server = self
+            .metrics_addr
+            .map(|addr| crate::metrics::bind_metrics(self.metrics(), addr))
+            .transpose()?;
+
         let (tx_quit_signal, rx_quit_signal) = oneshot::channel::<()>();
 
         let raft_node = self.raft_node.clone();
         let raft_node_handle = tokio::spawn(raft_node.run());
-        let raft_server = self.raft_server.clone();
-        let raft_server_handle = tokio::spawn(raft_server.run(rx_quit_signal));
+        let transport = self.transport.clone();
+        let raft_addr = self.raft_addr;
+        let inbound = self.inbound_messages();
+        let raft_server_handle =
+            tokio::spawn(async move { transport.serve(raft_addr, inbound, rx_quit_signal).await });
 
-        tokio::select! {
+        // Stops both tasks without a clean shutdown if this future is dropped, e.g. when its task is aborted.
+        let _abort_on_drop = AbortOnDrop(vec![
+            raft_node_handle.abort_handle(),
+            raft_server_handle.abort_handle(),
+        ]);
+
+        #[cfg(feature = "prometheus")]
+        let metrics_server_handle = metrics_server.map(tokio::spawn);
+
+        let result = tokio::select! {
             _ = signal::ctrl_c() => {
                 self.logger.info("Ctrl+C signal detected. Shutting down...");
                 Ok(())
@@ -163,7 +233,48 @@ impl<
                     }
                 }
             }
+        };
+
+        #[cfg(feature = "prometheus")]
+        if let Some(handle) = metrics_server_handle {
+            handle.abort();
         }
+
+        result
+    }
+
+    fn inbound_messages(&self) -> InboundMessages {
+        let tx_server = self.tx_server.clone();
+        let raft_server = self.raft_server.clone();
+        let grpc_server = self.raft_server.clone();
+
+        InboundMessages::new(
+            move |message| {
+                let tx_server = tx_server.clone();
+                Box::pin(async move {
+                    tx_server
+                        .send(ServerRequestMsg::SendMessage {
+                            message: Box::new(message),
+                        })
+                        .await
+                        .is_ok()
+                })
+            },
+            move |request| {
+                let raft_server = raft_server.clone();
+                Box::pin(async move {
+                    raft_server
+                        .handle_forwarded(request)
+                        .await
+                        .map_err(|status| SendMessageError::TransmissionError(status.to_string()))
+                })
+            },
+            move |inbound| {
+                RaftGrpcService::new(RaftServiceServer::new(
+                    grpc_server.clone().with_inbound(inbound),
+                ))
+            },
+        )
     }
 
     /// Requests a cluster join ticket from the peer.
@@ -203,3 +314,14 @@ impl<
         }
     }
 }
+
+/// Aborts the tasks when dropped.
+struct AbortOnDrop(Vec<AbortHandle>);
+
+impl Drop for AbortOnDrop {
+    fn drop(&mut self) {
+        for handle in self.0.iter() {
+            handle.abort();
+        }
+    }
+}
@@ -0,0 +1,282 @@
+use std::{path::PathBuf, sync::Arc};
+use tokio::{
+    fs::File,
+    io::{AsyncReadExt, AsyncWriteExt},
+    sync::{mpsc, oneshot},
+    time::Instant,
+};
+
+use super::session::{ProposalContext, SessionTable};
+use crate::{
+    metrics::RaftMetrics,
+    raft::eraftpb::{Entry, EntryType},
+    response::{
+        local_response_message::LocalResponseMsg, server_response_message::ProposeResponseResult,
+    },
+    AbstractLogEntry, AbstractStateMachine, Error, Result, StableStorage,
+};
+
+/// Request from the Raft loop to the apply task, handled in order.
+pub(crate) enum ApplyMsg<
+    LogEntry: AbstractLogEntry,
+    LogStorage: StableStorage + 'static,
+    FSM: AbstractStateMachine,
+> {
+    /// Committed entries, in log order.
+    Entries(Vec<Entry>),
+    /// Restores the state machine from a snapshot already applied to the storage.
+    Restore {
+        index: u64,
+        term: u64,
+        data: Option<SnapshotData>,
+    },
+    /// Snapshots the state machine at the last applied entry.
+    /// Streams it into `staging_path` when the storage provides one.
+    MakeSnapshot { staging_path: Option<PathBuf> },
+    GetStateMachine {
+        tx_msg: oneshot::Sender<LocalResponseMsg<LogEntry, LogStorage, FSM>>,
+    },
+}
+
+/// Snapshot to restore the state machine from.
+pub(crate) enum SnapshotData {
+    File(File),
+    Memory(Vec<u8>),
+}
+
+/// Progress reported by the apply task to the Raft loop, in the order it was made.
+pub(crate) enum ApplyEvent {
+    /// Entries up to `index` were applied, with the results of the proposals among them keyed by response_seq.
+    Applied {
+        index: u64,
+        results: Vec<(u64, ProposeResponseResult)>,
+    },
+    /// Reached a conf change entry, which the Raft loop applies.
+    /// Every entry before it has been applied.
+    ConfChange(Entry),
+    Restored {
+        index: u64,
+        term: u64,
+    },
+    SnapshotCreated {
+        index: u64,
+        term: u64,
+        /// None when the snapshot was streamed into the staging file.
+        data: Option<Vec<u8>>,
+        size: u64,
+        started: Instant,
+    },
+    /// The state machine failed, so the node stops.
+    Failed(Error),
+}
+
+pub(crate) type ApplyMsgSender<LogEntry, LogStorage, FSM> =
+    mpsc::UnboundedSender<ApplyMsg<LogEntry, LogStorage, FSM>>;
+
+/// Applies committed entries to the state machine off the Raft loop,
+/// so that a slow state machine doesn't hold back ticks and messages.
+/// Also owns the client sessions, which change along with the state machine.
+pub(crate) struct ApplyTask<
+    LogEntry: AbstractLogEntry,
+    LogStorage: StableStorage + 'static,
+    FSM: AbstractStateMachine,
+> {
+    fsm: FSM,
+    sessions: SessionTable,
+    applied_index: u64,
+    applied_term: u64,
+    max_client_sessions: usize,
+    metrics: Arc<RaftMetrics>,
+    rx_msg: mpsc::UnboundedReceiver<ApplyMsg<LogEntry, LogStorage, FSM>>,
+    tx_event: mpsc::UnboundedSender<ApplyEvent>,
+}
+
+impl<
+        LogEntry: AbstractLogEntry + 'static,
+        LogStorage: StableStorage + Send + 'static,
+        FSM: AbstractStateMachine + 'static,
+    > ApplyTask<LogEntry, LogStorage, FSM>
+{
+    pub fn new(
+        fsm: FSM,
+        sessions: SessionTable,
+        applied_index: u64,
+        applied_term: u64,
+        max_client_sessions: usize,
+        metrics: Arc<RaftMetrics>,
+        tx_event: mpsc::UnboundedSender<ApplyEvent>,
+    ) -> (Self, ApplyMsgSender<LogEntry, LogStorage, FSM>) {
+        // Unbounded, so that handing over committed entries never blocks the Raft loop.
+        let (tx_msg, rx_msg) = mpsc::unbounded_channel();
+        let task = Self {
+            fsm,
+            sessions,
+            applied_index,
+            applied_term,
+            max_client_sessions,
+            metrics,
+            rx_msg,
+            tx_event,
+        };
+        (task, tx_msg)
+    }
+
+    /// Runs until the Raft loop drops its sender, or the state machine fails.
+    pub async fn run(mut self) {
+        while let Some(msg) = self.rx_msg.recv().await {
+            if let Err(e) = self.handle_msg(msg).await {
+                self.send(ApplyEvent::Failed(e));
+                return;
+            }
+        }
+    }
+
+    fn send(&self, event: ApplyEvent) {
+        // Only fails once the Raft loop has stopped
+        let _ = self.tx_event.send(event);
+    }
+
+    async fn handle_msg(&mut self, msg: ApplyMsg<LogEntry, LogStorage, FSM>) -> Result<()> {
+        match msg {
+            ApplyMsg::Entries(entries) => self.apply_entries(entries).await,
+            ApplyMsg::Restore { index, term, data } => self.restore(index, term, data).await,
+            ApplyMsg::MakeSnapshot { staging_path } => self.make_snapshot(staging_path).await,
+            ApplyMsg::GetStateMachine { tx_msg } => {
+                // The caller may have stopped waiting already
+                let _ = tx_msg.send(LocalResponseMsg::GetStateMachine {
+                    store: self.fsm.clone(),
+                });
+                Ok(())
+            }
+        }
+    }
+
+    async fn apply_entries(&mut self, entries: Vec<Entry>) -> Result<()> {
+        let mut results = vec![];
+        for entry in entries {
+            match entry.get_entry_type() {
+                EntryType::EntryNormal => {
+                    if !entry.get_data().is_empty() {
+                        results.push(self.apply_normal_entry(&entry).await?);
+                    }
+                    self.applied_index = entry.index;
+                    self.applied_term = entry.term;
+                }
+                EntryType::EntryConfChange | EntryType::EntryConfChangeV2 => {
+                    self.send(ApplyEvent::Applied {
+                        index: self.applied_index,
+                        results: std::mem::take(&mut results),
+                    });
+                    self.applied_index = entry.index;
+                    self.applied_term = entry.term;
+                    self.send(ApplyEvent::ConfChange(entry));
+                }
+            }
+        }
+
+        self.send(ApplyEvent::Applied {
+            index: self.applied_index,
+            results,
+        });
+        Ok(())
+    }
+
+    async fn apply_normal_entry(&mut self, entry: &Entry) -> Result<(u64, ProposeResponseResult)> {
+        let ProposalContext {
+            response_seq,
+            session,
+        } = ProposalContext::decode(entry.get_context())?;
+
+        let apply_started = Instant::now();
+        let applied = self
+            .sessions
+            .apply::<LogEntry, _>(
+                &mut self.fsm,
+                session.as_ref(),
+                entry.index,
+                entry.get_data().to_vec(),
+                self.max_client_sessions,
+            )
+            .await?;
+        self.metrics
+            .apply_latency
+            .observe_duration(apply_started.elapsed());
+
+        let result = match applied {
+            Some(data) => ProposeResponseResult::Success { data },
+            None => ProposeResponseResult::Error(Error::Rejected(
+                "The request is older than the last applied request of its session".to_owned(),
+            )),
+        };
+
+        Ok((response_seq, result))
+    }
+
+    async fn restore(&mut self, index: u64, term: u64, data: Option<SnapshotData>) -> Result<()> {
+        self.applied_index = index;
+        self.applied_term = term;
+
+        let data = match data {
+            Some(data) => data,
+            None => {
+                self.send(ApplyEvent::Applied {
+                    index,
+                    results: vec![],
+                });
+                return Ok(());
+            }
+        };
+
+        let restore_started = Instant::now();
+        match data {
+            SnapshotData::File(mut file) => {
+                let (sessions, read_ahead) = SessionTable::read_snapshot_header(&mut file).await?;
+                self.sessions = sessions;
+                self.fsm.restore_from(&mut read_ahead.chain(file)).await?;
+            }
+            SnapshotData::Memory(data) => {
+                let (sessions, fsm_data) = SessionTable::split_snapshot(&data)?;
+                self.sessions = sessions;
+                self.fsm.restore(fsm_data.to_vec()).await?;
+            }
+        }
+        self.metrics
+            .snapshot_restore_duration
+            .observe_duration(restore_started.elapsed());
+
+        self.send(ApplyEvent::Restored { index, term });
+        Ok(())
+    }
+
+    async fn make_snapshot(&mut self, staging_path: Option<PathBuf>) -> Result<()> {
+        let started = Instant::now();
+        let (data, size) = match staging_path {
+            Some(staging_path) => {
+                // Stream the state machine into the storage's staging file instead of
+                // building the whole snapshot in memory.
+                let mut file = File::create(&staging_path).await?;
+                file.write_all(&self.sessions.snapshot_header()?).await?;
+                self.fsm.snapshot_to(&mut file).await?;
+                file.flush().await?;
+                file.sync_all().await?;
+                let size = file.metadata().await?.len();
+                (None, size)
+            }
+            None => {
+                let mut data = self.sessions.snapshot_header()?;
+                data.extend(self.fsm.snapshot().await?);
+                let size = data.len() as u64;
+                (Some(data), size)
+            }
+        };
+
+        self.send(ApplyEvent::SnapshotCreated {
+            index: self.applied_index,
+            term: self.applied_term,
+            data,
+            size,
+            started,
+        });
+        Ok(())
+    }
+}
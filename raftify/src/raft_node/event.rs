@@ -0,0 +1,47 @@
+use std::net::SocketAddr;
+
+use crate::{raft::StateRole, InitialRole};
+
+/// Capacity of the broadcast channel behind `RaftNode::subscribe`.
+/// Subscribers lagging further behind miss the oldest events.
+pub(crate) const EVENT_CHANNEL_CAPACITY: usize = 256;
+
+/// Events published by a RaftNode through `RaftNode::subscribe`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum RaftEvent {
+    /// The node became a leader, follower, candidate or pre-candidate.
+    RoleChanged {
+        role: StateRole,
+        term: u64,
+    },
+    /// The node learned about a new leader.
+    LeaderChanged {
+        leader_id: u64,
+        term: u64,
+    },
+    PeerAdded {
+        node_id: u64,
+        addr: SocketAddr,
+        role: InitialRole,
+    },
+    PeerRemoved {
+        node_id: u64,
+    },
+    SnapshotCreated {
+        index: u64,
+        term: u64,
+    },
+    /// The state machine was restored from a snapshot received from the leader.
+    SnapshotRestored {
+        index: u64,
+        term: u64,
+    },
+    /// The stored snapshot failed its integrity check. It isn't sent to followers anymore,
+    /// and a new snapshot is created to replace it.
+    SnapshotCorrupted {
+        index: u64,
+        term: u64,
+    },
+    /// The node is shutting down, either on request or because it was removed from the cluster.
+    Quitting,
+}
@@ -0,0 +1,63 @@
+use std::{collections::VecDeque, time::Duration};
+use tokio::time::Instant;
+
+use super::{
+    response_sender::{RequestKind, ResponseSender},
+    session::ClientRequest,
+};
+use crate::{
+    raft::eraftpb::ConfChangeV2, AbstractLogEntry, AbstractStateMachine, Error, StableStorage,
+};
+
+/// Request received while there was no leader, waiting for one to be elected.
+pub(crate) enum LeaderlessRequest<
+    LogEntry: AbstractLogEntry,
+    LogStorage: StableStorage + 'static,
+    FSM: AbstractStateMachine,
+> {
+    Propose {
+        proposal: Vec<u8>,
+        timeout: Option<Duration>,
+        session: Option<ClientRequest>,
+        response_sender: ResponseSender<LogEntry, LogStorage, FSM>,
+    },
+    ChangeConfig {
+        conf_change: ConfChangeV2,
+        response_sender: ResponseSender<LogEntry, LogStorage, FSM>,
+    },
+    ReadIndex {
+        lease_based: bool,
+        response_sender: ResponseSender<LogEntry, LogStorage, FSM>,
+    },
+    PromoteLearner {
+        node_id: u64,
+        response_sender: ResponseSender<LogEntry, LogStorage, FSM>,
+    },
+}
+
+/// Leaderless requests in arrival order, with the deadline of each.
+pub(crate) type LeaderlessQueue<LogEntry, LogStorage, FSM> =
+    VecDeque<(Instant, LeaderlessRequest<LogEntry, LogStorage, FSM>)>;
+
+impl<LogEntry: AbstractLogEntry, LogStorage: StableStorage, FSM: AbstractStateMachine>
+    LeaderlessRequest<LogEntry, LogStorage, FSM>
+{
+    /// Answers the request with `Error::NoLeader`.
+    pub fn reject(self) {
+        let (kind, response_sender) = match self {
+            LeaderlessRequest::Propose {
+                response_sender, ..
+            } => (RequestKind::Propose, response_sender),
+            LeaderlessRequest::ReadIndex {
+                response_sender, ..
+            } => (RequestKind::ReadIndex, response_sender),
+            LeaderlessRequest::ChangeConfig {
+                response_sender, ..
+            }
+            | LeaderlessRequest::PromoteLearner {
+                response_sender, ..
+            } => (RequestKind::ChangeConfig, response_sender),
+        };
+        response_sender.send_error(kind, Error::NoLeader);
+    }
+}
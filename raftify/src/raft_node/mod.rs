@@ -1,12 +1,19 @@
+mod apply_task;
 mod bootstrap;
+pub mod event;
+mod leaderless_request;
+mod peer_stream;
 mod response_sender;
 pub mod role;
+pub mod session;
 pub mod utils;
 
 use bincode::{deserialize, serialize};
 use prost::Message as PMessage;
 use std::{
-    collections::HashMap,
+    cmp::Reverse,
+    collections::{BinaryHeap, HashMap},
+    fs,
     marker::PhantomData,
     net::{SocketAddr, ToSocketAddrs},
     str::FromStr,
@@ -14,20 +21,24 @@ use std::{
         atomic::{AtomicU64, Ordering},
         Arc,
     },
-    time::{Duration, Instant},
+    time::Duration,
 };
 use tokio::{
-    sync::{mpsc, oneshot, Mutex},
-    time::timeout,
+    sync::{broadcast, mpsc, oneshot, Mutex},
+    time::{sleep_until, timeout, Instant},
 };
-use tonic::Request;
 
-use response_sender::ResponseSender;
+use apply_task::{ApplyEvent, ApplyMsg, ApplyMsgSender, ApplyTask, SnapshotData};
+use event::{RaftEvent, EVENT_CHANNEL_CAPACITY};
+use leaderless_request::{LeaderlessQueue, LeaderlessRequest};
+use peer_stream::PeerStreams;
+use response_sender::{RequestKind, ResponseSender};
+use session::{ClientRequest, ProposalContext, SessionTable};
 use utils::inspect_raftnode;
 
 use crate::{
-    create_client,
-    error::{Result, SendMessageError},
+    error::Result,
+    metrics::RaftMetrics,
     raft::{
         eraftpb::{
             ConfChange, ConfChangeSingle, ConfChangeTransition, ConfChangeType, ConfChangeV2,
@@ -36,6 +47,7 @@ use crate::{
         formatter::{format_confchangev2, format_message},
         logger::Logger,
         raw_node::RawNode,
+        GetEntriesContext, ProgressState, ReadOnlyOption, ReadState, SnapshotStatus, StateRole,
     },
     raft_service::{self, ChangeConfigResultType, ProposeArgs},
     request::{
@@ -45,15 +57,23 @@ use crate::{
     response::{
         local_response_message::LocalResponseMsg,
         server_response_message::{
-            ConfChangeResponseResult, RequestIdResponseResult, ResponseResult, ServerResponseMsg,
+            ConfChangeResponseResult, ProposeResponseResult, ReadIndexResponseResult,
+            RequestIdResponseResult, ServerResponseMsg,
         },
         ResponseMessage,
     },
-    utils::{membership::to_confchange_v2, oneshot_mutex::OneShotMutex},
-    AbstractLogEntry, AbstractStateMachine, ClusterJoinTicket, Config, Error, InitialRole, Peers,
-    StableStorage,
+    transport::{parse_addr, ForwardedRequest, ForwardedResponse, Transport},
+    utils::{
+        membership::{to_conf_change_result, to_confchange_v2},
+        oneshot_mutex::OneShotMutex,
+    },
+    AbstractLogEntry, AbstractOutput, AbstractStateMachine, ClusterJoinTicket, Config, Error,
+    InitialRole, Peers, StableStorage,
 };
 
+/// Number of entries read at once when measuring the size of the log.
+const LOG_SCAN_BATCH: u64 = 1024;
+
 #[derive(Clone)]
 pub struct RaftNode<
     LogEntry: AbstractLogEntry + Send + 'static,
@@ -66,6 +86,9 @@ pub struct RaftNode<
     // RaftNode.(method_call) >>> RaftNodeCore.run
     tx_local: mpsc::Sender<LocalRequestMsg<LogEntry, LogStorage, FSM>>,
     config: Config,
+    metrics: Arc<RaftMetrics>,
+    tx_events: broadcast::Sender<RaftEvent>,
+    transport: Arc<dyn Transport>,
 }
 
 impl<
@@ -83,6 +106,7 @@ impl<
         config: Config,
         raft_addr: SocketAddr,
         logger: Arc<dyn Logger>,
+        transport: Arc<dyn Transport>,
         tx_server: mpsc::Sender<ServerRequestMsg<LogEntry, LogStorage, FSM>>,
         rx_server: mpsc::Receiver<ServerRequestMsg<LogEntry, LogStorage, FSM>>,
     ) -> Result<Self> {
@@ -96,18 +120,44 @@ impl<
             config.clone(),
             raft_addr,
             logger,
+            transport.clone(),
             tx_server,
             rx_server,
             tx_local.clone(),
             rx_local,
         )
         .map(|core| Self {
+            metrics: core.metrics.clone(),
+            tx_events: core.tx_events.clone(),
             inner: Arc::new(OneShotMutex::new(core)),
             tx_local: tx_local.clone(),
             config,
+            transport,
         })
     }
 
+    /// Forwards a request this node can't serve to the leader through the transport.
+    async fn forward(
+        &self,
+        leader_addr: &str,
+        request: ForwardedRequest,
+    ) -> Result<ForwardedResponse> {
+        Ok(self
+            .transport
+            .forward(parse_addr(leader_addr)?, request)
+            .await?)
+    }
+
+    pub fn metrics(&self) -> Arc<RaftMetrics> {
+        self.metrics.clone()
+    }
+
+    /// Subscribes to role, leadership, membership and snapshot events of this node.
+    /// Only events published after subscribing are received.
+    pub fn subscribe(&self) -> broadcast::Receiver<RaftEvent> {
+        self.tx_events.subscribe()
+    }
+
     pub async fn is_leader(&self) -> Result<bool> {
         let (tx, rx) = oneshot::channel();
         self.tx_local
@@ -245,11 +295,50 @@ impl<
         }
     }
 
-    pub async fn propose(&self, proposal: Vec<u8>) -> Result<()> {
+    /// Proposes the given data and waits until it is committed and applied.
+    /// Returns the output of `AbstractStateMachine::apply` for the proposed entry.
+    pub async fn propose(&self, proposal: Vec<u8>) -> Result<Vec<u8>> {
+        self.request_propose(proposal, None, None).await
+    }
+
+    /// Same as `propose`, but retrying it with the same `request` applies the proposal at most once.
+    /// Retries of an already applied request resolve with the result cached in the session.
+    pub async fn propose_in_session(
+        &self,
+        request: ClientRequest,
+        proposal: Vec<u8>,
+    ) -> Result<Vec<u8>> {
+        self.request_propose(proposal, None, Some(request)).await
+    }
+
+    /// Typed version of `propose`, which encodes the entry and decodes the output of `apply`.
+    pub async fn propose_entry(&self, log_entry: &LogEntry) -> Result<FSM::Output> {
+        let output = self.propose(log_entry.encode()?).await?;
+        FSM::Output::decode(&output)
+    }
+
+    /// Same as `propose`, but fails with `Error::Timeout` if the proposal isn't applied within
+    /// `timeout` instead of `Config::proposal_timeout`.
+    pub async fn propose_with_timeout(
+        &self,
+        proposal: Vec<u8>,
+        timeout: Duration,
+    ) -> Result<Vec<u8>> {
+        self.request_propose(proposal, Some(timeout), None).await
+    }
+
+    async fn request_propose(
+        &self,
+        proposal: Vec<u8>,
+        timeout: Option<Duration>,
+        session: Option<ClientRequest>,
+    ) -> Result<Vec<u8>> {
         let (tx, rx) = oneshot::channel();
         self.tx_local
             .send(LocalRequestMsg::Propose {
                 proposal: proposal.clone(),
+                timeout,
+                session,
                 tx_msg: tx,
             })
             .await
@@ -258,19 +347,30 @@ impl<
         let resp = rx.await?;
         match resp {
             LocalResponseMsg::Propose { result } => match result {
-                ResponseResult::Success => (),
-                ResponseResult::Error(e) => return Err(e),
-                ResponseResult::WrongLeader { leader_addr, .. } => {
-                    let mut client =
-                        create_client(leader_addr, self.config.client_tls_config.clone()).await?;
-                    client
-                        .propose(Request::new(ProposeArgs { msg: proposal }))
-                        .await?;
+                ProposeResponseResult::Success { data } => Ok(data),
+                ProposeResponseResult::Error(e) => Err(e),
+                ProposeResponseResult::WrongLeader { leader_addr, .. } => {
+                    let request = ForwardedRequest::Propose(ProposeArgs {
+                        msg: proposal,
+                        timeout_ms: timeout.map_or(0, |timeout| timeout.as_millis() as u64),
+                        session: session.map(Into::into),
+                    });
+                    let response = match self.forward(&leader_addr, request).await? {
+                        ForwardedResponse::Propose(response) => response,
+                        _ => unreachable!(),
+                    };
+
+                    if !response.error.is_empty() {
+                        return Err(Error::from_request_error(
+                            response.error_type(),
+                            &response.error,
+                        ));
+                    }
+                    Ok(response.data)
                 }
             },
             _ => unreachable!(),
         }
-        Ok(())
     }
 
     pub async fn change_config(
@@ -291,26 +391,46 @@ impl<
         match resp {
             LocalResponseMsg::ConfigChange { result } => match result {
                 ConfChangeResponseResult::WrongLeader { leader_addr, .. } => {
-                    let mut client =
-                        create_client(leader_addr, self.config.client_tls_config.clone())
-                            .await
-                            .unwrap();
-
-                    let conf_change: ConfChangeRequest = conf_change;
-                    let conf_change: raft_service::ChangeConfigArgs = conf_change.into();
-                    let res = client.change_config(conf_change).await.unwrap();
+                    let request = ForwardedRequest::ChangeConfig(conf_change.into());
+                    match self.forward(&leader_addr, request).await? {
+                        ForwardedResponse::ChangeConfig(response) => {
+                            to_conf_change_result(response)
+                        }
+                        _ => unreachable!(),
+                    }
+                }
+                _ => Ok(result),
+            },
+            _ => unreachable!(),
+        }
+    }
 
-                    let result = res.into_inner();
+    /// Promotes the learner to a voter once it has caught up with the leader's log,
+    /// that is, once it lags at most `learner_catch_up_threshold` entries behind.
+    /// Resolves after the `AddNode` conf change is committed.
+    pub async fn promote_learner(&self, node_id: u64) -> Result<ConfChangeResponseResult> {
+        let (tx, rx) = oneshot::channel();
+        self.tx_local
+            .send(LocalRequestMsg::PromoteLearner {
+                node_id,
+                tx_msg: tx,
+            })
+            .await
+            .unwrap();
 
-                    if result.result_type
-                        == raft_service::ChangeConfigResultType::ChangeConfigSuccess as i32
-                    {
-                        Ok(ConfChangeResponseResult::JoinSuccess {
-                            assigned_ids: result.assigned_ids,
-                            peers: deserialize(result.peers.as_slice()).unwrap(),
-                        })
-                    } else {
-                        Ok(ConfChangeResponseResult::Error(Error::Unknown))
+        let resp = rx.await?;
+        match resp {
+            LocalResponseMsg::ConfigChange { result } => match result {
+                ConfChangeResponseResult::WrongLeader { leader_addr, .. } => {
+                    let request =
+                        ForwardedRequest::PromoteLearner(raft_service::PromoteLearnerArgs {
+                            node_id,
+                        });
+                    match self.forward(&leader_addr, request).await? {
+                        ForwardedResponse::ChangeConfig(response) => {
+                            to_conf_change_result(response)
+                        }
+                        _ => unreachable!(),
                     }
                 }
                 _ => Ok(result),
@@ -319,6 +439,85 @@ impl<
         }
     }
 
+    /// Confirms the current commit index with the leader (ReadIndex) and
+    /// waits until the local state machine has applied up to it.
+    /// Returns the confirmed read index.
+    pub async fn read_index(&self) -> Result<u64> {
+        self.request_read_index(false).await
+    }
+
+    /// Same as `read_index`, but the leader answers from its lease instead of
+    /// confirming its leadership with a heartbeat round.
+    /// Rejected when `check_quorum` is disabled, because the lease is not safe then.
+    pub async fn lease_read(&self) -> Result<u64> {
+        self.request_read_index(true).await
+    }
+
+    async fn request_read_index(&self, lease_based: bool) -> Result<u64> {
+        let (tx, rx) = oneshot::channel();
+        self.tx_local
+            .send(LocalRequestMsg::ReadIndex {
+                lease_based,
+                tx_msg: tx,
+            })
+            .await
+            .unwrap();
+
+        let resp = rx.await?;
+        match resp {
+            LocalResponseMsg::ReadIndex { result } => match result {
+                ReadIndexResponseResult::Success { index } => Ok(index),
+                ReadIndexResponseResult::Error(e) => Err(e),
+                ReadIndexResponseResult::WrongLeader { leader_addr, .. } => {
+                    let request =
+                        ForwardedRequest::ReadIndex(raft_service::ReadIndexArgs { lease_based });
+                    let response = match self.forward(&leader_addr, request).await? {
+                        ForwardedResponse::ReadIndex(response) => response,
+                        _ => unreachable!(),
+                    };
+
+                    if !response.error.is_empty() {
+                        return Err(Error::from_request_error(
+                            response.error_type(),
+                            &response.error,
+                        ));
+                    }
+                    self.wait_for_applied(response.index).await
+                }
+            },
+            _ => unreachable!(),
+        }
+    }
+
+    /// Runs `f` against the state machine after a successful `read_index`,
+    /// so the observed state reflects every write committed before the call.
+    pub async fn linearizable_read<F, T>(&self, f: F) -> Result<T>
+    where
+        F: FnOnce(&FSM) -> T,
+    {
+        self.read_index().await?;
+        let fsm = self.state_machine().await?;
+        Ok(f(&fsm))
+    }
+
+    async fn wait_for_applied(&self, index: u64) -> Result<u64> {
+        let (tx, rx) = oneshot::channel();
+        self.tx_local
+            .send(LocalRequestMsg::WaitForApplied { index, tx_msg: tx })
+            .await
+            .unwrap();
+
+        let resp = rx.await?;
+        match resp {
+            LocalResponseMsg::ReadIndex { result } => match result {
+                ReadIndexResponseResult::Success { index } => Ok(index),
+                ReadIndexResponseResult::Error(e) => Err(e),
+                ReadIndexResponseResult::WrongLeader { .. } => unreachable!(),
+            },
+            _ => unreachable!(),
+        }
+    }
+
     pub async fn get_cluster_size(&self) -> Result<usize> {
         let (tx, rx) = oneshot::channel();
         self.tx_local
@@ -428,14 +627,12 @@ impl<
         }
     }
 
-    pub async fn make_snapshot(&self, index: u64, term: u64) -> Result<()> {
+    /// Snapshots the state machine at its last applied entry and compacts the log
+    /// according to the compaction settings.
+    pub async fn create_snapshot(&self) -> Result<()> {
         let (tx, rx) = oneshot::channel();
         self.tx_local
-            .send(LocalRequestMsg::MakeSnapshot {
-                index,
-                term,
-                tx_msg: tx,
-            })
+            .send(LocalRequestMsg::MakeSnapshot { tx_msg: tx })
             .await
             .unwrap();
         let resp = rx.await?;
@@ -445,6 +642,12 @@ impl<
         }
     }
 
+    /// The snapshot has to match the applied state, so `index` and `term` are ignored.
+    #[deprecated(note = "use `create_snapshot`, the snapshot always covers the applied entries")]
+    pub async fn make_snapshot(&self, _index: u64, _term: u64) -> Result<()> {
+        self.create_snapshot().await
+    }
+
     pub async fn join_cluster(&self, tickets: Vec<ClusterJoinTicket>) -> Result<()> {
         let (tx, rx) = oneshot::channel();
         self.tx_local
@@ -494,21 +697,48 @@ pub struct RaftNodeCore<
 > {
     pub raw_node: RawNode<LogStorage>,
     // pub log_storage: LogStorage,  # Since there is no particular reason to store it, we do not save the log_storage.
-    pub fsm: FSM,
     pub peers: Arc<Mutex<Peers>>,
+    peer_streams: PeerStreams,
+    transport: Arc<dyn Transport>,
     response_seq: AtomicU64,
     raft_addr: SocketAddr,
     config: Config,
     should_exit: bool,
     last_snapshot_created: Instant,
+    // Index of the latest snapshot, and bytes of the entries in the log.
+    last_snapshot_index: u64,
+    log_size: u64,
     logger: Arc<dyn Logger>,
-    response_senders: HashMap<u64, ResponseSender<LogEntry, LogStorage, FSM>>,
+    response_senders: HashMap<u64, (RequestKind, ResponseSender<LogEntry, LogStorage, FSM>)>,
+    // Read requests waiting for the state machine to apply up to their read index.
+    pending_reads: Vec<(u64, ResponseSender<LogEntry, LogStorage, FSM>)>,
+    // Learners waiting to catch up with the leader's log before being promoted.
+    pending_promotions: Vec<(u64, ResponseSender<LogEntry, LogStorage, FSM>)>,
+    // Committed joint configuration change waiting for the joint configuration to be left.
+    pending_joint_response: Option<(u64, Vec<ConfChangeSingle>)>,
+    // Requests received while there was no leader, retried once one is known.
+    leaderless_requests: LeaderlessQueue<LogEntry, LogStorage, FSM>,
+    // When each pending proposal was received, keyed by response_seq.
+    proposal_starts: HashMap<u64, Instant>,
+    // Deadlines of the requests in response_senders, soonest first. Answered ones are skipped lazily.
+    response_deadlines: BinaryHeap<Reverse<(Instant, u64)>>,
+    // Spawned when the node starts running, then owns the state machine.
+    apply_task: Option<ApplyTask<LogEntry, LogStorage, FSM>>,
+    tx_apply: ApplyMsgSender<LogEntry, LogStorage, FSM>,
+    rx_apply: mpsc::UnboundedReceiver<ApplyEvent>,
+    // Callers waiting for the snapshot being created, None when no snapshot is being created.
+    snapshot_waiters: Option<Vec<ResponseSender<LogEntry, LogStorage, FSM>>>,
+    // Callers of snapshots requested while another one was being created.
+    queued_snapshot_waiters: Option<Vec<ResponseSender<LogEntry, LogStorage, FSM>>>,
+    metrics: Arc<RaftMetrics>,
+    tx_events: broadcast::Sender<RaftEvent>,
+    // Role and leader last published through tx_events
+    last_role: StateRole,
+    last_leader_id: u64,
 
-    #[allow(dead_code)]
     tx_server: mpsc::Sender<ServerRequestMsg<LogEntry, LogStorage, FSM>>,
     rx_server: mpsc::Receiver<ServerRequestMsg<LogEntry, LogStorage, FSM>>,
 
-    #[allow(dead_code)]
     tx_local: mpsc::Sender<LocalRequestMsg<LogEntry, LogStorage, FSM>>,
     rx_local: mpsc::Receiver<LocalRequestMsg<LogEntry, LogStorage, FSM>>,
 
@@ -533,6 +763,7 @@ impl<
         config: Config,
         raft_addr: SocketAddr,
         logger: Arc<dyn Logger>,
+        transport: Arc<dyn Transport>,
         tx_server: mpsc::Sender<ServerRequestMsg<LogEntry, LogStorage, FSM>>,
         rx_server: mpsc::Receiver<ServerRequestMsg<LogEntry, LogStorage, FSM>>,
         tx_local: mpsc::Sender<LocalRequestMsg<LogEntry, LogStorage, FSM>>,
@@ -578,10 +809,10 @@ impl<
 
         if last_idx == 0 {
             logger.info("Bootstrapping cluster init...");
-            log_storage.apply_snapshot(snapshot)?;
+            log_storage.apply_snapshot(&snapshot)?;
         } else if config.bootstrap_from_snapshot {
             logger.info("Bootstrapping from snapshot...");
-            log_storage.apply_snapshot(snapshot)?;
+            log_storage.apply_snapshot(&snapshot)?;
         } else {
             logger.info("Bootstrapping from existing logs...");
         }
@@ -597,17 +828,60 @@ impl<
             raw_node.raft.become_leader();
         }
 
-        Ok(RaftNodeCore {
-            raw_node,
+        let metrics = Arc::new(RaftMetrics::new(node_id));
+        let last_snapshot_index = raw_node.raft.raft_log.first_index() - 1;
+        let applied = raw_node.raft.raft_log.applied;
+        let (tx_apply_event, rx_apply) = mpsc::unbounded_channel();
+        // The state machine starts from the stored snapshot, and so do the client sessions.
+        let sessions = SessionTable::from_storage(&log_storage)?;
+        let (apply_task, tx_apply) = ApplyTask::new(
             fsm,
+            sessions,
+            applied,
+            raw_node.raft.raft_log.term(applied)?,
+            config.max_client_sessions as usize,
+            metrics.clone(),
+            tx_apply_event,
+        );
+
+        let peers = Arc::new(Mutex::new(peers));
+        let peer_streams = PeerStreams::new(
+            peers.clone(),
+            transport.clone(),
+            tx_self.clone(),
+            &config,
+            logger.clone(),
+        );
+
+        let mut core = RaftNodeCore {
+            raw_node,
             response_seq,
             config,
             raft_addr,
             logger,
             last_snapshot_created,
+            last_snapshot_index,
+            log_size: 0,
             should_exit: false,
-            peers: Arc::new(Mutex::new(peers)),
+            peers,
+            peer_streams,
+            transport,
             response_senders: HashMap::new(),
+            pending_reads: Vec::new(),
+            pending_promotions: Vec::new(),
+            pending_joint_response: None,
+            leaderless_requests: LeaderlessQueue::new(),
+            proposal_starts: HashMap::new(),
+            response_deadlines: BinaryHeap::new(),
+            apply_task: Some(apply_task),
+            tx_apply,
+            rx_apply,
+            snapshot_waiters: None,
+            queued_snapshot_waiters: None,
+            metrics,
+            tx_events: broadcast::channel(EVENT_CHANNEL_CAPACITY).0,
+            last_role: StateRole::Follower,
+            last_leader_id: 0,
             tx_server,
             rx_server,
             tx_local,
@@ -615,7 +889,9 @@ impl<
             tx_self,
             rx_self,
             _phantom_log_entry_typ: PhantomData,
-        })
+        };
+        core.measure_log_size()?;
+        Ok(core)
     }
 
     pub fn is_leader(&self) -> bool {
@@ -652,94 +928,267 @@ impl<
         Ok(())
     }
 
-    pub async fn make_snapshot(&mut self, index: u64, term: u64) -> Result<()> {
+    /// Asks the apply task to snapshot the state machine at its last applied entry.
+    /// Only one snapshot is created at a time, later requests wait for the current one.
+    fn request_snapshot(
+        &mut self,
+        waiter: Option<ResponseSender<LogEntry, LogStorage, FSM>>,
+    ) -> Result<()> {
+        if self.snapshot_waiters.is_some() {
+            self.queued_snapshot_waiters
+                .get_or_insert_with(Vec::new)
+                .extend(waiter);
+            return Ok(());
+        }
+
         self.last_snapshot_created = Instant::now();
-        let snapshot_data = self.fsm.snapshot().await?;
+        self.snapshot_waiters = Some(waiter.into_iter().collect());
+        self.send_to_apply_task(ApplyMsg::MakeSnapshot {
+            staging_path: self.raw_node.store().snapshot_staging_path(),
+        })
+    }
 
-        let last_applied = self.raw_node.raft.raft_log.applied;
-        let store = self.raw_node.mut_store();
-        store.compact(last_applied)?;
-        store.create_snapshot(snapshot_data, index, term)?;
+    fn handle_snapshot_created(
+        &mut self,
+        index: u64,
+        term: u64,
+        data: Option<Vec<u8>>,
+        size: u64,
+        started: Instant,
+    ) -> Result<()> {
+        // A snapshot received from the leader meanwhile may already cover it.
+        if index + 1 >= self.raw_node.store().first_index()? {
+            let compact_index = self.compaction_index(index)?;
+            let store = self.raw_node.mut_store();
+            store.compact(compact_index)?;
+            match data {
+                Some(data) => store.create_snapshot(data, index, term)?,
+                None => {
+                    let staging_path = store.snapshot_staging_path().unwrap();
+                    store.create_snapshot_from_file(&staging_path, index, term)?;
+                }
+            }
+
+            self.last_snapshot_index = index;
+            self.measure_log_size()?;
+            self.metrics.snapshot_size.observe(size as f64);
+            self.metrics
+                .snapshot_duration
+                .observe_duration(started.elapsed());
+            self.publish(RaftEvent::SnapshotCreated { index, term });
+        } else if data.is_none() {
+            // Superseded, so the streamed snapshot is discarded.
+            let staging_path = self.raw_node.store().snapshot_staging_path().unwrap();
+            if let Err(e) = fs::remove_file(&staging_path) {
+                self.logger.warn(&format!(
+                    "Failed to remove the staging file {}: {}",
+                    staging_path.display(),
+                    e
+                ));
+            }
+        }
+
+        for waiter in self.snapshot_waiters.take().unwrap_or_default() {
+            match waiter {
+                ResponseSender::Local(tx_local) => {
+                    let _ = tx_local.send(LocalResponseMsg::MakeSnapshot {});
+                }
+                ResponseSender::Server(tx_server) => {
+                    let _ = tx_server.send(ServerResponseMsg::CreateSnapshot {});
+                }
+            }
+        }
+
+        if let Some(waiters) = self.queued_snapshot_waiters.take() {
+            self.request_snapshot(None)?;
+            self.snapshot_waiters = Some(waiters);
+        }
         Ok(())
     }
 
-    pub async fn inspect(&self) -> Result<String> {
-        inspect_raftnode(&self.raw_node)
+    /// Picks the index to compact the log up to when storing a snapshot at `snapshot_index`.
+    /// Keeps `compaction_retained_entries` entries before the snapshot and, on the leader, the entries
+    /// followers still need to catch up without a snapshot transfer, as long as the entries left
+    /// in the log fit in `compaction_max_retained_bytes`.
+    fn compaction_index(&self, snapshot_index: u64) -> Result<u64> {
+        let store = self.raw_node.store();
+        let first_index = store.first_index()?;
+        let mut index = snapshot_index.saturating_sub(self.config.compaction_retained_entries);
+
+        if self.is_leader() {
+            let id = self.get_id();
+            for (node_id, pr) in self.raw_node.raft.prs().iter() {
+                // Followers that fell behind the log already need a snapshot
+                if *node_id != id
+                    && pr.state != ProgressState::Snapshot
+                    && pr.matched >= first_index
+                {
+                    index = index.min(pr.matched);
+                }
+            }
+        }
+
+        let index = index.max(first_index);
+        if index >= snapshot_index {
+            return Ok(snapshot_index);
+        }
+
+        // Walk back from the end of the log, so that only the retained entries and
+        // one batch more are read, instead of the whole log.
+        let mut retained_bytes = 0;
+        let mut high = store.last_index()? + 1;
+        while high > index {
+            let low = high.saturating_sub(LOG_SCAN_BATCH).max(index);
+            let entries = store.entries(low, high, None, GetEntriesContext::empty(false))?;
+            for entry in entries.iter().rev() {
+                retained_bytes += entry.encoded_len() as u64;
+                if retained_bytes > self.config.compaction_max_retained_bytes {
+                    return Ok((entry.index + 1).min(snapshot_index));
+                }
+            }
+            high = low;
+        }
+        Ok(index)
     }
 
-    async fn send_message(
-        message: RaftMessage,
-        peers: Arc<Mutex<Peers>>,
-        tx_self: mpsc::Sender<SelfMessage>,
-        logger: Arc<dyn Logger>,
-    ) {
-        let node_id = message.get_to();
-        let mut ok = std::result::Result::<(), SendMessageError>::Ok(());
-
-        let client = match peers.lock().await.get_mut(&node_id) {
-            Some(peer) => {
-                if peer.client.is_none() {
-                    if let Err(e) = peer.connect().await {
-                        ok = Err(SendMessageError::ConnectionError(format!(
-                            "(to node {}). Error: {:?}",
-                            node_id,
-                            e.to_string()
-                        )));
+    /// Measures the bytes of the entries in the log, when `snapshot_log_size_threshold` needs them.
+    /// Appends keep the measure up to date in between.
+    fn measure_log_size(&mut self) -> Result<()> {
+        if self.config.snapshot_log_size_threshold.is_some() {
+            let store = self.raw_node.store();
+            self.log_size = entries_size(store, store.first_index()?, store.last_index()? + 1)?;
+        }
+        Ok(())
+    }
+
+    fn send_to_apply_task(&self, msg: ApplyMsg<LogEntry, LogStorage, FSM>) -> Result<()> {
+        self.tx_apply
+            .send(msg)
+            .map_err(|_| Error::Other("The apply task has stopped".into()))
+    }
+
+    async fn handle_apply_event(&mut self, event: ApplyEvent) -> Result<()> {
+        match event {
+            ApplyEvent::Applied { index, results } => {
+                for (response_seq, result) in results {
+                    if let Some(proposed) = self.proposal_starts.remove(&response_seq) {
+                        self.metrics
+                            .proposal_latency
+                            .observe_duration(proposed.elapsed());
+                    }
+                    if let Some((_, sender)) = self.response_senders.remove(&response_seq) {
+                        match sender {
+                            ResponseSender::Local(tx_local) => {
+                                let _ = tx_local.send(LocalResponseMsg::Propose { result });
+                            }
+                            ResponseSender::Server(tx_server) => {
+                                let _ = tx_server.send(ServerResponseMsg::Propose { result });
+                            }
+                        }
                     }
                 }
-                peer.client.clone()
+                self.advance_apply_to(index);
             }
-            None => {
-                ok = Err(SendMessageError::PeerNotFound(node_id.to_string()));
-                None
+            ApplyEvent::ConfChange(entry) => {
+                self.handle_committed_config_change_entry(&entry).await?;
+                self.advance_apply_to(entry.index);
             }
-        };
-
-        if let Some(mut client) = client {
-            let message = Request::new(message.clone());
-            if let Err(e) = client.send_message(message).await {
-                ok = Err(SendMessageError::TransmissionError(format!(
-                    "(to node {}). Error: {}",
-                    node_id, e
-                )));
+            ApplyEvent::Restored { index, term } => {
+                self.last_snapshot_index = index;
+                self.advance_apply_to(index);
+                self.publish(RaftEvent::SnapshotRestored { index, term });
+            }
+            ApplyEvent::SnapshotCreated {
+                index,
+                term,
+                data,
+                size,
+                started,
+            } => {
+                self.handle_snapshot_created(index, term, data, size, started)?;
             }
+            ApplyEvent::Failed(e) => return Err(e),
+        }
+        Ok(())
+    }
+
+    /// Snapshots once any of the configured snapshot policies is met.
+    /// Checked on every iteration of the loop, so that a node gone idle still snapshots what it applied.
+    fn check_snapshot_policies(&mut self) -> Result<()> {
+        let applied = self.raw_node.raft.raft_log.applied;
+        if self.snapshot_waiters.is_some() || applied <= self.last_snapshot_index {
+            return Ok(());
         }
 
-        if let Err(e) = ok {
-            logger.debug(&format!("Error occurred while sending message: {}", e));
-            let _ = tx_self
-                .send(SelfMessage::ReportUnreachable { node_id })
-                .await;
+        let since_last_snapshot = self.last_snapshot_created.elapsed();
+        let is_interval_due = self
+            .config
+            .snapshot_interval
+            .is_some_and(|interval| since_last_snapshot > Duration::from_secs_f32(interval));
+        let is_entries_due = self
+            .config
+            .snapshot_entries_threshold
+            .is_some_and(|threshold| applied - self.last_snapshot_index >= threshold);
+        let is_log_size_due = self
+            .config
+            .snapshot_log_size_threshold
+            .is_some_and(|threshold| self.log_size >= threshold);
+
+        let is_due = is_interval_due || is_entries_due || is_log_size_due;
+        if is_due {
+            self.request_snapshot(None)?;
         }
+        Ok(())
     }
 
-    async fn send_messages(&mut self, messages: Vec<RaftMessage>) {
-        for message in messages {
-            tokio::spawn(RaftNodeCore::<LogEntry, LogStorage, FSM>::send_message(
-                message,
-                self.peers.clone(),
-                self.tx_self.clone(),
-                self.logger.clone(),
-            ));
+    /// Reports a snapshot the storage found corrupted and snapshots again to replace it,
+    /// as followers lagging behind the log can't catch up without one.
+    fn replace_corrupted_snapshot(&mut self) -> Result<()> {
+        if let Some((index, term)) = self.raw_node.store().take_corrupted_snapshot() {
+            self.metrics.snapshot_corruptions.inc();
+            self.publish(RaftEvent::SnapshotCorrupted { index, term });
+            self.request_snapshot(None)?;
         }
+        Ok(())
     }
 
-    async fn handle_committed_entries(&mut self, committed_entries: Vec<Entry>) -> Result<()> {
-        for entry in committed_entries {
-            match entry.get_entry_type() {
-                EntryType::EntryNormal => {
-                    if entry.get_data().is_empty() {
-                        continue;
-                    }
+    /// Lets raft-rs know how far the state machine has applied, then answers the reads waiting for it.
+    fn advance_apply_to(&mut self, index: u64) {
+        if index > self.raw_node.raft.raft_log.applied {
+            self.raw_node.advance_apply_to(index);
+        }
+        self.handle_pending_reads();
+    }
 
-                    self.handle_committed_normal_entry(&entry).await?;
-                }
-                EntryType::EntryConfChange | EntryType::EntryConfChangeV2 => {
-                    self.handle_committed_config_change_entry(&entry).await?;
+    pub async fn inspect(&self) -> Result<String> {
+        inspect_raftnode(&self.raw_node)
+    }
+
+    fn send_messages(&mut self, messages: Vec<RaftMessage>) {
+        for message in messages {
+            let node_id = message.get_to();
+            let is_snapshot = message.get_msg_type() == MessageType::MsgSnapshot;
+
+            if let Err(e) = self.peer_streams.send(message) {
+                self.logger
+                    .debug(&format!("Error occurred while sending message: {}", e));
+                if is_snapshot {
+                    self.raw_node
+                        .report_snapshot(node_id, SnapshotStatus::Failure);
                 }
+                self.raw_node.report_unreachable(node_id);
+                self.metrics.record_peer_send_failure(node_id);
             }
         }
-        Ok(())
+    }
+
+    /// Hands committed entries over to the apply task.
+    /// raft-rs learns they were applied through `ApplyEvent`s.
+    fn handle_committed_entries(&mut self, committed_entries: Vec<Entry>) -> Result<()> {
+        if committed_entries.is_empty() {
+            return Ok(());
+        }
+        self.send_to_apply_task(ApplyMsg::Entries(committed_entries))
     }
 
     async fn handle_join(&mut self, tickets: Vec<ClusterJoinTicket>) -> Result<()> {
@@ -769,13 +1218,17 @@ impl<
         let cc_v2: ConfChangeRequest = cc_v2.clone().into();
         let cc_v2: raft_service::ChangeConfigArgs = cc_v2.into();
 
-        let mut leader_client =
-            create_client(peer_addr, self.config.client_tls_config.clone()).await?;
-
-        let response = leader_client
-            .change_config(cc_v2.clone())
+        let response = match self
+            .transport
+            .forward(
+                parse_addr(&peer_addr)?,
+                ForwardedRequest::ChangeConfig(cc_v2),
+            )
             .await?
-            .into_inner();
+        {
+            ForwardedResponse::ChangeConfig(response) => response,
+            _ => unreachable!(),
+        };
 
         match response.result_type() {
             ChangeConfigResultType::ChangeConfigSuccess => Ok(()),
@@ -784,6 +1237,7 @@ impl<
                 Err(Error::Rejected("Confchange request rejected".to_string()))
             }
             ChangeConfigResultType::ChangeConfigTimeoutError => Err(Error::Timeout),
+            ChangeConfigResultType::ChangeConfigNoLeader => Err(Error::NoLeader),
             ChangeConfigResultType::ChangeConfigWrongLeader => {
                 // Should be handled in RaftServiceClient
                 unreachable!()
@@ -791,64 +1245,60 @@ impl<
         }
     }
 
-    async fn handle_committed_normal_entry(&mut self, entry: &Entry) -> Result<()> {
-        let response_seq: u64 = deserialize(entry.get_context())?;
-        let _data = self.fsm.apply(entry.get_data().to_vec()).await?;
-
-        if let Some(sender) = self.response_senders.remove(&response_seq) {
-            match sender {
-                ResponseSender::Local(tx_local) => {
-                    tx_local
-                        .send(LocalResponseMsg::Propose {
-                            result: ResponseResult::Success,
-                        })
-                        .unwrap();
-                }
-                ResponseSender::Server(tx_server) => {
-                    tx_server
-                        .send(ServerResponseMsg::Propose {
-                            result: ResponseResult::Success,
-                        })
-                        .unwrap();
-                }
-            }
-        }
-
-        if let Some(snapshot_interval) = self.config.snapshot_interval {
-            if Instant::now()
-                > self.last_snapshot_created + Duration::from_secs_f32(snapshot_interval)
-            {
-                self.make_snapshot(entry.index, entry.term).await?;
-            }
-        }
-
-        Ok(())
-    }
-
     async fn handle_committed_config_change_entry(&mut self, entry: &Entry) -> Result<()> {
-        if entry.get_context().is_empty() {
-            let conf_change_v2 = match entry.get_entry_type() {
-                EntryType::EntryConfChange => {
-                    to_confchange_v2(ConfChange::decode(entry.get_data())?)
-                }
-                EntryType::EntryConfChangeV2 => ConfChangeV2::decode(entry.get_data())?,
-                _ => unreachable!(),
-            };
+        let conf_change_v2 = match entry.get_entry_type() {
+            EntryType::EntryConfChange => to_confchange_v2(ConfChange::decode(entry.get_data())?),
+            EntryType::EntryConfChangeV2 => ConfChangeV2::decode(entry.get_data())?,
+            _ => unreachable!(),
+        };
 
+        if entry.get_context().is_empty() {
+            // Leaving the joint configuration, the outgoing voters are only removed now.
+            let outgoing = self
+                .raw_node
+                .raft
+                .prs()
+                .conf()
+                .to_conf_state()
+                .voters_outgoing;
             let cs = self.raw_node.apply_conf_change(&conf_change_v2)?;
             let store = self.raw_node.mut_store();
             store.set_conf_state(&cs)?;
+
+            if cs.voters_outgoing.is_empty() {
+                let removed_ids = outgoing
+                    .into_iter()
+                    .filter(|id| !cs.voters.contains(id) && !cs.learners.contains(id))
+                    .collect::<Vec<_>>();
+                self.remove_peers(&removed_ids).await;
+
+                if let Some((response_seq, conf_changes)) = self.pending_joint_response.take() {
+                    self.respond_config_change(response_seq, &conf_changes)
+                        .await;
+                }
+            }
             return Ok(());
         }
 
-        let conf_change_v2 = match entry.get_entry_type() {
-            EntryType::EntryConfChange => to_confchange_v2(ConfChange::decode(entry.get_data())?),
-            EntryType::EntryConfChangeV2 => ConfChangeV2::decode(entry.get_data())?,
-            _ => unreachable!(),
-        };
-
-        let conf_changes = conf_change_v2.get_changes();
+        let conf_changes = conf_change_v2.get_changes().to_vec();
         let addrs: Vec<SocketAddr> = deserialize(conf_change_v2.get_context())?;
+        let response_seq: AtomicU64 = deserialize(entry.get_context())?;
+        let response_seq = response_seq.load(Ordering::Relaxed);
+
+        let conf_state = match self.raw_node.apply_conf_change(&conf_change_v2) {
+            Ok(conf_state) => conf_state,
+            Err(e) => {
+                self.logger.error(&format!(
+                    "Failed to apply the configuration change. Error: {:?}",
+                    e
+                ));
+                // Neither the configuration nor the peers have changed.
+                if let Some((kind, sender)) = self.response_senders.remove(&response_seq) {
+                    sender.send_error(kind, Error::RaftError(e));
+                }
+                return Ok(());
+            }
+        };
 
         for (cc_idx, conf_change) in conf_changes.iter().enumerate() {
             let node_id = conf_change.get_node_id();
@@ -866,6 +1316,11 @@ impl<
                         addr.to_string(),
                         Some(InitialRole::Voter),
                     );
+                    self.publish(RaftEvent::PeerAdded {
+                        node_id,
+                        addr,
+                        role: InitialRole::Voter,
+                    });
                 }
                 ConfChangeType::AddLearnerNode => {
                     let addr = addrs[cc_idx];
@@ -878,86 +1333,114 @@ impl<
                         addr.to_string(),
                         Some(InitialRole::Learner),
                     );
+                    self.publish(RaftEvent::PeerAdded {
+                        node_id,
+                        addr,
+                        role: InitialRole::Learner,
+                    });
                 }
-                ConfChangeType::RemoveNode => {
-                    if node_id == self.get_id() {
-                        self.should_exit = true;
-                    } else {
-                        self.logger
-                            .info(&format!("Node {} removed from the cluster.", node_id));
-                        self.peers.lock().await.remove(&node_id);
-                    }
-                }
-            }
-        }
-
-        match self.raw_node.apply_conf_change(&conf_change_v2) {
-            Ok(conf_state) => {
-                let store = self.raw_node.mut_store();
-                store.set_conf_state(&conf_state)?;
-                self.make_snapshot(entry.index, entry.term).await?;
-            }
-            Err(e) => {
-                self.logger.error(&format!(
-                    "Failed to apply the configuration change. Error: {:?}",
-                    e
-                ));
+                // Removals are handled once the new configuration is in effect.
+                ConfChangeType::RemoveNode => {}
             }
         }
 
-        let response_seq: AtomicU64 = deserialize(entry.get_context())?;
+        let store = self.raw_node.mut_store();
+        store.set_conf_state(&conf_state)?;
+        self.request_snapshot(None)?;
 
-        let response_seq_value = response_seq.load(Ordering::Relaxed);
+        if !conf_state.voters_outgoing.is_empty() {
+            // The removed voters still count in the outgoing majority until the joint
+            // configuration is left, so they are removed and the requester answered then.
+            self.pending_joint_response = Some((response_seq, conf_changes));
+        } else {
+            let removed_ids = conf_changes
+                .iter()
+                .filter(|cc| cc.get_change_type() == ConfChangeType::RemoveNode)
+                .map(|cc| cc.get_node_id())
+                .collect::<Vec<_>>();
+            self.remove_peers(&removed_ids).await;
+            self.respond_config_change(response_seq, &conf_changes)
+                .await;
+        }
 
-        if let Some(sender) = self.response_senders.remove(&response_seq_value) {
-            #[allow(unused_assignments)]
-            let mut response = ConfChangeResponseResult::Error(Error::Unknown);
+        Ok(())
+    }
 
-            if conf_changes.iter().all(|cc| {
-                cc.get_change_type() == ConfChangeType::AddNode
-                    || cc.get_change_type() == ConfChangeType::AddLearnerNode
-            }) {
-                response = ConfChangeResponseResult::JoinSuccess {
-                    assigned_ids: conf_changes.iter().map(|cc| cc.get_node_id()).collect(),
-                    peers: self.peers.lock().await.clone(),
-                };
-            } else if conf_changes
-                .iter()
-                .all(|cc| cc.get_change_type() == ConfChangeType::RemoveNode)
-            {
-                response = ConfChangeResponseResult::RemoveSuccess;
+    async fn remove_peers(&mut self, node_ids: &[u64]) {
+        for node_id in node_ids.iter().copied() {
+            if node_id == self.get_id() {
+                self.should_exit = true;
             } else {
-                // TODO: Handle mixed cases
-                unimplemented!();
+                self.logger
+                    .info(&format!("Node {} removed from the cluster.", node_id));
+                self.peers.lock().await.remove(&node_id);
+                self.peer_streams.remove(node_id);
             }
+            self.publish(RaftEvent::PeerRemoved { node_id });
+        }
+    }
 
-            match sender {
-                ResponseSender::Local(tx_local) => {
-                    tx_local
-                        .send(LocalResponseMsg::ConfigChange { result: response })
-                        .unwrap();
+    async fn respond_config_change(
+        &mut self,
+        response_seq: u64,
+        conf_changes: &[ConfChangeSingle],
+    ) {
+        if let Some((_, sender)) = self.response_senders.remove(&response_seq) {
+            let (removed, assigned): (Vec<_>, Vec<_>) = conf_changes
+                .iter()
+                .partition(|cc| cc.get_change_type() == ConfChangeType::RemoveNode);
+            let assigned_ids = assigned
+                .iter()
+                .map(|cc| cc.get_node_id())
+                .collect::<Vec<_>>();
+            let removed_ids = removed
+                .iter()
+                .map(|cc| cc.get_node_id())
+                .collect::<Vec<_>>();
+
+            let response = if removed_ids.is_empty() {
+                ConfChangeResponseResult::JoinSuccess {
+                    assigned_ids,
+                    peers: self.peers.lock().await.clone(),
+                }
+            } else if assigned_ids.is_empty() {
+                ConfChangeResponseResult::RemoveSuccess
+            } else {
+                ConfChangeResponseResult::ReplaceSuccess {
+                    assigned_ids,
+                    removed_ids,
+                    peers: self.peers.lock().await.clone(),
+                }
+            };
+
+            // The caller may have given up on the change after conf_change_request_timeout.
+            match sender {
+                ResponseSender::Local(tx_local) => {
+                    let _ = tx_local.send(LocalResponseMsg::ConfigChange { result: response });
                 }
                 ResponseSender::Server(tx_server) => {
-                    tx_server
-                        .send(ServerResponseMsg::ConfigChange { result: response })
-                        .unwrap();
+                    let _ = tx_server.send(ServerResponseMsg::ConfigChange { result: response });
                 }
             }
         }
-
-        Ok(())
     }
 
     async fn handle_propose_request(
         &mut self,
         proposal: Vec<u8>,
+        proposal_timeout: Option<Duration>,
+        session: Option<ClientRequest>,
         response_sender: ResponseSender<LogEntry, LogStorage, FSM>,
     ) -> Result<()> {
         if !self.is_leader() {
             let leader_id = self.get_leader_id();
             if leader_id == 0 {
-                self.logger
-                    .error("There is no leader in the cluster at the time. try later...");
+                self.queue_leaderless_request(LeaderlessRequest::Propose {
+                    proposal,
+                    timeout: proposal_timeout,
+                    session,
+                    response_sender,
+                });
                 return Ok(());
             }
 
@@ -973,14 +1456,14 @@ impl<
 
             let raft_response: ResponseMessage<LogEntry, LogStorage, FSM> = match response_sender {
                 ResponseSender::Local(_) => LocalResponseMsg::Propose {
-                    result: ResponseResult::WrongLeader {
+                    result: ProposeResponseResult::WrongLeader {
                         leader_id,
                         leader_addr,
                     },
                 }
                 .into(),
                 ResponseSender::Server(_) => ServerResponseMsg::Propose {
-                    result: ResponseResult::WrongLeader {
+                    result: ProposeResponseResult::WrongLeader {
                         leader_id,
                         leader_addr,
                     },
@@ -990,19 +1473,15 @@ impl<
 
             response_sender.send(raft_response);
         } else {
-            let response_seq = self.response_seq.fetch_add(1, Ordering::Relaxed);
-            match response_sender {
-                ResponseSender::Local(tx_local) => {
-                    self.response_senders
-                        .insert(response_seq, ResponseSender::Local(tx_local));
-                }
-                ResponseSender::Server(tx_server) => {
-                    self.response_senders
-                        .insert(response_seq, ResponseSender::Server(tx_server));
-                }
-            };
+            let response_seq =
+                self.track_response(RequestKind::Propose, response_sender, proposal_timeout);
+            self.proposal_starts.insert(response_seq, Instant::now());
 
-            self.raw_node.propose(serialize(&response_seq)?, proposal)?;
+            let context = ProposalContext {
+                response_seq,
+                session,
+            };
+            self.raw_node.propose(context.encode()?, proposal)?;
         }
 
         Ok(())
@@ -1015,14 +1494,22 @@ impl<
     ) -> Result<()> {
         if self.raw_node.raft.has_pending_conf() {
             self.logger.warn(&format!("Reject the conf change because pending conf change exist! (pending_conf_index={}), try later...", self.raw_node.raft.pending_conf_index));
+            Self::send_conf_change_response(
+                response_sender,
+                ConfChangeResponseResult::Error(Error::Rejected(
+                    "A conf change is already pending".to_owned(),
+                )),
+            );
             return Ok(());
         }
 
         if !self.is_leader() {
             let leader_id = self.get_leader_id();
             if leader_id == 0 {
-                self.logger
-                    .error("There is no leader in the cluster at the time. try later...");
+                self.queue_leaderless_request(LeaderlessRequest::ChangeConfig {
+                    conf_change,
+                    response_sender,
+                });
                 return Ok(());
             }
 
@@ -1047,18 +1534,8 @@ impl<
                     .unwrap(),
             }
         } else {
-            let response_seq = self.response_seq.fetch_add(1, Ordering::Relaxed);
-
-            match response_sender {
-                ResponseSender::Local(tx_local) => {
-                    self.response_senders
-                        .insert(response_seq, ResponseSender::Local(tx_local));
-                }
-                ResponseSender::Server(tx_server) => {
-                    self.response_senders
-                        .insert(response_seq, ResponseSender::Server(tx_server));
-                }
-            };
+            let response_seq =
+                self.track_response(RequestKind::ChangeConfig, response_sender, None);
 
             self.logger.debug(&format!(
                 "Proposed new config change..., seq={}, conf_change_v2={}",
@@ -1073,6 +1550,364 @@ impl<
         Ok(())
     }
 
+    async fn handle_promote_learner_request(
+        &mut self,
+        node_id: u64,
+        response_sender: ResponseSender<LogEntry, LogStorage, FSM>,
+    ) -> Result<()> {
+        if !self.is_leader() {
+            let leader_id = self.get_leader_id();
+            if leader_id == 0 {
+                self.queue_leaderless_request(LeaderlessRequest::PromoteLearner {
+                    node_id,
+                    response_sender,
+                });
+                return Ok(());
+            }
+
+            let leader_addr = self
+                .peers
+                .lock()
+                .await
+                .get(&leader_id)
+                .unwrap()
+                .addr
+                .to_string();
+
+            Self::send_conf_change_response(
+                response_sender,
+                ConfChangeResponseResult::WrongLeader {
+                    leader_id,
+                    leader_addr,
+                },
+            );
+            return Ok(());
+        }
+
+        let is_learner = self.raw_node.raft.prs().conf().learners.contains(&node_id);
+
+        if !is_learner || self.peers.lock().await.get(&node_id).is_none() {
+            Self::send_conf_change_response(
+                response_sender,
+                ConfChangeResponseResult::Error(Error::Rejected(format!(
+                    "Node {} is not a learner of the cluster",
+                    node_id
+                ))),
+            );
+            return Ok(());
+        }
+
+        self.logger.info(&format!(
+            "Node {} will be promoted once it catches up with the leader.",
+            node_id
+        ));
+        self.pending_promotions.push((node_id, response_sender));
+        Ok(())
+    }
+
+    async fn handle_pending_promotions(&mut self) -> Result<()> {
+        if self.pending_promotions.is_empty() {
+            return Ok(());
+        }
+
+        if !self.is_leader() {
+            for (_, sender) in std::mem::take(&mut self.pending_promotions) {
+                Self::send_conf_change_response(
+                    sender,
+                    ConfChangeResponseResult::Error(Error::Rejected(
+                        "Leadership was lost before the learner was promoted".to_owned(),
+                    )),
+                );
+            }
+            return Ok(());
+        }
+
+        // Only one conf change can be in flight, the others wait for the next iteration.
+        if self.raw_node.raft.has_pending_conf() {
+            return Ok(());
+        }
+
+        let last_index = self.raw_node.raft.raft_log.last_index();
+        let threshold = self.config.learner_catch_up_threshold;
+        let prs = self.raw_node.raft.prs();
+        // Learners removed in the meantime are picked as well, to reject them below.
+        let position = self.pending_promotions.iter().position(|(node_id, _)| {
+            !matches!(prs.get(*node_id), Some(progress) if progress.matched + threshold < last_index)
+        });
+
+        let (node_id, sender) = match position {
+            Some(position) => self.pending_promotions.remove(position),
+            None => return Ok(()),
+        };
+
+        let is_removed = self.raw_node.raft.prs().get(node_id).is_none();
+        let addr = match self.peers.lock().await.get(&node_id) {
+            Some(peer) if !is_removed => peer.addr,
+            _ => {
+                Self::send_conf_change_response(
+                    sender,
+                    ConfChangeResponseResult::Error(Error::Rejected(format!(
+                        "Node {} was removed before it was promoted",
+                        node_id
+                    ))),
+                );
+                return Ok(());
+            }
+        };
+
+        self.logger.info(&format!(
+            "Node {} caught up with the leader, promoting it to voter...",
+            node_id
+        ));
+
+        let mut conf_change = ConfChange::default();
+        conf_change.set_node_id(node_id);
+        conf_change.set_change_type(ConfChangeType::AddNode);
+        conf_change.set_context(serialize(&vec![addr])?);
+
+        self.handle_confchange_request(to_confchange_v2(conf_change), sender)
+            .await
+    }
+
+    fn send_conf_change_response(
+        response_sender: ResponseSender<LogEntry, LogStorage, FSM>,
+        result: ConfChangeResponseResult,
+    ) {
+        // The caller may have given up on the change after conf_change_request_timeout.
+        let _ = match response_sender {
+            ResponseSender::Local(tx_local) => tx_local
+                .send(LocalResponseMsg::ConfigChange { result })
+                .map_err(|_| ()),
+            ResponseSender::Server(tx_server) => tx_server
+                .send(ServerResponseMsg::ConfigChange { result })
+                .map_err(|_| ()),
+        };
+    }
+
+    /// Registers a request waiting for its response, which fails with `Error::Timeout`
+    /// after `timeout`, or `Config::proposal_timeout` by default. Returns its response_seq.
+    fn track_response(
+        &mut self,
+        kind: RequestKind,
+        response_sender: ResponseSender<LogEntry, LogStorage, FSM>,
+        timeout: Option<Duration>,
+    ) -> u64 {
+        let response_seq = self.response_seq.fetch_add(1, Ordering::Relaxed);
+        self.response_senders
+            .insert(response_seq, (kind, response_sender));
+
+        let timeout =
+            timeout.unwrap_or_else(|| Duration::from_secs_f32(self.config.proposal_timeout));
+        if !timeout.is_zero() {
+            self.response_deadlines
+                .push(Reverse((Instant::now() + timeout, response_seq)));
+        }
+        response_seq
+    }
+
+    fn expire_responses(&mut self) {
+        let now = Instant::now();
+        while let Some(Reverse((deadline, response_seq))) = self.response_deadlines.peek() {
+            if *deadline > now {
+                break;
+            }
+            let response_seq = *response_seq;
+            self.response_deadlines.pop();
+
+            // Already answered if the request completed in time
+            if let Some((kind, sender)) = self.response_senders.remove(&response_seq) {
+                self.proposal_starts.remove(&response_seq);
+                sender.send_error(kind, Error::Timeout);
+            }
+        }
+    }
+
+    /// A leader stepping down can't tell whether its pending requests will be committed,
+    /// so they are failed instead of being left waiting forever.
+    fn fail_pending_responses_on_step_down(&mut self) {
+        if self.last_role != StateRole::Leader || self.is_leader() {
+            return;
+        }
+
+        if !self.response_senders.is_empty() {
+            self.logger.warn(&format!(
+                "Leadership lost, failing {} pending requests.",
+                self.response_senders.len()
+            ));
+        }
+        for (_, (kind, sender)) in self.response_senders.drain() {
+            sender.send_error(kind, Error::LeadershipLost);
+        }
+        self.proposal_starts.clear();
+        self.response_deadlines.clear();
+    }
+
+    fn queue_leaderless_request(&mut self, request: LeaderlessRequest<LogEntry, LogStorage, FSM>) {
+        let timeout = self.config.leaderless_request_timeout;
+        if timeout <= 0.0 {
+            request.reject();
+            return;
+        }
+
+        self.logger
+            .warn("There is no leader in the cluster at the time. Queue the request until one is elected...");
+        let deadline = Instant::now() + Duration::from_secs_f32(timeout);
+        self.leaderless_requests.push_back((deadline, request));
+    }
+
+    async fn handle_leaderless_requests(&mut self) -> Result<()> {
+        if self.leaderless_requests.is_empty() {
+            return Ok(());
+        }
+
+        if self.get_leader_id() == 0 {
+            let now = Instant::now();
+            while matches!(self.leaderless_requests.front(), Some((deadline, _)) if *deadline <= now)
+            {
+                let (_, request) = self.leaderless_requests.pop_front().unwrap();
+                request.reject();
+            }
+            return Ok(());
+        }
+
+        for (_, request) in std::mem::take(&mut self.leaderless_requests) {
+            match request {
+                LeaderlessRequest::Propose {
+                    proposal,
+                    timeout,
+                    session,
+                    response_sender,
+                } => {
+                    self.handle_propose_request(proposal, timeout, session, response_sender)
+                        .await?
+                }
+                LeaderlessRequest::ChangeConfig {
+                    conf_change,
+                    response_sender,
+                } => {
+                    self.handle_confchange_request(conf_change, response_sender)
+                        .await?
+                }
+                LeaderlessRequest::ReadIndex {
+                    lease_based,
+                    response_sender,
+                } => {
+                    self.handle_read_index_request(lease_based, response_sender)
+                        .await?
+                }
+                LeaderlessRequest::PromoteLearner {
+                    node_id,
+                    response_sender,
+                } => {
+                    self.handle_promote_learner_request(node_id, response_sender)
+                        .await?
+                }
+            }
+        }
+        Ok(())
+    }
+
+    async fn handle_read_index_request(
+        &mut self,
+        lease_based: bool,
+        response_sender: ResponseSender<LogEntry, LogStorage, FSM>,
+    ) -> Result<()> {
+        if lease_based && !self.config.raft_config.check_quorum {
+            Self::send_read_index_response(
+                response_sender,
+                ReadIndexResponseResult::Error(Error::Rejected(
+                    "Lease read requires check_quorum to be enabled".to_owned(),
+                )),
+            );
+            return Ok(());
+        }
+
+        if !self.is_leader() {
+            let leader_id = self.get_leader_id();
+            if leader_id == 0 {
+                self.queue_leaderless_request(LeaderlessRequest::ReadIndex {
+                    lease_based,
+                    response_sender,
+                });
+                return Ok(());
+            }
+
+            let leader_addr = self
+                .peers
+                .lock()
+                .await
+                .get(&leader_id)
+                .unwrap()
+                .addr
+                .to_string();
+
+            Self::send_read_index_response(
+                response_sender,
+                ReadIndexResponseResult::WrongLeader {
+                    leader_id,
+                    leader_addr,
+                },
+            );
+            return Ok(());
+        }
+
+        let response_seq = self.track_response(RequestKind::ReadIndex, response_sender, None);
+
+        if lease_based {
+            // raft-rs decides how to serve a ReadIndex request by `read_only.option`, which it
+            // only reads while stepping the request. So switching it around `read_index` affects
+            // this request only, and the configured option still applies to every other one.
+            let read_only_option = self.raw_node.raft.read_only.option;
+            self.raw_node.raft.read_only.option = ReadOnlyOption::LeaseBased;
+            self.raw_node.read_index(serialize(&response_seq)?);
+            self.raw_node.raft.read_only.option = read_only_option;
+        } else {
+            self.raw_node.read_index(serialize(&response_seq)?);
+        }
+        Ok(())
+    }
+
+    fn handle_read_states(&mut self, read_states: Vec<ReadState>) -> Result<()> {
+        for read_state in read_states {
+            let response_seq: u64 = deserialize(&read_state.request_ctx)?;
+            if let Some((_, sender)) = self.response_senders.remove(&response_seq) {
+                self.pending_reads.push((read_state.index, sender));
+            }
+        }
+        Ok(())
+    }
+
+    fn handle_pending_reads(&mut self) {
+        if self.pending_reads.is_empty() {
+            return;
+        }
+
+        let applied = self.raw_node.raft.raft_log.applied;
+        let (readable, pending): (Vec<_>, Vec<_>) = std::mem::take(&mut self.pending_reads)
+            .into_iter()
+            .partition(|(index, _)| *index <= applied);
+        self.pending_reads = pending;
+
+        for (index, sender) in readable {
+            Self::send_read_index_response(sender, ReadIndexResponseResult::Success { index });
+        }
+    }
+
+    fn send_read_index_response(
+        response_sender: ResponseSender<LogEntry, LogStorage, FSM>,
+        result: ReadIndexResponseResult,
+    ) {
+        // The caller may have given up on the read meanwhile.
+        match response_sender {
+            ResponseSender::Local(tx_local) => {
+                let _ = tx_local.send(LocalResponseMsg::ReadIndex { result });
+            }
+            ResponseSender::Server(tx_server) => {
+                let _ = tx_server.send(ServerResponseMsg::ReadIndex { result });
+            }
+        }
+    }
+
     async fn handle_local_request_msg(
         &mut self,
         message: LocalRequestMsg<LogEntry, LogStorage, FSM>,
@@ -1118,11 +1953,7 @@ impl<
                 tx_msg.send(LocalResponseMsg::AddPeers {}).unwrap();
             }
             LocalRequestMsg::GetStateMachine { tx_msg } => {
-                tx_msg
-                    .send(LocalResponseMsg::GetStateMachine {
-                        store: self.fsm.clone(),
-                    })
-                    .unwrap();
+                self.send_to_apply_task(ApplyMsg::GetStateMachine { tx_msg })?;
             }
             LocalRequestMsg::GetStorage { tx_msg } => {
                 tx_msg
@@ -1138,9 +1969,19 @@ impl<
                     })
                     .unwrap();
             }
-            LocalRequestMsg::Propose { proposal, tx_msg } => {
-                self.handle_propose_request(proposal, ResponseSender::Local(tx_msg))
-                    .await?;
+            LocalRequestMsg::Propose {
+                proposal,
+                timeout,
+                session,
+                tx_msg,
+            } => {
+                self.handle_propose_request(
+                    proposal,
+                    timeout,
+                    session,
+                    ResponseSender::Local(tx_msg),
+                )
+                .await?;
             }
             LocalRequestMsg::GetClusterSize { tx_msg } => {
                 let size = self.raw_node.raft.prs().iter().collect::<Vec<_>>().len();
@@ -1194,13 +2035,24 @@ impl<
                 self.handle_confchange_request(conf_change, ResponseSender::Local(tx_msg))
                     .await?;
             }
-            LocalRequestMsg::MakeSnapshot {
-                index,
-                term,
+            LocalRequestMsg::PromoteLearner { node_id, tx_msg } => {
+                self.handle_promote_learner_request(node_id, ResponseSender::Local(tx_msg))
+                    .await?;
+            }
+            LocalRequestMsg::MakeSnapshot { tx_msg } => {
+                self.request_snapshot(Some(ResponseSender::Local(tx_msg)))?;
+            }
+            LocalRequestMsg::ReadIndex {
+                lease_based,
                 tx_msg,
             } => {
-                self.make_snapshot(index, term).await?;
-                tx_msg.send(LocalResponseMsg::MakeSnapshot {}).unwrap();
+                self.handle_read_index_request(lease_based, ResponseSender::Local(tx_msg))
+                    .await?;
+            }
+            LocalRequestMsg::WaitForApplied { index, tx_msg } => {
+                self.pending_reads
+                    .push((index, ResponseSender::Local(tx_msg)));
+                self.handle_pending_reads();
             }
             LocalRequestMsg::JoinCluster { tickets, tx_msg } => {
                 self.handle_join(tickets).await?;
@@ -1232,6 +2084,15 @@ impl<
         match message {
             SelfMessage::ReportUnreachable { node_id } => {
                 self.raw_node.report_unreachable(node_id);
+                self.metrics.record_peer_send_failure(node_id);
+            }
+            SelfMessage::ReportSnapshotFailure { node_id } => {
+                self.raw_node
+                    .report_snapshot(node_id, SnapshotStatus::Failure);
+            }
+            SelfMessage::ReportSnapshotFinish { node_id } => {
+                self.raw_node
+                    .report_snapshot(node_id, SnapshotStatus::Finish);
             }
         }
 
@@ -1251,6 +2112,10 @@ impl<
                 self.handle_confchange_request(conf_change, ResponseSender::Server(tx_msg))
                     .await?;
             }
+            ServerRequestMsg::PromoteLearner { node_id, tx_msg } => {
+                self.handle_promote_learner_request(node_id, ResponseSender::Server(tx_msg))
+                    .await?;
+            }
             ServerRequestMsg::SendMessage { message } => {
                 let is_heartbeat_message = message.get_msg_type() == MessageType::MsgHeartbeat
                     || message.get_msg_type() == MessageType::MsgHeartbeatResponse;
@@ -1265,16 +2130,29 @@ impl<
                 }
                 let _ = self.raw_node.step(*message);
             }
-            ServerRequestMsg::Propose { proposal, tx_msg } => {
-                self.handle_propose_request(proposal, ResponseSender::Server(tx_msg))
-                    .await?;
+            ServerRequestMsg::Propose {
+                proposal,
+                timeout,
+                session,
+                tx_msg,
+            } => {
+                self.handle_propose_request(
+                    proposal,
+                    timeout,
+                    session,
+                    ResponseSender::Server(tx_msg),
+                )
+                .await?;
             }
             ServerRequestMsg::RequestId { raft_addr, tx_msg } => {
                 if !self.is_leader() {
                     let leader_id = self.get_leader_id();
                     if leader_id == 0 {
-                        self.logger
-                            .error("There is no leader in the cluster at the time. try later...");
+                        tx_msg
+                            .send(ServerResponseMsg::RequestId {
+                                result: RequestIdResponseResult::Error(Error::NoLeader),
+                            })
+                            .unwrap();
                         return Ok(());
                     }
 
@@ -1338,10 +2216,14 @@ impl<
                     .unwrap();
             }
             ServerRequestMsg::CreateSnapshot { tx_msg } => {
-                let last_index = self.raw_node.store().last_index()?;
-                let last_term = self.raw_node.store().hard_state()?.term;
-                self.make_snapshot(last_index, last_term).await?;
-                tx_msg.send(ServerResponseMsg::CreateSnapshot {}).unwrap();
+                self.request_snapshot(Some(ResponseSender::Server(tx_msg)))?;
+            }
+            ServerRequestMsg::ReadIndex {
+                lease_based,
+                tx_msg,
+            } => {
+                self.handle_read_index_request(lease_based, ResponseSender::Server(tx_msg))
+                    .await?;
             }
             ServerRequestMsg::SetPeers { tx_msg, peers } => {
                 self.peers.lock().await.replace(peers);
@@ -1356,6 +2238,10 @@ impl<
     }
 
     pub async fn run(mut self) -> Result<()> {
+        if let Some(apply_task) = self.apply_task.take() {
+            tokio::spawn(apply_task.run());
+        }
+
         let mut tick_timer = Duration::from_secs_f32(self.config.tick_interval);
         let fixed_tick_timer = tick_timer;
         let mut now = Instant::now();
@@ -1364,6 +2250,7 @@ impl<
             if self.should_exit {
                 self.logger
                     .info(&format!("Node {} quit the cluster.", self.get_id()));
+                self.publish(RaftEvent::Quitting);
                 return Ok(());
             }
 
@@ -1373,14 +2260,27 @@ impl<
                         self.handle_self_message(msg).await?;
                     }
                 }
+                event = timeout(fixed_tick_timer, self.rx_apply.recv()) => {
+                    if let Ok(Some(event)) = event {
+                        self.handle_apply_event(event).await?;
+                    }
+                }
                 msg = timeout(fixed_tick_timer, self.rx_server.recv()) => {
                     if let Ok(Some(msg)) = msg {
+                        let is_proposal = matches!(msg, ServerRequestMsg::Propose { .. });
                         self.handle_server_request_msg(msg).await?;
+                        if is_proposal {
+                            self.batch_proposals().await?;
+                        }
                     }
                 }
                 msg = timeout(fixed_tick_timer, self.rx_local.recv()) => {
                     if let Ok(Some(msg)) = msg {
+                        let is_proposal = matches!(msg, LocalRequestMsg::Propose { .. });
                         self.handle_local_request_msg(msg).await?;
+                        if is_proposal {
+                            self.batch_proposals().await?;
+                        }
                     }
                 }
             }
@@ -1394,8 +2294,117 @@ impl<
                 tick_timer -= elapsed;
             }
 
-            self.on_ready().await?
+            self.on_ready().await?;
+            self.check_snapshot_policies()?;
+            self.replace_corrupted_snapshot()?;
+            self.leave_joint_if_needed()?;
+            self.handle_pending_promotions().await?;
+            self.handle_leaderless_requests().await?;
+            self.expire_responses();
+            self.fail_pending_responses_on_step_down();
+            self.update_metrics();
+            self.publish_state_changes();
+        }
+    }
+
+    /// Keeps proposing queued proposals until `max_proposal_batch_size` were proposed or
+    /// `proposal_batch_delay` has passed, so that they're appended and replicated in one ready.
+    /// Any other request ends the batch, so that requests are still handled in order.
+    async fn batch_proposals(&mut self) -> Result<()> {
+        let max_batch_size = self.config.max_proposal_batch_size;
+        if max_batch_size <= 1 || !self.is_leader() {
+            return Ok(());
+        }
+
+        let deadline = Instant::now() + Duration::from_secs_f32(self.config.proposal_batch_delay);
+        let mut batch_size = 1;
+        while batch_size < max_batch_size && !self.should_exit && Instant::now() < deadline {
+            tokio::select! {
+                biased;
+                Some(msg) = self.rx_local.recv() => {
+                    let is_proposal = matches!(msg, LocalRequestMsg::Propose { .. });
+                    self.handle_local_request_msg(msg).await?;
+                    if !is_proposal {
+                        break;
+                    }
+                }
+                Some(msg) = self.rx_server.recv() => {
+                    let is_proposal = matches!(msg, ServerRequestMsg::Propose { .. });
+                    self.handle_server_request_msg(msg).await?;
+                    if !is_proposal {
+                        break;
+                    }
+                }
+                _ = sleep_until(deadline) => break,
+            }
+            batch_size += 1;
+        }
+
+        self.metrics.proposal_batch_size.observe(batch_size as f64);
+        Ok(())
+    }
+
+    /// Conf changes with several changes are proposed with the explicit transition,
+    /// so the leader proposes leaving the joint configuration once it has been applied.
+    fn leave_joint_if_needed(&mut self) -> Result<()> {
+        if !self.is_leader() || self.raw_node.raft.has_pending_conf() {
+            return Ok(());
+        }
+
+        let conf_state = self.raw_node.raft.prs().conf().to_conf_state();
+        // raft-rs leaves the joint configuration by itself when auto_leave is set
+        if conf_state.voters_outgoing.is_empty() || conf_state.auto_leave {
+            return Ok(());
         }
+
+        self.logger.info("Leaving the joint configuration...");
+        self.raw_node
+            .propose_conf_change(vec![], ConfChangeV2::default())?;
+        Ok(())
+    }
+
+    fn publish(&self, event: RaftEvent) {
+        // Sending only fails when nobody is subscribed
+        let _ = self.tx_events.send(event);
+    }
+
+    fn publish_state_changes(&mut self) {
+        let (role, leader_id, term) = (
+            self.raw_node.raft.state,
+            self.raw_node.raft.leader_id,
+            self.raw_node.raft.term,
+        );
+
+        if role != self.last_role {
+            self.last_role = role;
+            self.publish(RaftEvent::RoleChanged { role, term });
+        }
+
+        if leader_id != self.last_leader_id {
+            self.last_leader_id = leader_id;
+            if leader_id != 0 {
+                self.publish(RaftEvent::LeaderChanged { leader_id, term });
+            }
+        }
+    }
+
+    fn update_metrics(&self) {
+        let raft = &self.raw_node.raft;
+        self.metrics.term.set(raft.term);
+        self.metrics.set_leader_id(raft.leader_id);
+        self.metrics.commit_index.set(raft.raft_log.committed);
+        self.metrics.applied_index.set(raft.raft_log.applied);
+        self.metrics.persisted_index.set(raft.raft_log.persisted);
+
+        let queue_depth = |max_capacity: usize, capacity: usize| (max_capacity - capacity) as u64;
+        self.metrics.local_queue_depth.set(queue_depth(
+            self.tx_local.max_capacity(),
+            self.tx_local.capacity(),
+        ));
+        self.metrics.server_queue_depth.set(queue_depth(
+            self.tx_server.max_capacity(),
+            self.tx_server.capacity(),
+        ));
     }
 
     async fn on_ready(&mut self) -> Result<()> {
@@ -1405,26 +2414,54 @@ impl<
         let mut ready = self.raw_node.ready();
 
         if !ready.messages().is_empty() {
-            self.send_messages(ready.take_messages()).await;
+            self.send_messages(ready.take_messages());
+        }
+
+        if !ready.read_states().is_empty() {
+            self.handle_read_states(ready.take_read_states())?;
         }
 
         if *ready.snapshot() != Snapshot::default() {
             self.logger
                 .info("Restoring state machine and snapshot metadata...");
             let snapshot = ready.snapshot();
-            if !snapshot.get_data().is_empty() {
-                self.fsm.restore(snapshot.get_data().to_vec()).await?;
-            }
+            // The snapshot replaces the whole log.
+            self.log_size = 0;
             let store = self.raw_node.mut_store();
-            store.apply_snapshot(snapshot.clone())?;
+            store.apply_snapshot(snapshot)?;
+
+            let data = if snapshot.get_data().is_empty() {
+                None
+            } else {
+                // Stream the state machine back from the persisted snapshot when the storage keeps it in a file.
+                // The file is opened right away, as a later snapshot may replace it.
+                match store.snapshot_data_path()? {
+                    Some(path) => Some(SnapshotData::File(tokio::fs::File::open(path).await?)),
+                    None => Some(SnapshotData::Memory(snapshot.get_data().to_vec())),
+                }
+            };
+            self.send_to_apply_task(ApplyMsg::Restore {
+                index: snapshot.get_metadata().index,
+                term: snapshot.get_metadata().term,
+                data,
+            })?;
         }
 
-        self.handle_committed_entries(ready.take_committed_entries())
-            .await?;
+        self.handle_committed_entries(ready.take_committed_entries())?;
 
         if !ready.entries().is_empty() {
             let entries = &ready.entries()[..];
             let store = self.raw_node.mut_store();
+            // Appending truncates the conflicting entries at the end of the log.
+            let last_index = store.last_index()?;
+            if entries[0].index <= last_index {
+                let truncated = entries_size(store, entries[0].index, last_index + 1)?;
+                self.log_size = self.log_size.saturating_sub(truncated);
+            }
+            self.log_size += entries
+                .iter()
+                .map(|entry| entry.encoded_len() as u64)
+                .sum::<u64>();
             store.append(entries)?;
         }
 
@@ -1434,10 +2471,11 @@ impl<
         }
 
         if !ready.persisted_messages().is_empty() {
-            self.send_messages(ready.take_persisted_messages()).await;
+            self.send_messages(ready.take_persisted_messages());
         }
 
-        let mut light_rd = self.raw_node.advance(ready);
+        // Entries are applied asynchronously, see `advance_apply_to`.
+        let mut light_rd = self.raw_node.advance_append(ready);
 
         if let Some(commit) = light_rd.commit_index() {
             let store = self.raw_node.mut_store();
@@ -1445,14 +2483,27 @@ impl<
         }
 
         if !light_rd.messages().is_empty() {
-            self.send_messages(light_rd.take_messages()).await;
+            self.send_messages(light_rd.take_messages());
         }
 
-        self.handle_committed_entries(light_rd.take_committed_entries())
-            .await?;
-
-        self.raw_node.advance_apply();
+        self.handle_committed_entries(light_rd.take_committed_entries())?;
 
         Ok(())
     }
 }
+
+/// Bytes of the entries in `[low, high)` of the log, read a batch at a time.
+fn entries_size<LogStorage: StableStorage>(store: &LogStorage, low: u64, high: u64) -> Result<u64> {
+    let mut size = 0;
+    let mut low = low.max(store.first_index()?);
+    while low < high {
+        let batch_high = (low + LOG_SCAN_BATCH).min(high);
+        size += store
+            .entries(low, batch_high, None, GetEntriesContext::empty(false))?
+            .iter()
+            .map(|entry| entry.encoded_len() as u64)
+            .sum::<u64>();
+        low = batch_high;
+    }
+    Ok(size)
+}
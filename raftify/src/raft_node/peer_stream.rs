@@ -0,0 +1,188 @@
+use std::{collections::HashMap, sync::Arc};
+use tokio::sync::{
+    mpsc::{self, error::TrySendError},
+    Mutex,
+};
+
+use crate::{
+    error::SendMessageError,
+    raft::{
+        eraftpb::{Message as RaftMessage, MessageType},
+        logger::Logger,
+    },
+    request::self_request_message::SelfMessage,
+    transport::Transport,
+    Config, Peer, Peers,
+};
+
+/// Outgoing message queues, one bounded queue per peer.
+/// Each queue is drained by its own task, which hands the messages to the transport in order.
+pub(crate) struct PeerStreams {
+    queues: HashMap<u64, mpsc::Sender<RaftMessage>>,
+    peers: Arc<Mutex<Peers>>,
+    transport: Arc<dyn Transport>,
+    tx_self: mpsc::Sender<SelfMessage>,
+    queue_size: usize,
+    max_batch_size: usize,
+    logger: Arc<dyn Logger>,
+}
+
+impl PeerStreams {
+    pub fn new(
+        peers: Arc<Mutex<Peers>>,
+        transport: Arc<dyn Transport>,
+        tx_self: mpsc::Sender<SelfMessage>,
+        config: &Config,
+        logger: Arc<dyn Logger>,
+    ) -> Self {
+        Self {
+            queues: HashMap::new(),
+            peers,
+            transport,
+            tx_self,
+            queue_size: config.peer_message_queue_size as usize,
+            max_batch_size: config.max_message_batch_size as usize,
+            logger,
+        }
+    }
+
+    /// Queues the message for its peer without waiting.
+    /// Fails when the peer's queue is full, in which case the message is dropped.
+    pub fn send(&mut self, message: RaftMessage) -> Result<(), SendMessageError> {
+        let node_id = message.get_to();
+
+        let is_queue_open = matches!(self.queues.get(&node_id), Some(queue) if !queue.is_closed());
+        if !is_queue_open {
+            let queue = self.spawn(node_id);
+            self.queues.insert(node_id, queue);
+        }
+
+        match self.queues[&node_id].try_send(message) {
+            Ok(()) => Ok(()),
+            Err(TrySendError::Full(_)) => Err(SendMessageError::QueueFull(node_id.to_string())),
+            Err(TrySendError::Closed(_)) => Err(SendMessageError::TransmissionError(format!(
+                "(to node {}). Message queue closed",
+                node_id
+            ))),
+        }
+    }
+
+    /// Drops the queue of a peer removed from the cluster, which also stops its task.
+    pub fn remove(&mut self, node_id: u64) {
+        self.queues.remove(&node_id);
+    }
+
+    fn spawn(&self, node_id: u64) -> mpsc::Sender<RaftMessage> {
+        let (tx, rx) = mpsc::channel(self.queue_size);
+        let task = PeerStreamTask {
+            node_id,
+            peers: self.peers.clone(),
+            transport: self.transport.clone(),
+            tx_self: self.tx_self.clone(),
+            max_batch_size: self.max_batch_size,
+            logger: self.logger.clone(),
+        };
+        tokio::spawn(task.run(rx));
+        tx
+    }
+}
+
+struct PeerStreamTask {
+    node_id: u64,
+    peers: Arc<Mutex<Peers>>,
+    transport: Arc<dyn Transport>,
+    tx_self: mpsc::Sender<SelfMessage>,
+    max_batch_size: usize,
+    logger: Arc<dyn Logger>,
+}
+
+impl PeerStreamTask {
+    async fn run(self, mut rx: mpsc::Receiver<RaftMessage>) {
+        while let Some(message) = rx.recv().await {
+            // Everything queued up to now goes out in the same flush
+            let mut batch = vec![message];
+            while batch.len() < self.max_batch_size {
+                match rx.try_recv() {
+                    Ok(message) => batch.push(message),
+                    Err(_) => break,
+                }
+            }
+            self.flush(batch).await;
+        }
+    }
+
+    async fn flush(&self, batch: Vec<RaftMessage>) {
+        // Snapshots are sent separately, so the batch is split around them to keep the order.
+        // The transport only sends a snapshot once the messages before it were delivered.
+        let mut messages = Vec::new();
+        for message in batch {
+            if message.get_msg_type() == MessageType::MsgSnapshot {
+                self.send_batch(std::mem::take(&mut messages)).await;
+                self.send_snapshot(message).await;
+            } else {
+                messages.push(message);
+            }
+        }
+        self.send_batch(messages).await;
+    }
+
+    async fn send_batch(&self, messages: Vec<RaftMessage>) {
+        if messages.is_empty() {
+            return;
+        }
+
+        let result = match self.peer().await {
+            Ok(peer) => self.transport.send_messages(&peer, messages).await,
+            Err(e) => Err(e),
+        };
+
+        if let Err(e) = result {
+            self.report_failure(e, false).await;
+        }
+    }
+
+    async fn send_snapshot(&self, message: RaftMessage) {
+        let result = match self.peer().await {
+            Ok(peer) => self.transport.send_snapshot(&peer, message).await,
+            Err(e) => Err(e),
+        };
+
+        match result {
+            Ok(()) => {
+                let _ = self
+                    .tx_self
+                    .send(SelfMessage::ReportSnapshotFinish {
+                        node_id: self.node_id,
+                    })
+                    .await;
+            }
+            Err(e) => self.report_failure(e, true).await,
+        }
+    }
+
+    async fn peer(&self) -> Result<Peer, SendMessageError> {
+        self.peers
+            .lock()
+            .await
+            .get(&self.node_id)
+            .cloned()
+            .ok_or_else(|| SendMessageError::PeerNotFound(self.node_id.to_string()))
+    }
+
+    async fn report_failure(&self, error: SendMessageError, is_snapshot: bool) {
+        self.logger
+            .debug(&format!("Error occurred while sending message: {}", error));
+
+        let node_id = self.node_id;
+        if is_snapshot {
+            let _ = self
+                .tx_self
+                .send(SelfMessage::ReportSnapshotFailure { node_id })
+                .await;
+        }
+        let _ = self
+            .tx_self
+            .send(SelfMessage::ReportUnreachable { node_id })
+            .await;
+    }
+}
@@ -2,12 +2,24 @@ use tokio::sync::oneshot;
 
 use crate::{
     response::{
-        local_response_message::LocalResponseMsg, server_response_message::ServerResponseMsg,
+        local_response_message::LocalResponseMsg,
+        server_response_message::{
+            ConfChangeResponseResult, ProposeResponseResult, ReadIndexResponseResult,
+            ServerResponseMsg,
+        },
         ResponseMessage,
     },
-    AbstractLogEntry, AbstractStateMachine, StableStorage,
+    AbstractLogEntry, AbstractStateMachine, Error, StableStorage,
 };
 
+/// Request a pending response answers, which decides the message to fail it with.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum RequestKind {
+    Propose,
+    ChangeConfig,
+    ReadIndex,
+}
+
 pub(crate) enum ResponseSender<
     LogEntry: AbstractLogEntry,
     LogStorage: StableStorage + 'static,
@@ -38,4 +50,41 @@ impl<LogEntry: AbstractLogEntry, LogStorage: StableStorage, FSM: AbstractStateMa
             }
         }
     }
+
+    /// Answers the request with the error.
+    /// The caller may have stopped waiting already, so send errors are ignored.
+    pub fn send_error(self, kind: RequestKind, error: Error) {
+        let _ = match (self, kind) {
+            (ResponseSender::Local(tx), RequestKind::Propose) => tx
+                .send(LocalResponseMsg::Propose {
+                    result: ProposeResponseResult::Error(error),
+                })
+                .map_err(|_| ()),
+            (ResponseSender::Server(tx), RequestKind::Propose) => tx
+                .send(ServerResponseMsg::Propose {
+                    result: ProposeResponseResult::Error(error),
+                })
+                .map_err(|_| ()),
+            (ResponseSender::Local(tx), RequestKind::ChangeConfig) => tx
+                .send(LocalResponseMsg::ConfigChange {
+                    result: ConfChangeResponseResult::Error(error),
+                })
+                .map_err(|_| ()),
+            (ResponseSender::Server(tx), RequestKind::ChangeConfig) => tx
+                .send(ServerResponseMsg::ConfigChange {
+                    result: ConfChangeResponseResult::Error(error),
+                })
+                .map_err(|_| ()),
+            (ResponseSender::Local(tx), RequestKind::ReadIndex) => tx
+                .send(LocalResponseMsg::ReadIndex {
+                    result: ReadIndexResponseResult::Error(error),
+                })
+                .map_err(|_| ()),
+            (ResponseSender::Server(tx), RequestKind::ReadIndex) => tx
+                .send(ServerResponseMsg::ReadIndex {
+                    result: ReadIndexResponseResult::Error(error),
+                })
+                .map_err(|_| ()),
+        };
+    }
 }
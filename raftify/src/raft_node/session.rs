@@ -0,0 +1,336 @@
+use bincode::{deserialize, serialize};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{Cursor, Read},
+};
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+use crate::{
+    raft::Storage, raft_service, AbstractLogEntry, AbstractOutput, AbstractStateMachine, Error,
+    Result, StableStorage,
+};
+
+/// Prefix of snapshots that carry the session table ahead of the state machine's data.
+/// Snapshots without it are passed to the state machine as they are.
+const SNAPSHOT_MAGIC: &[u8; 8] = b"RFYSESS1";
+
+/// Identifies a proposal within a client session.
+/// Retrying a proposal with the same `ClientRequest` applies it at most once.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub struct ClientRequest {
+    pub client_id: u64,
+    /// Increases by one with every new proposal of the client.
+    pub seq: u64,
+}
+
+impl From<raft_service::ClientRequest> for ClientRequest {
+    fn from(request: raft_service::ClientRequest) -> Self {
+        Self {
+            client_id: request.client_id,
+            seq: request.seq,
+        }
+    }
+}
+
+impl From<ClientRequest> for raft_service::ClientRequest {
+    fn from(request: ClientRequest) -> Self {
+        Self {
+            client_id: request.client_id,
+            seq: request.seq,
+        }
+    }
+}
+
+/// Context of a normal entry.
+#[derive(Debug, Deserialize, Serialize, PartialEq)]
+pub(crate) struct ProposalContext {
+    pub response_seq: u64,
+    pub session: Option<ClientRequest>,
+}
+
+impl ProposalContext {
+    pub fn encode(&self) -> Result<Vec<u8>> {
+        Ok(serialize(self)?)
+    }
+
+    /// Entries written before sessions existed only carry the `response_seq`.
+    pub fn decode(bytes: &[u8]) -> Result<Self> {
+        deserialize(bytes).or_else(|_| {
+            Ok(Self {
+                response_seq: deserialize(bytes)?,
+                session: None,
+            })
+        })
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+struct Session {
+    last_seq: u64,
+    last_result: Vec<u8>,
+    // Index of the entry that last touched the session, used to evict the idlest session.
+    last_index: u64,
+}
+
+pub(crate) enum SessionCheck {
+    New,
+    /// Already applied, answer with the cached result.
+    Duplicate(Vec<u8>),
+    /// Older than the last applied request of the session, whose result isn't kept anymore.
+    Stale,
+}
+
+/// Last applied request of each client session.
+/// Every node builds the same table by applying the same entries, and it's included in snapshots.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub(crate) struct SessionTable {
+    sessions: HashMap<u64, Session>,
+}
+
+impl SessionTable {
+    pub fn check(&self, request: &ClientRequest) -> SessionCheck {
+        match self.sessions.get(&request.client_id) {
+            Some(session) if request.seq == session.last_seq => {
+                SessionCheck::Duplicate(session.last_result.clone())
+            }
+            Some(session) if request.seq < session.last_seq => SessionCheck::Stale,
+            _ => SessionCheck::New,
+        }
+    }
+
+    /// Records the result of an applied request, evicting the idlest sessions beyond `max_sessions`.
+    pub fn record(
+        &mut self,
+        request: &ClientRequest,
+        index: u64,
+        result: Vec<u8>,
+        max_sessions: usize,
+    ) {
+        self.sessions.insert(
+            request.client_id,
+            Session {
+                last_seq: request.seq,
+                last_result: result,
+                last_index: index,
+            },
+        );
+
+        while self.sessions.len() > max_sessions {
+            let idlest = self
+                .sessions
+                .iter()
+                .min_by_key(|(_, session)| session.last_index)
+                .map(|(client_id, _)| *client_id)
+                .unwrap();
+            self.sessions.remove(&idlest);
+        }
+    }
+
+    /// Applies a committed entry to `fsm`, at most once per request if it was made in a session.
+    /// Returns the result of the request's first application, or `None` if the request is stale.
+    pub async fn apply<LogEntry: AbstractLogEntry, FSM: AbstractStateMachine>(
+        &mut self,
+        fsm: &mut FSM,
+        session: Option<&ClientRequest>,
+        index: u64,
+        data: Vec<u8>,
+        max_sessions: usize,
+    ) -> Result<Option<Vec<u8>>> {
+        match session.map(|request| self.check(request)) {
+            Some(SessionCheck::Duplicate(result)) => Ok(Some(result)),
+            Some(SessionCheck::Stale) => Ok(None),
+            None | Some(SessionCheck::New) => {
+                let result = fsm.apply_entry(LogEntry::decode(&data)?).await?.encode()?;
+                if let Some(request) = session {
+                    self.record(request, index, result.clone(), max_sessions);
+                }
+                Ok(Some(result))
+            }
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.sessions.len()
+    }
+
+    /// Header to write ahead of the state machine's data in a snapshot.
+    pub fn snapshot_header(&self) -> Result<Vec<u8>> {
+        let table = serialize(self)?;
+        let mut header = Vec::with_capacity(SNAPSHOT_MAGIC.len() + 8 + table.len());
+        header.extend_from_slice(SNAPSHOT_MAGIC);
+        header.extend_from_slice(&(table.len() as u64).to_le_bytes());
+        header.extend_from_slice(&table);
+        Ok(header)
+    }
+
+    /// Splits snapshot data into the session table and the state machine's data.
+    pub fn split_snapshot(data: &[u8]) -> Result<(Self, &[u8])> {
+        if !data.starts_with(SNAPSHOT_MAGIC) {
+            return Ok((Self::default(), data));
+        }
+
+        let rest = &data[SNAPSHOT_MAGIC.len()..];
+        if rest.len() < 8 {
+            return Err(Error::DecodingError(
+                "Truncated session table in snapshot".to_owned(),
+            ));
+        }
+        let (len, rest) = rest.split_at(8);
+        let len = u64::from_le_bytes(len.try_into().unwrap()) as usize;
+        if rest.len() < len {
+            return Err(Error::DecodingError(
+                "Truncated session table in snapshot".to_owned(),
+            ));
+        }
+        let (table, fsm_data) = rest.split_at(len);
+        Ok((deserialize(table)?, fsm_data))
+    }
+
+    /// Reads the session table off a streamed snapshot.
+    /// Returns the bytes read ahead from a snapshot without it, to be replayed to the state machine.
+    pub async fn read_snapshot_header<R: AsyncRead + Unpin>(
+        reader: &mut R,
+    ) -> Result<(Self, Cursor<Vec<u8>>)> {
+        let mut magic = Vec::with_capacity(SNAPSHOT_MAGIC.len());
+        (&mut *reader)
+            .take(SNAPSHOT_MAGIC.len() as u64)
+            .read_to_end(&mut magic)
+            .await?;
+
+        if magic != SNAPSHOT_MAGIC {
+            return Ok((Self::default(), Cursor::new(magic)));
+        }
+
+        let len = reader.read_u64_le().await? as usize;
+        let mut table = vec![0; len];
+        reader.read_exact(&mut table).await?;
+        Ok((deserialize(&table)?, Cursor::new(vec![])))
+    }
+
+    /// Session table of the snapshot kept in `storage`, which the state machine starts from.
+    pub fn from_storage<LogStorage: StableStorage>(storage: &LogStorage) -> Result<Self> {
+        let data_path = match storage.snapshot_data_path()? {
+            Some(data_path) => data_path,
+            None => return Ok(Self::split_snapshot(storage.snapshot(0, 0)?.get_data())?.0),
+        };
+
+        // Only the header is read, the state machine's data may be large.
+        let mut file = File::open(data_path)?;
+        let mut magic = Vec::with_capacity(SNAPSHOT_MAGIC.len());
+        (&mut file)
+            .take(SNAPSHOT_MAGIC.len() as u64)
+            .read_to_end(&mut magic)?;
+        if magic != SNAPSHOT_MAGIC {
+            return Ok(Self::default());
+        }
+
+        let mut len = [0; 8];
+        file.read_exact(&mut len)?;
+        let mut table = vec![0; u64::from_le_bytes(len) as usize];
+        file.read_exact(&mut table)?;
+        Ok(deserialize(&table)?)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_session_check() {
+        let mut table = SessionTable::default();
+        let request = ClientRequest {
+            client_id: 1,
+            seq: 2,
+        };
+        assert!(matches!(table.check(&request), SessionCheck::New));
+
+        table.record(&request, 10, b"result".to_vec(), 100);
+        assert!(
+            matches!(table.check(&request), SessionCheck::Duplicate(result) if result == b"result")
+        );
+        assert!(matches!(
+            table.check(&ClientRequest {
+                client_id: 1,
+                seq: 1
+            }),
+            SessionCheck::Stale
+        ));
+        assert!(matches!(
+            table.check(&ClientRequest {
+                client_id: 1,
+                seq: 3
+            }),
+            SessionCheck::New
+        ));
+    }
+
+    #[test]
+    fn test_evict_idlest_session() {
+        let mut table = SessionTable::default();
+        for client_id in 1..=3 {
+            let request = ClientRequest { client_id, seq: 1 };
+            table.record(&request, 10 - client_id, vec![], 2);
+        }
+
+        assert_eq!(table.len(), 2);
+        // Client 3 touched its session at the lowest index
+        assert!(matches!(
+            table.check(&ClientRequest {
+                client_id: 3,
+                seq: 1
+            }),
+            SessionCheck::New
+        ));
+    }
+
+    #[test]
+    fn test_legacy_proposal_context() {
+        let context = ProposalContext::decode(&serialize(&7u64).unwrap()).unwrap();
+        assert_eq!(
+            context,
+            ProposalContext {
+                response_seq: 7,
+                session: None
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_snapshot_header() {
+        let mut table = SessionTable::default();
+        let request = ClientRequest {
+            client_id: 1,
+            seq: 1,
+        };
+        table.record(&request, 1, b"result".to_vec(), 100);
+
+        let mut data = table.snapshot_header().unwrap();
+        data.extend_from_slice(b"fsm");
+
+        let (restored, fsm_data) = SessionTable::split_snapshot(&data).unwrap();
+        assert_eq!(fsm_data, b"fsm");
+        assert!(matches!(
+            restored.check(&request),
+            SessionCheck::Duplicate(_)
+        ));
+
+        let (restored, replay) = SessionTable::read_snapshot_header(&mut data.as_slice())
+            .await
+            .unwrap();
+        assert!(replay.get_ref().is_empty());
+        assert!(matches!(
+            restored.check(&request),
+            SessionCheck::Duplicate(_)
+        ));
+
+        // Snapshots without sessions are passed through
+        let (restored, replay) = SessionTable::read_snapshot_header(&mut &b"fsm"[..])
+            .await
+            .unwrap();
+        assert_eq!(restored.len(), 0);
+        assert_eq!(replay.into_inner(), b"fsm");
+    }
+}
@@ -5,35 +5,28 @@ use std::{
     time::Duration,
 };
 use tokio::{
-    sync::{
-        mpsc,
-        oneshot::{self, Receiver},
-    },
+    sync::{mpsc, oneshot},
     time::timeout,
 };
-use tonic::{transport::Server, Request, Response, Status};
-
-#[cfg(feature = "tls")]
-use tonic::transport::{Certificate, Identity, ServerTlsConfig};
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::{Request, Response, Status, Streaming};
 
 use super::{
     macro_utils::function_name,
-    raft_service::{
-        self,
-        raft_service_server::{RaftService, RaftServiceServer},
-    },
-    Config, Error,
+    raft_service::{self, raft_service_server::RaftService},
+    Config,
 };
 use crate::{
-    create_client,
     raft::{eraftpb::Message as RaftMessage, logger::Logger},
-    raft_service::ProposeArgs,
     request::{
         common::confchange_request::ConfChangeRequest, server_request_message::ServerRequestMsg,
     },
     response::server_response_message::{
-        ConfChangeResponseResult, RequestIdResponseResult, ResponseResult, ServerResponseMsg,
+        ConfChangeResponseResult, ProposeResponseResult, ReadIndexResponseResult,
+        RequestIdResponseResult, ServerResponseMsg,
     },
+    transport::{parse_addr, ForwardedRequest, ForwardedResponse, InboundMessages, Transport},
+    utils::snapshot_chunk::SnapshotChunkAssembler,
     AbstractLogEntry, AbstractStateMachine, StableStorage,
 };
 
@@ -47,6 +40,8 @@ pub struct RaftServer<
     raft_addr: SocketAddr,
     config: Config,
     logger: Arc<dyn Logger>,
+    transport: Arc<dyn Transport>,
+    inbound: Option<InboundMessages>,
 }
 
 impl<
@@ -60,6 +55,7 @@ impl<
         raft_addr: A,
         config: Config,
         logger: Arc<dyn Logger>,
+        transport: Arc<dyn Transport>,
     ) -> Self {
         let raft_addr = raft_addr.to_socket_addrs().unwrap().next().unwrap();
         RaftServer {
@@ -67,53 +63,53 @@ impl<
             raft_addr,
             config,
             logger,
+            transport,
+            inbound: None,
         }
     }
 
-    pub(crate) async fn run(self, rx_quit_signal: Receiver<()>) -> Result<(), Error> {
-        let raft_addr = self.raft_addr;
-        let logger = self.logger.clone();
-        logger.debug(&format!(
-            "RaftServer starts to listen gRPC requests on \"{}\"...",
-            raft_addr
-        ));
+    /// Delivers the messages received over gRPC through `inbound`, which a transport may have wrapped.
+    pub(crate) fn with_inbound(mut self, inbound: InboundMessages) -> Self {
+        self.inbound = Some(inbound);
+        self
+    }
 
-        let quit_signal = async {
-            rx_quit_signal.await.ok();
-        };
+    /// Forwards the request to the leader through the transport.
+    async fn forward(
+        &self,
+        leader_addr: &str,
+        request: ForwardedRequest,
+    ) -> Result<ForwardedResponse, Status> {
+        let addr = parse_addr(leader_addr).map_err(|e| Status::unavailable(e.to_string()))?;
+        self.transport
+            .forward(addr, request)
+            .await
+            .map_err(|e| Status::unavailable(e.to_string()))
+    }
 
-        let mut server_builder = Server::builder();
-
-        #[cfg(feature = "tls")]
-        if let Some(tls_cfg) = &self.config.server_tls_config {
-            logger.debug("TLS enabled.");
-            let cert_path = tls_cfg
-                .cert_path
-                .as_ref()
-                .expect("Server requires cert_path");
-            let cert = tokio::fs::read(cert_path).await?;
-            let key_path = tls_cfg.key_path.as_ref().expect("Server requires key_path");
-            let key = tokio::fs::read(key_path).await?;
-            let identity = Identity::from_pem(cert, key);
-
-            let mut tls_config = ServerTlsConfig::new().identity(identity);
-
-            // mTLS
-            if let Some(ca_cert_path) = &tls_cfg.ca_cert_path {
-                let ca_cert = tokio::fs::read(ca_cert_path).await?;
-                let ca_cert = Certificate::from_pem(ca_cert);
-                tls_config = tls_config.client_ca_root(ca_cert);
+    /// Serves a request forwarded through the transport like the matching RPC.
+    pub(crate) async fn handle_forwarded(
+        &self,
+        request: ForwardedRequest,
+    ) -> Result<ForwardedResponse, Status> {
+        let response = match request {
+            ForwardedRequest::RequestId(args) => ForwardedResponse::RequestId(
+                self.request_id(Request::new(args)).await?.into_inner(),
+            ),
+            ForwardedRequest::ChangeConfig(args) => ForwardedResponse::ChangeConfig(
+                self.change_config(Request::new(args)).await?.into_inner(),
+            ),
+            ForwardedRequest::PromoteLearner(args) => ForwardedResponse::ChangeConfig(
+                self.promote_learner(Request::new(args)).await?.into_inner(),
+            ),
+            ForwardedRequest::Propose(args) => {
+                ForwardedResponse::Propose(self.propose(Request::new(args)).await?.into_inner())
             }
-
-            server_builder = server_builder.tls_config(tls_config)?;
-        }
-
-        server_builder
-            .add_service(RaftServiceServer::new(self))
-            .serve_with_shutdown(raft_addr, quit_signal)
-            .await?;
-
-        Ok(())
+            ForwardedRequest::ReadIndex(args) => ForwardedResponse::ReadIndex(
+                self.read_index(Request::new(args)).await?.into_inner(),
+            ),
+        };
+        Ok(response)
     }
 }
 
@@ -123,6 +119,21 @@ impl<
         FSM: AbstractStateMachine + 'static,
     > RaftServer<LogEntry, LogStorage, FSM>
 {
+    /// Hands a received message over to the RaftNode, through the transport's
+    /// `InboundMessages` once the server runs. Returns false if the RaftNode has shut down.
+    async fn deliver(&self, message: RaftMessage) -> bool {
+        match &self.inbound {
+            Some(inbound) => inbound.deliver(message).await.is_ok(),
+            None => self
+                .tx
+                .send(ServerRequestMsg::SendMessage {
+                    message: Box::new(message),
+                })
+                .await
+                .is_ok(),
+        }
+    }
+
     fn print_send_error(&self, function_name: &str) {
         self.logger.error(&format!(
             "Error occurred in sending message ('RaftServer --> RaftNode'). Function: '{}'",
@@ -176,13 +187,13 @@ impl<
                     }))
                 }
                 RequestIdResponseResult::WrongLeader { leader_addr, .. } => {
-                    let mut client =
-                        create_client(leader_addr, self.config.client_tls_config.clone())
-                            .await
-                            .unwrap();
-                    let reply = client.request_id(request_args).await?.into_inner();
-
-                    Ok(Response::new(reply))
+                    match self
+                        .forward(&leader_addr, ForwardedRequest::RequestId(request_args))
+                        .await?
+                    {
+                        ForwardedResponse::RequestId(reply) => Ok(Response::new(reply)),
+                        _ => unreachable!(),
+                    }
                 }
             },
             _ => unreachable!(),
@@ -220,45 +231,51 @@ impl<
         )
         .await
         {
-            Ok(Ok(raft_response)) => {
-                match raft_response {
-                    ServerResponseMsg::ConfigChange { result } => match result {
-                        ConfChangeResponseResult::JoinSuccess {
-                            assigned_ids,
-                            peers,
-                        } => {
-                            reply.result_type =
-                                raft_service::ChangeConfigResultType::ChangeConfigSuccess as i32;
-                            reply.assigned_ids = assigned_ids;
-                            reply.peers = serialize(&peers).unwrap();
-                        }
-                        ConfChangeResponseResult::RemoveSuccess {} => {
-                            reply.result_type =
-                                raft_service::ChangeConfigResultType::ChangeConfigSuccess as i32;
-                        }
-                        ConfChangeResponseResult::Error(e) => {
-                            reply.result_type =
-                                raft_service::ChangeConfigResultType::ChangeConfigUnknownError
-                                    as i32;
-                            reply.error = e.to_string().as_bytes().to_vec();
-                        }
-                        ConfChangeResponseResult::WrongLeader { leader_addr, .. } => {
-                            reply.result_type =
-                                raft_service::ChangeConfigResultType::ChangeConfigWrongLeader
-                                    as i32;
-
-                            let mut client =
-                                create_client(leader_addr, self.config.client_tls_config.clone())
-                                    .await
-                                    .unwrap();
-                            reply = client.change_config(request_args).await?.into_inner();
-                        }
-                    },
-                    _ => unreachable!(),
-                }
-                reply.result_type =
-                    raft_service::ChangeConfigResultType::ChangeConfigSuccess as i32;
-            }
+            Ok(Ok(raft_response)) => match raft_response {
+                ServerResponseMsg::ConfigChange { result } => match result {
+                    ConfChangeResponseResult::JoinSuccess {
+                        assigned_ids,
+                        peers,
+                    } => {
+                        reply.result_type =
+                            raft_service::ChangeConfigResultType::ChangeConfigSuccess as i32;
+                        reply.assigned_ids = assigned_ids;
+                        reply.peers = serialize(&peers).unwrap();
+                    }
+                    ConfChangeResponseResult::RemoveSuccess {} => {
+                        reply.result_type =
+                            raft_service::ChangeConfigResultType::ChangeConfigSuccess as i32;
+                    }
+                    ConfChangeResponseResult::ReplaceSuccess {
+                        assigned_ids,
+                        removed_ids,
+                        peers,
+                    } => {
+                        reply.result_type =
+                            raft_service::ChangeConfigResultType::ChangeConfigSuccess as i32;
+                        reply.assigned_ids = assigned_ids;
+                        reply.removed_ids = removed_ids;
+                        reply.peers = serialize(&peers).unwrap();
+                    }
+                    ConfChangeResponseResult::Error(e) => {
+                        reply.result_type = conf_change_error_type(&e) as i32;
+                        reply.error = e.to_string().as_bytes().to_vec();
+                    }
+                    ConfChangeResponseResult::WrongLeader { leader_addr, .. } => {
+                        reply.result_type =
+                            raft_service::ChangeConfigResultType::ChangeConfigWrongLeader as i32;
+
+                        reply = match self
+                            .forward(&leader_addr, ForwardedRequest::ChangeConfig(request_args))
+                            .await?
+                        {
+                            ForwardedResponse::ChangeConfig(reply) => reply,
+                            _ => unreachable!(),
+                        };
+                    }
+                },
+                _ => unreachable!(),
+            },
             Ok(Err(e)) => {
                 reply.result_type =
                     raft_service::ChangeConfigResultType::ChangeConfigUnknownError as i32;
@@ -278,15 +295,18 @@ impl<
         Ok(Response::new(reply))
     }
 
-    async fn send_message(
+    async fn promote_learner(
         &self,
-        request: Request<RaftMessage>,
-    ) -> Result<Response<raft_service::Empty>, Status> {
+        request: Request<raft_service::PromoteLearnerArgs>,
+    ) -> Result<Response<raft_service::ChangeConfigResponse>, Status> {
         let request_args = request.into_inner();
         let sender = self.tx.clone();
+        let (tx_msg, rx_msg) = oneshot::channel();
+
         match sender
-            .send(ServerRequestMsg::SendMessage {
-                message: Box::new(request_args),
+            .send(ServerRequestMsg::PromoteLearner {
+                node_id: request_args.node_id,
+                tx_msg,
             })
             .await
         {
@@ -294,6 +314,134 @@ impl<
             Err(_) => self.print_send_error(function_name!()),
         }
 
+        let mut reply = raft_service::ChangeConfigResponse::default();
+        match timeout(
+            Duration::from_secs_f32(self.config.conf_change_request_timeout),
+            rx_msg,
+        )
+        .await
+        {
+            Ok(Ok(raft_response)) => match raft_response {
+                ServerResponseMsg::ConfigChange { result } => match result {
+                    ConfChangeResponseResult::JoinSuccess {
+                        assigned_ids,
+                        peers,
+                    } => {
+                        reply.result_type =
+                            raft_service::ChangeConfigResultType::ChangeConfigSuccess as i32;
+                        reply.assigned_ids = assigned_ids;
+                        reply.peers = serialize(&peers).unwrap();
+                    }
+                    ConfChangeResponseResult::Error(e) => {
+                        reply.result_type = conf_change_error_type(&e) as i32;
+                        reply.error = e.to_string().as_bytes().to_vec();
+                    }
+                    ConfChangeResponseResult::WrongLeader { leader_addr, .. } => {
+                        reply = match self
+                            .forward(&leader_addr, ForwardedRequest::PromoteLearner(request_args))
+                            .await?
+                        {
+                            ForwardedResponse::ChangeConfig(reply) => reply,
+                            _ => unreachable!(),
+                        };
+                    }
+                    _ => unreachable!(),
+                },
+                _ => unreachable!(),
+            },
+            Ok(Err(e)) => {
+                reply.result_type =
+                    raft_service::ChangeConfigResultType::ChangeConfigUnknownError as i32;
+                reply.error = e.to_string().as_bytes().to_vec();
+            }
+            Err(e) => {
+                reply.result_type =
+                    raft_service::ChangeConfigResultType::ChangeConfigTimeoutError as i32;
+                reply.error = e.to_string().as_bytes().to_vec();
+                self.logger.error(&format!(
+                    "Learner promotion timeout! (\"conf_change_request_timeout\" = {})",
+                    self.config.conf_change_request_timeout
+                ));
+            }
+        }
+
+        Ok(Response::new(reply))
+    }
+
+    async fn send_message(
+        &self,
+        request: Request<RaftMessage>,
+    ) -> Result<Response<raft_service::Empty>, Status> {
+        let request_args = request.into_inner();
+        if !self.deliver(request_args).await {
+            self.print_send_error(function_name!());
+        }
+
+        Ok(Response::new(raft_service::Empty {}))
+    }
+
+    type MessageStreamStream = ReceiverStream<Result<raft_service::Empty, Status>>;
+
+    async fn message_stream(
+        &self,
+        request: Request<Streaming<raft_service::MessageBatch>>,
+    ) -> Result<Response<Self::MessageStreamStream>, Status> {
+        let mut stream = request.into_inner();
+        let server = self.clone();
+
+        // The response stream is kept open until the peer closes its side, which keeps the RPC alive.
+        let (tx_response, rx_response) = mpsc::channel(1);
+
+        tokio::spawn(async move {
+            loop {
+                match stream.message().await {
+                    Ok(Some(batch)) => {
+                        // Forwarded one by one in the order the peer sent them
+                        for message in batch.messages {
+                            if !server.deliver(message).await {
+                                server.print_send_error("message_stream");
+                                return;
+                            }
+                        }
+                        // Lets the peer know the batch was handed over, see `GrpcTransport::send_snapshot`
+                        if tx_response.send(Ok(raft_service::Empty {})).await.is_err() {
+                            break;
+                        }
+                    }
+                    Ok(None) => break,
+                    Err(status) => {
+                        let _ = tx_response.send(Err(status)).await;
+                        break;
+                    }
+                }
+            }
+        });
+
+        Ok(Response::new(ReceiverStream::new(rx_response)))
+    }
+
+    async fn install_snapshot(
+        &self,
+        request: Request<Streaming<raft_service::SnapshotChunk>>,
+    ) -> Result<Response<raft_service::Empty>, Status> {
+        let mut stream = request.into_inner();
+        let mut assembler = SnapshotChunkAssembler::new(self.config.max_snapshot_size);
+
+        while let Some(chunk) = stream.message().await? {
+            assembler
+                .push(chunk)
+                .map_err(|e| Status::invalid_argument(e.to_string()))?;
+        }
+
+        // Only hand the snapshot over to the RaftNode once it is complete and verified
+        let message = assembler
+            .finish()
+            .map_err(|e| Status::data_loss(e.to_string()))?;
+
+        if !self.deliver(message).await {
+            self.print_send_error(function_name!());
+        }
+
         Ok(Response::new(raft_service::Empty {}))
     }
 
@@ -308,6 +456,9 @@ impl<
         match sender
             .send(ServerRequestMsg::Propose {
                 proposal: request_args.msg.clone(),
+                timeout: (request_args.timeout_ms > 0)
+                    .then_some(Duration::from_millis(request_args.timeout_ms)),
+                session: request_args.session.clone().map(Into::into),
                 tx_msg,
             })
             .await
@@ -318,34 +469,79 @@ impl<
 
         let response = rx_msg.await.unwrap();
         match response {
-            ServerResponseMsg::Propose { result } => {
-                match result {
-                    ResponseResult::Success => Ok(Response::new(raft_service::ProposeResponse {
+            ServerResponseMsg::Propose { result } => match result {
+                ProposeResponseResult::Success { data } => {
+                    Ok(Response::new(raft_service::ProposeResponse {
+                        data,
                         ..Default::default()
-                    })),
-                    ResponseResult::Error(error) => {
-                        Ok(Response::new(raft_service::ProposeResponse {
-                            error: error.to_string().as_bytes().to_vec(),
-                        }))
+                    }))
+                }
+                ProposeResponseResult::Error(error) => {
+                    Ok(Response::new(raft_service::ProposeResponse {
+                        error: error.to_string().as_bytes().to_vec(),
+                        error_type: error.request_error_type() as i32,
+                        ..Default::default()
+                    }))
+                }
+                ProposeResponseResult::WrongLeader { leader_addr, .. } => {
+                    match self
+                        .forward(&leader_addr, ForwardedRequest::Propose(request_args))
+                        .await?
+                    {
+                        ForwardedResponse::Propose(reply) => Ok(Response::new(reply)),
+                        _ => unreachable!(),
                     }
-                    ResponseResult::WrongLeader { leader_addr, .. } => {
-                        // TODO: Handle this kind of errors
-                        let mut client =
-                            create_client(leader_addr, self.config.client_tls_config.clone())
-                                .await
-                                .unwrap();
-                        let _ = client
-                            .propose(ProposeArgs {
-                                msg: request_args.msg,
-                            })
-                            .await?;
-
-                        Ok(Response::new(raft_service::ProposeResponse {
-                            ..Default::default()
-                        }))
+                }
+            },
+            _ => unreachable!(),
+        }
+    }
+
+    async fn read_index(
+        &self,
+        request: Request<raft_service::ReadIndexArgs>,
+    ) -> Result<Response<raft_service::ReadIndexResponse>, Status> {
+        let request_args = request.into_inner();
+        let sender = self.tx.clone();
+        let (tx_msg, rx_msg) = oneshot::channel();
+
+        match sender
+            .send(ServerRequestMsg::ReadIndex {
+                lease_based: request_args.lease_based,
+                tx_msg,
+            })
+            .await
+        {
+            Ok(_) => (),
+            Err(_) => self.print_send_error(function_name!()),
+        }
+
+        let response = rx_msg.await.unwrap();
+        match response {
+            ServerResponseMsg::ReadIndex { result } => match result {
+                ReadIndexResponseResult::Success { index } => {
+                    Ok(Response::new(raft_service::ReadIndexResponse {
+                        index,
+                        ..Default::default()
+                    }))
+                }
+                ReadIndexResponseResult::Error(error) => {
+                    Ok(Response::new(raft_service::ReadIndexResponse {
+                        error: error.to_string().as_bytes().to_vec(),
+                        error_type: error.request_error_type() as i32,
+                        ..Default::default()
+                    }))
+                }
+                ReadIndexResponseResult::WrongLeader { leader_addr, .. } => {
+                    match self
+                        .forward(&leader_addr, ForwardedRequest::ReadIndex(request_args))
+                        .await?
+                    {
+                        ForwardedResponse::ReadIndex(reply) => Ok(Response::new(reply)),
+                        _ => unreachable!(),
                     }
                 }
-            }
+            },
             _ => unreachable!(),
         }
     }
@@ -462,3 +658,12 @@ impl<
         }
     }
 }
+
+fn conf_change_error_type(error: &Error) -> raft_service::ChangeConfigResultType {
+    match error {
+        Error::Rejected(_) => raft_service::ChangeConfigResultType::ChangeConfigRejected,
+        Error::NoLeader => raft_service::ChangeConfigResultType::ChangeConfigNoLeader,
+        Error::Timeout => raft_service::ChangeConfigResultType::ChangeConfigTimeoutError,
+        _ => raft_service::ChangeConfigResultType::ChangeConfigUnknownError,
+    }
+}
@@ -0,0 +1,142 @@
+use crate::{
+    raft::{
+        eraftpb::{Entry, EntryType},
+        GetEntriesContext,
+    },
+    raft_node::session::{ProposalContext, SessionTable},
+    AbstractLogEntry, AbstractStateMachine, Config, Error, LogArchive, Result, StableStorage,
+};
+
+/// Where `replay_log` stops.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ReplayTarget {
+    /// Replays every committed entry.
+    Latest,
+    /// Replays the committed entries up to and including the index.
+    Index(u64),
+    /// Replays the committed entries up to the last one of the term.
+    Term(u64),
+}
+
+impl ReplayTarget {
+    fn includes(&self, index: u64, term: u64) -> bool {
+        match self {
+            ReplayTarget::Latest => true,
+            ReplayTarget::Index(target) => index <= *target,
+            ReplayTarget::Term(target) => term <= *target,
+        }
+    }
+}
+
+/// How far `replay_log` brought the state machine.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ReplayOutcome {
+    /// Index of the snapshot the state machine was restored from, 0 if it started empty.
+    pub snapshot_index: u64,
+    /// Index and term of the last replayed entry.
+    pub applied_index: u64,
+    pub applied_term: u64,
+    /// Number of entries passed to the state machine.
+    pub applied_entries: u64,
+}
+
+/// Rebuilds the state of `fsm` at `target` from a node's log, e.g. to debug state divergence offline.
+///
+/// Restores the latest snapshot if it doesn't go past `target`, otherwise starts from an empty
+/// state machine. Then applies the committed normal entries following it, reading the ones
+/// compacted out of `storage` from `archive`. Retried proposals are applied at most once,
+/// like on a running node, so `config` should match the node's config.
+pub async fn replay_log<
+    LogEntry: AbstractLogEntry,
+    LogStorage: StableStorage,
+    FSM: AbstractStateMachine,
+>(
+    storage: &LogStorage,
+    archive: Option<&LogArchive>,
+    fsm: &mut FSM,
+    target: ReplayTarget,
+    config: &Config,
+) -> Result<ReplayOutcome> {
+    let mut outcome = ReplayOutcome::default();
+    let mut sessions = SessionTable::default();
+
+    let snapshot = storage.snapshot(0, 0)?;
+    let metadata = snapshot.get_metadata();
+    if metadata.index > 0 && target.includes(metadata.index, metadata.term) {
+        if !snapshot.get_data().is_empty() {
+            let (snapshot_sessions, fsm_data) = SessionTable::split_snapshot(snapshot.get_data())?;
+            sessions = snapshot_sessions;
+            fsm.restore(fsm_data.to_vec()).await?;
+        }
+        outcome.snapshot_index = metadata.index;
+        outcome.applied_index = metadata.index;
+        outcome.applied_term = metadata.term;
+    }
+
+    let next_index = outcome.applied_index + 1;
+    let first_index = storage.first_index()?;
+    let committed = storage.hard_state()?.commit.min(storage.last_index()?);
+
+    let mut entries = vec![];
+    if next_index < first_index {
+        let archived = match archive {
+            Some(archive) => archive.read(next_index, first_index)?,
+            None => vec![],
+        };
+        if archived.first().map(|entry| entry.index) != Some(next_index)
+            || archived.len() as u64 != first_index - next_index
+        {
+            return Err(Error::Other(
+                format!(
+                    "Entries {next_index} to {} are compacted and missing from the archive",
+                    first_index - 1
+                )
+                .into(),
+            ));
+        }
+        entries.extend(archived);
+    }
+    if committed >= next_index.max(first_index) {
+        entries.extend(storage.entries(
+            next_index.max(first_index),
+            committed + 1,
+            None,
+            GetEntriesContext::empty(false),
+        )?);
+    }
+
+    let max_client_sessions = config.max_client_sessions as usize;
+    for entry in entries {
+        if !target.includes(entry.index, entry.term) {
+            break;
+        }
+
+        if entry.get_entry_type() == EntryType::EntryNormal && !entry.get_data().is_empty() {
+            apply_entry::<LogEntry, _>(fsm, &mut sessions, &entry, max_client_sessions).await?;
+            outcome.applied_entries += 1;
+        }
+        outcome.applied_index = entry.index;
+        outcome.applied_term = entry.term;
+    }
+
+    Ok(outcome)
+}
+
+async fn apply_entry<LogEntry: AbstractLogEntry, FSM: AbstractStateMachine>(
+    fsm: &mut FSM,
+    sessions: &mut SessionTable,
+    entry: &Entry,
+    max_client_sessions: usize,
+) -> Result<()> {
+    let session = ProposalContext::decode(entry.get_context())?.session;
+    sessions
+        .apply::<LogEntry, _>(
+            fsm,
+            session.as_ref(),
+            entry.index,
+            entry.get_data().to_vec(),
+            max_client_sessions,
+        )
+        .await?;
+    Ok(())
+}
@@ -1,11 +1,11 @@
-use std::{collections::HashMap, net::SocketAddr};
+use std::{collections::HashMap, net::SocketAddr, time::Duration};
 
 use crate::{raft::eraftpb::Message as RaftMessage, StableStorage};
 use tokio::sync::oneshot::Sender;
 
 use crate::{
-    response::local_response_message::LocalResponseMsg, AbstractLogEntry, AbstractStateMachine,
-    ClusterJoinTicket, InitialRole,
+    raft_node::session::ClientRequest, response::local_response_message::LocalResponseMsg,
+    AbstractLogEntry, AbstractStateMachine, ClusterJoinTicket, InitialRole,
 };
 
 use super::common::confchange_request::ConfChangeRequest;
@@ -70,22 +70,35 @@ pub enum LocalRequestMsg<
         tx_msg: Sender<LocalResponseMsg<LogEntry, LogStorage, FSM>>,
     },
     MakeSnapshot {
-        index: u64,
-        term: u64,
         tx_msg: Sender<LocalResponseMsg<LogEntry, LogStorage, FSM>>,
     },
     Propose {
         proposal: Vec<u8>,
+        /// Overrides `Config::proposal_timeout`.
+        timeout: Option<Duration>,
+        session: Option<ClientRequest>,
         tx_msg: Sender<LocalResponseMsg<LogEntry, LogStorage, FSM>>,
     },
     ChangeConfig {
         conf_change: ConfChangeRequest,
         tx_msg: Sender<LocalResponseMsg<LogEntry, LogStorage, FSM>>,
     },
+    PromoteLearner {
+        node_id: u64,
+        tx_msg: Sender<LocalResponseMsg<LogEntry, LogStorage, FSM>>,
+    },
     SendMessage {
         message: Box<RaftMessage>,
         tx_msg: Sender<LocalResponseMsg<LogEntry, LogStorage, FSM>>,
     },
+    ReadIndex {
+        lease_based: bool,
+        tx_msg: Sender<LocalResponseMsg<LogEntry, LogStorage, FSM>>,
+    },
+    WaitForApplied {
+        index: u64,
+        tx_msg: Sender<LocalResponseMsg<LogEntry, LogStorage, FSM>>,
+    },
     JoinCluster {
         tickets: Vec<ClusterJoinTicket>,
         tx_msg: Sender<LocalResponseMsg<LogEntry, LogStorage, FSM>>,
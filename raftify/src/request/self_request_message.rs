@@ -3,4 +3,6 @@
 #[derive(Debug)]
 pub enum SelfMessage {
     ReportUnreachable { node_id: u64 },
+    ReportSnapshotFailure { node_id: u64 },
+    ReportSnapshotFinish { node_id: u64 },
 }
@@ -1,10 +1,11 @@
-use std::marker::PhantomData;
+use std::{marker::PhantomData, time::Duration};
 
 use tokio::sync::oneshot::Sender;
 
 use crate::{
-    raft::eraftpb::Message as RaftMessage, response::server_response_message::ServerResponseMsg,
-    AbstractLogEntry, AbstractStateMachine, Peers, StableStorage,
+    raft::eraftpb::Message as RaftMessage, raft_node::session::ClientRequest,
+    response::server_response_message::ServerResponseMsg, AbstractLogEntry, AbstractStateMachine,
+    Peers, StableStorage,
 };
 
 use super::common::confchange_request::ConfChangeRequest;
@@ -22,12 +23,19 @@ pub enum ServerRequestMsg<
     },
     Propose {
         proposal: Vec<u8>,
+        /// Overrides `Config::proposal_timeout`.
+        timeout: Option<Duration>,
+        session: Option<ClientRequest>,
         tx_msg: Sender<ServerResponseMsg>,
     },
     ChangeConfig {
         conf_change: ConfChangeRequest,
         tx_msg: Sender<ServerResponseMsg>,
     },
+    PromoteLearner {
+        node_id: u64,
+        tx_msg: Sender<ServerResponseMsg>,
+    },
     DebugNode {
         tx_msg: Sender<ServerResponseMsg>,
     },
@@ -47,6 +55,10 @@ pub enum ServerRequestMsg<
     CreateSnapshot {
         tx_msg: Sender<ServerResponseMsg>,
     },
+    ReadIndex {
+        lease_based: bool,
+        tx_msg: Sender<ServerResponseMsg>,
+    },
     _Phantom(PhantomData<LogEntry>),
     _Phantom2(PhantomData<FSM>),
     _Phantom3(PhantomData<LogStorage>),
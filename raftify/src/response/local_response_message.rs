@@ -6,7 +6,9 @@ use tokio::sync::Mutex;
 use crate::{AbstractLogEntry, AbstractStateMachine, Peers};
 
 use super::{
-    server_response_message::{ConfChangeResponseResult, ResponseResult},
+    server_response_message::{
+        ConfChangeResponseResult, ProposeResponseResult, ReadIndexResponseResult,
+    },
     ResponseMessage,
 };
 
@@ -56,11 +58,14 @@ pub enum LocalResponseMsg<
 
     // Rerouting available
     Propose {
-        result: ResponseResult,
+        result: ProposeResponseResult,
     },
     ConfigChange {
         result: ConfChangeResponseResult,
     },
+    ReadIndex {
+        result: ReadIndexResponseResult,
+    },
 }
 
 impl<LogEntry: AbstractLogEntry, LogStorage: StableStorage, FSM: AbstractStateMachine> fmt::Debug
@@ -9,6 +9,20 @@ pub enum ResponseResult {
     WrongLeader { leader_id: u64, leader_addr: String },
 }
 
+#[derive(Debug)]
+pub enum ProposeResponseResult {
+    Success { data: Vec<u8> },
+    Error(Error),
+    WrongLeader { leader_id: u64, leader_addr: String },
+}
+
+#[derive(Debug)]
+pub enum ReadIndexResponseResult {
+    Success { index: u64 },
+    Error(Error),
+    WrongLeader { leader_id: u64, leader_addr: String },
+}
+
 #[derive(Debug)]
 pub enum ConfChangeResponseResult {
     JoinSuccess {
@@ -16,6 +30,12 @@ pub enum ConfChangeResponseResult {
         peers: Peers,
     },
     RemoveSuccess,
+    /// Nodes were added and removed by the same joint consensus change.
+    ReplaceSuccess {
+        assigned_ids: Vec<u64>,
+        removed_ids: Vec<u64>,
+        peers: Peers,
+    },
     Error(Error),
     WrongLeader {
         leader_id: u64,
@@ -49,9 +69,10 @@ pub enum ServerResponseMsg {
     JoinCluster {},
 
     // Rerouting available
-    Propose { result: ResponseResult },
+    Propose { result: ProposeResponseResult },
     ConfigChange { result: ConfChangeResponseResult },
     RequestId { result: RequestIdResponseResult },
+    ReadIndex { result: ReadIndexResponseResult },
 }
 
 impl<LogEntry: AbstractLogEntry, LogStorage: StableStorage, FSM: AbstractStateMachine>
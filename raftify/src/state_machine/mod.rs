@@ -1,13 +1,68 @@
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 use tonic::async_trait;
 
-use crate::Result;
+use crate::{AbstractLogEntry, Result};
+
+/// Destination of a streamed state machine snapshot.
+pub type SnapshotWriter = dyn AsyncWrite + Send + Unpin;
+/// Source of a streamed state machine snapshot.
+pub type SnapshotReader = dyn AsyncRead + Send + Unpin;
+
+/// Result of applying a log entry.
+/// It reaches the proposer as the bytes `AbstractStateMachine::apply` returns.
+pub trait AbstractOutput: Send + Sized {
+    fn encode(&self) -> Result<Vec<u8>>;
+    fn decode(bytes: &[u8]) -> Result<Self>;
+}
+
+/// Raw bytes, for state machines that don't need a typed output.
+impl AbstractOutput for Vec<u8> {
+    fn encode(&self) -> Result<Vec<u8>> {
+        Ok(self.clone())
+    }
+
+    fn decode(bytes: &[u8]) -> Result<Self> {
+        Ok(bytes.to_vec())
+    }
+}
 
 #[async_trait]
 pub trait AbstractStateMachine: Clone + Send + Sync {
+    /// Returned by `apply_entry` and `RaftNode::propose_entry`.
+    /// State machines written before it was added can set it to `Vec<u8>`.
+    type Output: AbstractOutput;
+
     async fn apply(&mut self, log_entry: Vec<u8>) -> Result<Vec<u8>>;
+
+    /// Typed version of `apply`, called for every committed entry.
+    /// By default it encodes the entry, applies it and decodes the output of `apply`.
+    async fn apply_entry<LogEntry: AbstractLogEntry>(
+        &mut self,
+        log_entry: LogEntry,
+    ) -> Result<Self::Output> {
+        let output = self.apply(log_entry.encode()?).await?;
+        Self::Output::decode(&output)
+    }
+
     async fn snapshot(&self) -> Result<Vec<u8>>;
     async fn restore(&mut self, snapshot: Vec<u8>) -> Result<()>;
 
+    /// Streams the snapshot into `writer`.
+    /// By default it writes the output of `snapshot`, override it to avoid holding the whole state in memory.
+    async fn snapshot_to(&self, writer: &mut SnapshotWriter) -> Result<()> {
+        let snapshot = self.snapshot().await?;
+        writer.write_all(&snapshot).await?;
+        Ok(())
+    }
+
+    /// Restores the state machine from a snapshot streamed from `reader`.
+    /// By default it reads the whole snapshot and passes it to `restore`.
+    async fn restore_from(&mut self, reader: &mut SnapshotReader) -> Result<()> {
+        let mut snapshot = Vec::new();
+        reader.read_to_end(&mut snapshot).await?;
+        self.restore(snapshot).await
+    }
+
     fn encode(&self) -> Result<Vec<u8>>;
     fn decode(bytes: &[u8]) -> Result<Self>
     where
@@ -1,4 +1,5 @@
 pub const SNAPSHOT_KEY: &str = "snapshot";
+pub const SNAPSHOT_FILE_KEY: &str = "snapshot_file";
 pub const LAST_INDEX_KEY: &str = "last_index";
 pub const HARD_STATE_KEY: &str = "hard_state";
 pub const CONF_STATE_KEY: &str = "conf_state";
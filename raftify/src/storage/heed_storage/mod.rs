@@ -2,24 +2,27 @@ mod codec;
 mod constant;
 
 use self::codec::{format_entry_key_string, HeedEntry, HeedEntryKeyString};
-use super::{utils::append_compacted_logs, StableStorage, StorageType};
+use super::{
+    log_archive::LogArchive,
+    snapshot_files::{snapshot_unavailable, SnapshotFileMeta, SnapshotFiles},
+    StableStorage, StorageType,
+};
 use crate::{
     config::Config,
-    error::Result,
+    error::{DatabaseError, Error, Result},
     raft::{self, prelude::*, GetEntriesContext},
 };
 use bincode::{deserialize, serialize};
-use constant::{CONF_STATE_KEY, HARD_STATE_KEY, LAST_INDEX_KEY, SNAPSHOT_KEY};
+use constant::{CONF_STATE_KEY, HARD_STATE_KEY, LAST_INDEX_KEY, SNAPSHOT_FILE_KEY, SNAPSHOT_KEY};
 use heed::{
     types::{Bytes as HeedBytes, Str as HeedStr},
-    Database, Env,
+    Database, Env, EnvFlags,
 };
 use parking_lot::{RwLock, RwLockReadGuard, RwLockWriteGuard};
 use prost::Message as PMessage;
 use raft::{logger::Logger, util::limit_size};
 use std::{
     cmp::max,
-    fs,
     path::{Path, PathBuf},
     sync::Arc,
 };
@@ -37,6 +40,19 @@ impl HeedStorage {
         )?))))
     }
 
+    /// Opens an existing storage without writing to it, e.g. to inspect the log of a node.
+    pub fn open_readonly(
+        log_dir_path: &str,
+        config: &Config,
+        logger: Arc<dyn Logger>,
+    ) -> Result<Self> {
+        Ok(Self(Arc::new(RwLock::new(HeedStorageCore::open_readonly(
+            Path::new(log_dir_path).to_path_buf(),
+            config,
+            logger,
+        )?))))
+    }
+
     fn wl(&mut self) -> RwLockWriteGuard<HeedStorageCore> {
         self.0.write()
     }
@@ -118,12 +134,13 @@ impl StableStorage for HeedStorage {
         meta.index = index;
         meta.term = term;
 
-        store.set_snapshot(&mut writer, &snapshot)?;
+        let snapshot_file = store.set_snapshot(&mut writer, &snapshot)?;
         writer.commit()?;
+        store.snapshot_files.remove_stale(Some(&snapshot_file))?;
         Ok(())
     }
 
-    fn apply_snapshot(&mut self, snapshot: Snapshot) -> Result<()> {
+    fn apply_snapshot(&mut self, snapshot: &Snapshot) -> Result<()> {
         let store = self.wl();
         let mut writer = store.env.write_txn()?;
         let metadata = snapshot.get_metadata();
@@ -144,8 +161,9 @@ impl StableStorage for HeedStorage {
         store.set_hard_state(&mut writer, &hard_state)?;
         store.set_conf_state(&mut writer, conf_state)?;
         store.set_last_index(&mut writer, metadata.index)?;
-        store.set_snapshot(&mut writer, &snapshot)?;
+        let snapshot_file = store.set_snapshot(&mut writer, snapshot)?;
         writer.commit()?;
+        store.snapshot_files.remove_stale(Some(&snapshot_file))?;
         Ok(())
     }
 
@@ -158,6 +176,41 @@ impl StableStorage for HeedStorage {
             .map_err(|e| raft::Error::Store(raft::StorageError::Other(e.into())))?;
         Ok(entries)
     }
+
+    fn snapshot_staging_path(&self) -> Option<PathBuf> {
+        Some(self.rl().snapshot_files.staging_path())
+    }
+
+    fn create_snapshot_from_file(&mut self, path: &Path, index: u64, term: u64) -> Result<()> {
+        let store = self.wl();
+        let mut writer = store.env.write_txn()?;
+        let conf_state = store.conf_state(&writer)?;
+
+        let mut metadata = SnapshotMetadata::default();
+        metadata.set_conf_state(conf_state);
+        metadata.index = index;
+        metadata.term = term;
+
+        let snapshot_file = store.snapshot_files.adopt(path, index, term)?;
+        store.set_snapshot_metadata(&mut writer, &metadata, &snapshot_file)?;
+        writer.commit()?;
+        store.snapshot_files.remove_stale(Some(&snapshot_file))?;
+        Ok(())
+    }
+
+    fn snapshot_data_path(&self) -> Result<Option<PathBuf>> {
+        let store = self.rl();
+        let reader = store.env.read_txn()?;
+
+        match store.snapshot_file(&reader)? {
+            Some(snapshot_file) => Ok(Some(store.snapshot_files.verified_path(&snapshot_file)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn take_corrupted_snapshot(&self) -> Option<(u64, u64)> {
+        self.rl().snapshot_files.take_corrupted()
+    }
 }
 
 impl Storage for HeedStorage {
@@ -212,9 +265,11 @@ impl Storage for HeedStorage {
             .first_index(&reader)
             .map_err(|_| raft::Error::Store(raft::StorageError::Unavailable))?;
 
-        let snapshot = store.snapshot(&reader, 0, 0).unwrap();
-        if snapshot.get_metadata().get_index() == idx {
-            return Ok(snapshot.get_metadata().get_term());
+        let snapshot_metadata = store
+            .snapshot_metadata(&reader)
+            .map_err(|_| raft::Error::Store(raft::StorageError::Unavailable))?;
+        if snapshot_metadata.get_index() == idx {
+            return Ok(snapshot_metadata.get_term());
         }
 
         let entry = store
@@ -257,7 +312,7 @@ impl Storage for HeedStorage {
 
         store
             .snapshot(&reader, request_index, to)
-            .map_err(|_| raft::Error::Store(raft::StorageError::SnapshotTemporarilyUnavailable))
+            .map_err(|e| snapshot_unavailable(e, store.logger.as_ref()))
     }
 }
 
@@ -276,6 +331,7 @@ pub struct HeedStorageCore {
     env: Env,
     entries_db: Database<HeedEntryKeyString, HeedEntry>,
     metadata_db: Database<HeedStr, HeedBytes>,
+    snapshot_files: SnapshotFiles,
     config: Config,
     logger: Arc<dyn Logger>,
 }
@@ -286,7 +342,7 @@ impl HeedStorageCore {
             heed::EnvOpenOptions::new()
                 .map_size(config.lmdb_map_size as usize)
                 .max_dbs(3000)
-                .open(log_dir_path)?
+                .open(&log_dir_path)?
         };
 
         let mut writer = env.write_txn()?;
@@ -302,18 +358,65 @@ impl HeedStorageCore {
             metadata_db,
             entries_db,
             env,
+            snapshot_files: SnapshotFiles::create(&log_dir_path)?,
             logger,
             config: config.clone(),
         };
 
+        // Clean up the snapshot files left behind by a crash between writing a snapshot file and committing its metadata
+        let snapshot_file = storage.snapshot_file(&storage.env.read_txn()?)?;
+        storage
+            .snapshot_files
+            .remove_stale(snapshot_file.as_ref())?;
+
         Ok(storage)
     }
 
+    pub fn open_readonly(
+        log_dir_path: PathBuf,
+        config: &Config,
+        logger: Arc<dyn Logger>,
+    ) -> Result<Self> {
+        let env = unsafe {
+            heed::EnvOpenOptions::new()
+                .map_size(config.lmdb_map_size as usize)
+                .max_dbs(3000)
+                .flags(EnvFlags::READ_ONLY)
+                .open(&log_dir_path)?
+        };
+
+        let reader = env.read_txn()?;
+        let entries_db: Database<HeedEntryKeyString, HeedEntry> = env
+            .open_database(&reader, Some("entries"))?
+            .ok_or_else(|| DatabaseError::Corrupted("The entries database is missing".into()))?;
+        let metadata_db: Database<HeedStr, HeedBytes> =
+            env.open_database(&reader, Some("meta"))?
+                .ok_or_else(|| DatabaseError::Corrupted("The meta database is missing".into()))?;
+        reader.commit()?;
+
+        Ok(Self {
+            metadata_db,
+            entries_db,
+            env,
+            snapshot_files: SnapshotFiles::open(&log_dir_path),
+            logger,
+            config: config.clone(),
+        })
+    }
+
     pub fn compact(&self, writer: &mut heed::RwTxn, index: u64) -> Result<()> {
-        // TODO, check that compaction is legal
-        //let last_index = self.last_index(&writer)?;
-        // there should always be at least one entry in the log
-        //assert!(last_index > index + 1);
+        // Entries not appended yet can't be compacted
+        let last_index = self.last_index(writer)?;
+        if index > last_index + 1 {
+            return Err(Error::Other(
+                format!("Compaction index {index} is beyond the last index {last_index}").into(),
+            ));
+        }
+
+        // Already compacted
+        if index <= self.first_index(writer)? {
+            return Ok(());
+        }
 
         let index = format_entry_key_string(index.to_string().as_str());
 
@@ -377,12 +480,62 @@ impl HeedStorageCore {
         }
     }
 
-    fn set_snapshot(&self, writer: &mut heed::RwTxn, snapshot: &Snapshot) -> Result<()> {
-        self.metadata_db
-            .put(writer, SNAPSHOT_KEY, snapshot.encode_to_vec().as_slice())?;
+    /// Writes the snapshot data to a snapshot file and keeps only the metadata in the DB.
+    /// The returned file should be kept when cleaning up stale snapshot files after the commit.
+    fn set_snapshot(
+        &self,
+        writer: &mut heed::RwTxn,
+        snapshot: &Snapshot,
+    ) -> Result<SnapshotFileMeta> {
+        let metadata = snapshot.get_metadata();
+        let snapshot_file =
+            self.snapshot_files
+                .write(metadata.index, metadata.term, snapshot.get_data())?;
+        self.set_snapshot_metadata(writer, metadata, &snapshot_file)?;
+        Ok(snapshot_file)
+    }
+
+    fn set_snapshot_metadata(
+        &self,
+        writer: &mut heed::RwTxn,
+        metadata: &SnapshotMetadata,
+        snapshot_file: &SnapshotFileMeta,
+    ) -> Result<()> {
+        let mut metadata_only = Snapshot::default();
+        metadata_only.set_metadata(metadata.clone());
+
+        self.metadata_db.put(
+            writer,
+            SNAPSHOT_KEY,
+            metadata_only.encode_to_vec().as_slice(),
+        )?;
+        self.metadata_db.put(
+            writer,
+            SNAPSHOT_FILE_KEY,
+            serialize(snapshot_file)?.as_slice(),
+        )?;
         Ok(())
     }
 
+    fn snapshot_file(&self, reader: &heed::RoTxn) -> Result<Option<SnapshotFileMeta>> {
+        let snapshot_file = self.metadata_db.get(reader, SNAPSHOT_FILE_KEY)?;
+
+        match snapshot_file {
+            Some(snapshot_file) => Ok(Some(deserialize(snapshot_file)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Returns the snapshot metadata without loading the snapshot data.
+    pub fn snapshot_metadata(&self, reader: &heed::RoTxn) -> Result<SnapshotMetadata> {
+        let snapshot = self.metadata_db.get(reader, SNAPSHOT_KEY)?;
+
+        Ok(match snapshot {
+            Some(snapshot) => Snapshot::decode(snapshot)?.metadata.unwrap_or_default(),
+            None => SnapshotMetadata::default(),
+        })
+    }
+
     pub fn snapshot(
         &self,
         reader: &heed::RoTxn,
@@ -391,10 +544,17 @@ impl HeedStorageCore {
     ) -> Result<Snapshot> {
         let snapshot = self.metadata_db.get(reader, SNAPSHOT_KEY)?;
 
-        Ok(match snapshot {
+        let mut snapshot = match snapshot {
             Some(snapshot) => Snapshot::decode(snapshot)?,
-            None => Snapshot::default(),
-        })
+            None => return Ok(Snapshot::default()),
+        };
+
+        // Snapshots written by older versions keep their data inline and have no snapshot file
+        if let Some(snapshot_file) = self.snapshot_file(reader)? {
+            snapshot.set_data(self.snapshot_files.read(&snapshot_file)?);
+        }
+
+        Ok(snapshot)
     }
 
     fn last_index(&self, reader: &heed::RoTxn) -> Result<u64> {
@@ -419,9 +579,8 @@ impl HeedStorageCore {
         match self.entries_db.first(reader)? {
             Some((index, _first_entry)) => Ok(index.parse::<u64>().unwrap()),
             None => {
-                // TODO: Pass proper arguments after handling snapshot's `request_index` and `to`
-                let snapshot = self.snapshot(reader, 0, 0)?;
-                Ok(snapshot.get_metadata().get_index() + 1)
+                let snapshot_metadata = self.snapshot_metadata(reader)?;
+                Ok(snapshot_metadata.get_index() + 1)
             }
         }
     }
@@ -504,24 +663,12 @@ impl HeedStorageCore {
     }
 
     fn save_compacted_entries(&self, entries: &[Entry]) -> Result<()> {
-        let compacted_log_dir_path = Path::new(&self.config.compacted_log_dir);
-        let compacted_log_dir_path = compacted_log_dir_path.to_str().unwrap();
-        let dest_path = format!("{}/compacted_logs.json", compacted_log_dir_path);
-
-        match fs::metadata(&dest_path) {
-            Ok(metadata) if metadata.len() > self.config.compacted_log_size_threshold => {
-                self.logger.debug(
-                    "Compacted log size is over threshold. Removing all previous compacted logs.",
-                );
-                fs::remove_file(&dest_path)?;
-            }
-            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
-            Err(e) => return Err(e.into()),
-            _ => {}
-        }
-
-        append_compacted_logs(Path::new(&dest_path), entries)?;
-        Ok(())
+        LogArchive::open(Path::new(&self.config.compacted_log_dir))
+            .with_limits(
+                self.config.compacted_log_segment_size,
+                self.config.compacted_log_size_threshold,
+            )
+            .append(entries)
     }
 
     // Test only
@@ -546,6 +693,7 @@ impl HeedStorageCore {
 mod test {
     use std::fs;
     use std::panic::{self, AssertUnwindSafe};
+    use std::path::Path;
     use std::sync::Arc;
 
     use crate::config::config_builder::ConfigBuilder;
@@ -555,7 +703,8 @@ mod test {
         logger::Slogger,
         Config as RaftConfig, Error as RaftError, GetEntriesContext, Storage, StorageError,
     };
-    use crate::{Config, HeedStorage, StableStorage};
+    use crate::storage::snapshot_files::SNAPSHOT_DIR_NAME;
+    use crate::{Config, HeedStorage, LogArchive, StableStorage};
     use prost::Message;
 
     fn new_entry(index: u64, term: u64) -> Entry {
@@ -819,6 +968,44 @@ mod test {
         teardown(tempdir);
     }
 
+    #[test]
+    fn test_storage_compact_beyond_last_index() {
+        let tempdir = setup();
+        let cfg = build_config(&tempdir);
+        let logger = Arc::new(Slogger {
+            slog: build_logger(),
+        });
+
+        let ents = vec![new_entry(3, 3), new_entry(4, 4), new_entry(5, 5)];
+        let mut storage = HeedStorage::create(&tempdir, &cfg, logger).unwrap();
+        storage.replace_entries(&ents).unwrap();
+
+        assert!(storage.compact(7).is_err());
+        assert_eq!(storage.first_index(), Ok(3));
+
+        teardown(tempdir);
+    }
+
+    #[test]
+    fn test_storage_compact_archives_entries() {
+        let tempdir = setup();
+        let mut cfg = build_config(&tempdir);
+        cfg.save_compacted_logs = true;
+        let logger = Arc::new(Slogger {
+            slog: build_logger(),
+        });
+
+        let ents = vec![new_entry(3, 3), new_entry(4, 4), new_entry(5, 5)];
+        let mut storage = HeedStorage::create(&tempdir, &cfg, logger).unwrap();
+        storage.replace_entries(&ents).unwrap();
+
+        storage.compact(5).unwrap();
+        let archive = LogArchive::open(Path::new(&tempdir));
+        assert_eq!(archive.read(0, 10).unwrap(), ents[..2]);
+
+        teardown(tempdir);
+    }
+
     // TODO: Support the below test case
     // #[test]
     // fn test_storage_create_snapshot() {
@@ -946,7 +1133,7 @@ mod test {
 
         // Apply snapshot successfully
         let snap = new_snapshot(4, 4, nodes.clone());
-        storage.apply_snapshot(snap).unwrap();
+        storage.apply_snapshot(&snap).unwrap();
 
         // Apply snapshot fails due to StorageError::SnapshotOutOfDate
         // TODO: Support the below test case
@@ -955,4 +1142,56 @@ mod test {
 
         teardown(tempdir);
     }
+
+    #[test]
+    fn test_storage_snapshot_data_in_file() {
+        let tempdir = setup();
+        let cfg = build_config(&tempdir);
+        let logger = Arc::new(Slogger {
+            slog: build_logger(),
+        });
+
+        let mut storage = HeedStorage::create(&tempdir, &cfg, logger.clone()).unwrap();
+        storage.create_snapshot(b"first".to_vec(), 4, 4).unwrap();
+        storage.create_snapshot(b"second".to_vec(), 6, 5).unwrap();
+
+        let snapshot = storage.snapshot(0, 0).unwrap();
+        assert_eq!(snapshot.get_data(), b"second");
+        assert_eq!(snapshot.get_metadata().get_index(), 6);
+        assert_eq!(storage.term(6), Ok(5));
+
+        // Only the latest snapshot file is kept
+        let snapshot_dir = Path::new(&tempdir).join(SNAPSHOT_DIR_NAME);
+        assert_eq!(fs::read_dir(&snapshot_dir).unwrap().count(), 1);
+
+        // Corrupted snapshot files are detected on load
+        let snapshot_file = fs::read_dir(&snapshot_dir)
+            .unwrap()
+            .next()
+            .unwrap()
+            .unwrap()
+            .path();
+        fs::write(&snapshot_file, b"corrupted").unwrap();
+        assert_eq!(
+            storage.snapshot(0, 0),
+            Err(RaftError::Store(
+                StorageError::SnapshotTemporarilyUnavailable
+            ))
+        );
+
+        // and withheld without being read again, until a new snapshot replaces them
+        fs::remove_file(&snapshot_file).unwrap();
+        assert_eq!(
+            storage.snapshot(0, 0),
+            Err(RaftError::Store(
+                StorageError::SnapshotTemporarilyUnavailable
+            ))
+        );
+        assert_eq!(storage.take_corrupted_snapshot(), Some((6, 5)));
+        assert_eq!(storage.take_corrupted_snapshot(), None);
+        storage.create_snapshot(b"third".to_vec(), 8, 5).unwrap();
+        assert_eq!(storage.snapshot(0, 0).unwrap().get_data(), b"third");
+
+        teardown(tempdir);
+    }
 }
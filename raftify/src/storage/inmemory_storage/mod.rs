@@ -74,7 +74,7 @@ impl StableStorage for MemStorage {
         Ok(())
     }
 
-    fn apply_snapshot(&mut self, snapshot: Snapshot) -> Result<()> {
+    fn apply_snapshot(&mut self, snapshot: &Snapshot) -> Result<()> {
         let mut store = self.core.wl();
 
         // Pass apply snapshot if the snapshot is empty
@@ -83,7 +83,10 @@ impl StableStorage for MemStorage {
             return Ok(());
         }
 
-        store.apply_snapshot(snapshot)?;
+        // The in-memory log only keeps the metadata of an applied snapshot.
+        let mut metadata_only = Snapshot::default();
+        metadata_only.set_metadata(snapshot.get_metadata().clone());
+        store.apply_snapshot(metadata_only)?;
         Ok(())
     }
 
@@ -0,0 +1,430 @@
+use prost::Message as PMessage;
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, BufReader, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    error::{Error, Result},
+    raft::eraftpb::Entry,
+};
+
+pub const ARCHIVE_DIR_NAME: &str = "compacted_logs";
+const SEGMENT_FILE_PREFIX: &str = "segment-";
+const SEGMENT_FILE_EXTENSION: &str = "log";
+const INDEX_FILE_EXTENSION: &str = "idx";
+/// Length and crc32 of the encoded entry, both little endian u32.
+const RECORD_HEADER_SIZE: usize = 8;
+/// Index and segment offset of an entry, both little endian u64.
+const INDEX_RECORD_SIZE: usize = 16;
+
+/// Append-only archive of compacted log entries.
+///
+/// Entries are written to segment files as length-prefixed, checksummed protobuf records.
+/// Each segment is named after its first index and has an index file mapping the indexes
+/// it holds to their offsets, so that a range can be read without scanning older segments.
+/// Once a segment outgrows `max_segment_size` a new one is started, and the oldest segments
+/// are removed while the archive is larger than `max_archive_size`.
+pub struct LogArchive {
+    dir: PathBuf,
+    max_segment_size: u64,
+    max_archive_size: u64,
+}
+
+impl LogArchive {
+    /// Opens the archive kept in `compacted_log_dir`. The directory is only created on the first append.
+    pub fn open(compacted_log_dir: &Path) -> Self {
+        Self {
+            dir: compacted_log_dir.join(ARCHIVE_DIR_NAME),
+            max_segment_size: 64 * 1024 * 1024,
+            max_archive_size: u64::MAX,
+        }
+    }
+
+    pub fn with_limits(mut self, max_segment_size: u64, max_archive_size: u64) -> Self {
+        self.max_segment_size = max_segment_size;
+        self.max_archive_size = max_archive_size;
+        self
+    }
+
+    fn segment_path(&self, first_index: u64) -> PathBuf {
+        // Zero padded so that the file names sort in index order
+        self.dir.join(format!(
+            "{}{:020}.{}",
+            SEGMENT_FILE_PREFIX, first_index, SEGMENT_FILE_EXTENSION
+        ))
+    }
+
+    /// First indexes of the segments in the archive, in ascending order.
+    fn segments(&self) -> Result<Vec<u64>> {
+        let dir_entries = match fs::read_dir(&self.dir) {
+            Ok(dir_entries) => dir_entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut segments = vec![];
+        for dir_entry in dir_entries {
+            let path = dir_entry?.path();
+            if path.extension().and_then(|ext| ext.to_str()) != Some(SEGMENT_FILE_EXTENSION) {
+                continue;
+            }
+
+            let first_index = path
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .and_then(|stem| stem.strip_prefix(SEGMENT_FILE_PREFIX))
+                .and_then(|index| index.parse::<u64>().ok());
+
+            if let Some(first_index) = first_index {
+                segments.push(first_index);
+            }
+        }
+
+        segments.sort_unstable();
+        Ok(segments)
+    }
+
+    /// Reads the index file of a segment, ignoring a partially written trailing record.
+    fn read_index(&self, first_index: u64) -> Result<Vec<(u64, u64)>> {
+        let index_path = self
+            .segment_path(first_index)
+            .with_extension(INDEX_FILE_EXTENSION);
+        let data = match fs::read(index_path) {
+            Ok(data) => data,
+            // The segment was created, but nothing was indexed in it yet
+            Err(e) if e.kind() == io::ErrorKind::NotFound => vec![],
+            Err(e) => return Err(e.into()),
+        };
+
+        Ok(data
+            .chunks_exact(INDEX_RECORD_SIZE)
+            .map(|record| {
+                let (index, offset) = record.split_at(8);
+                (
+                    u64::from_le_bytes(index.try_into().unwrap()),
+                    u64::from_le_bytes(offset.try_into().unwrap()),
+                )
+            })
+            .collect())
+    }
+
+    /// Index of the last archived entry, if any.
+    pub fn last_index(&self) -> Result<Option<u64>> {
+        for first_index in self.segments()?.into_iter().rev() {
+            if let Some((index, _)) = self.read_index(first_index)?.last() {
+                return Ok(Some(*index));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Index of the first archived entry, if any.
+    pub fn first_index(&self) -> Result<Option<u64>> {
+        for first_index in self.segments()? {
+            if let Some((index, _)) = self.read_index(first_index)?.first() {
+                return Ok(Some(*index));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Drops what an interrupted append left in the last segment: a partially written index
+    /// record and the records missing from the index. A segment without indexed entries is removed.
+    fn repair_last_segment(&self) -> Result<()> {
+        let first_index = match self.segments()?.last() {
+            Some(first_index) => *first_index,
+            None => return Ok(()),
+        };
+        let segment_path = self.segment_path(first_index);
+        let index_path = segment_path.with_extension(INDEX_FILE_EXTENSION);
+
+        let index = self.read_index(first_index)?;
+        let last_offset = match index.last() {
+            Some((_, offset)) => *offset,
+            None => {
+                if index_path.exists() {
+                    fs::remove_file(&index_path)?;
+                }
+                fs::remove_file(&segment_path)?;
+                return Ok(());
+            }
+        };
+
+        let index_len = (index.len() * INDEX_RECORD_SIZE) as u64;
+        if file_size(&index_path)? > index_len {
+            OpenOptions::new()
+                .write(true)
+                .open(&index_path)?
+                .set_len(index_len)?;
+        }
+
+        // The segment is synced before the index, so the last indexed record is complete.
+        let mut segment = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(&segment_path)?;
+        segment.seek(SeekFrom::Start(last_offset))?;
+        let mut header = [0u8; RECORD_HEADER_SIZE];
+        segment.read_exact(&mut header)?;
+        let record_len = u32::from_le_bytes(header[..4].try_into().unwrap()) as u64;
+        let segment_len = last_offset + RECORD_HEADER_SIZE as u64 + record_len;
+        if segment.metadata()?.len() > segment_len {
+            segment.set_len(segment_len)?;
+        }
+        Ok(())
+    }
+
+    /// Appends the entries to the last segment, starting a new one if it is full.
+    /// Entries that are already archived are skipped.
+    pub fn append(&self, entries: &[Entry]) -> Result<()> {
+        self.repair_last_segment()?;
+        let last_index = self.last_index()?;
+        let entries = entries
+            .iter()
+            .filter(|entry| match last_index {
+                Some(last_index) => entry.index > last_index,
+                None => true,
+            })
+            .collect::<Vec<_>>();
+
+        let first_entry = match entries.first() {
+            Some(first_entry) => first_entry,
+            None => return Ok(()),
+        };
+        fs::create_dir_all(&self.dir)?;
+
+        let segment_first_index = match self.segments()?.last() {
+            Some(first_index)
+                if fs::metadata(self.segment_path(*first_index))?.len() < self.max_segment_size =>
+            {
+                *first_index
+            }
+            _ => first_entry.index,
+        };
+
+        let segment_path = self.segment_path(segment_first_index);
+        let mut segment = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&segment_path)?;
+        let mut index_file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(segment_path.with_extension(INDEX_FILE_EXTENSION))?;
+
+        let segment_len = segment.metadata()?.len();
+        let mut segment_data = vec![];
+        let mut index_data = vec![];
+        for entry in entries {
+            let data = entry.encode_to_vec();
+            let offset = segment_len + segment_data.len() as u64;
+            index_data.extend(entry.index.to_le_bytes());
+            index_data.extend(offset.to_le_bytes());
+
+            segment_data.extend((data.len() as u32).to_le_bytes());
+            segment_data.extend(crc32fast::hash(&data).to_le_bytes());
+            segment_data.extend(data);
+        }
+
+        // The segment is written first, so an index record never points past its end.
+        segment.write_all(&segment_data)?;
+        segment.sync_data()?;
+        index_file.write_all(&index_data)?;
+        index_file.sync_data()?;
+
+        self.apply_retention()
+    }
+
+    /// Removes the oldest segments while the archive is larger than `max_archive_size`.
+    /// The segment being appended to is always kept.
+    fn apply_retention(&self) -> Result<()> {
+        let segments = self.segments()?;
+        let mut sizes = vec![];
+        for first_index in segments.iter() {
+            let segment_path = self.segment_path(*first_index);
+            let index_path = segment_path.with_extension(INDEX_FILE_EXTENSION);
+            sizes.push(fs::metadata(&segment_path)?.len() + file_size(&index_path)?);
+        }
+
+        let mut archive_size = sizes.iter().sum::<u64>();
+        for (first_index, size) in segments.iter().zip(sizes).take(segments.len() - 1) {
+            if archive_size <= self.max_archive_size {
+                break;
+            }
+
+            let segment_path = self.segment_path(*first_index);
+            fs::remove_file(segment_path.with_extension(INDEX_FILE_EXTENSION))?;
+            fs::remove_file(&segment_path)?;
+            archive_size -= size;
+        }
+        Ok(())
+    }
+
+    /// Reads the archived entries in `[low, high)`.
+    /// Entries already removed by the retention policy are missing from the result.
+    pub fn read(&self, low: u64, high: u64) -> Result<Vec<Entry>> {
+        let segments = self.segments()?;
+        let mut entries = vec![];
+
+        for (i, first_index) in segments.iter().enumerate() {
+            let is_before_low = segments.get(i + 1).is_some_and(|next| *next <= low);
+            if is_before_low || *first_index >= high {
+                continue;
+            }
+
+            let index = self.read_index(*first_index)?;
+            let start = index.partition_point(|(entry_index, _)| *entry_index < low);
+            let (first_needed, offset) = match index.get(start) {
+                Some(record) => *record,
+                None => continue,
+            };
+            if first_needed >= high {
+                continue;
+            }
+
+            let segment_path = self.segment_path(*first_index);
+            let mut segment = BufReader::new(File::open(&segment_path)?);
+            segment.seek(SeekFrom::Start(offset))?;
+
+            for (entry_index, _) in index[start..].iter() {
+                if *entry_index >= high {
+                    break;
+                }
+                entries.push(read_record(&mut segment, &segment_path)?);
+            }
+        }
+
+        Ok(entries)
+    }
+}
+
+fn file_size(path: &Path) -> Result<u64> {
+    match fs::metadata(path) {
+        Ok(metadata) => Ok(metadata.len()),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(0),
+        Err(e) => Err(e.into()),
+    }
+}
+
+fn read_record(segment: &mut impl Read, segment_path: &Path) -> Result<Entry> {
+    let mut header = [0u8; RECORD_HEADER_SIZE];
+    segment.read_exact(&mut header)?;
+    let (len, checksum) = header.split_at(4);
+    let len = u32::from_le_bytes(len.try_into().unwrap());
+    let checksum = u32::from_le_bytes(checksum.try_into().unwrap());
+
+    let mut data = vec![0u8; len as usize];
+    segment.read_exact(&mut data)?;
+    if crc32fast::hash(&data) != checksum {
+        return Err(Error::Other(
+            format!("Archived entry in {} is corrupted", segment_path.display()).into(),
+        ));
+    }
+
+    Ok(Entry::decode(data.as_slice())?)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn new_entry(index: u64, term: u64) -> Entry {
+        let mut entry = Entry::default();
+        entry.index = index;
+        entry.term = term;
+        entry.data = vec![0u8; 64];
+        entry
+    }
+
+    #[test]
+    fn test_log_archive_read_range() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let archive = LogArchive::open(tempdir.path()).with_limits(256, u64::MAX);
+
+        let entries = (1..=20)
+            .map(|index| new_entry(index, 1))
+            .collect::<Vec<_>>();
+        archive.append(&entries[..10]).unwrap();
+        // Already archived entries are skipped.
+        archive.append(&entries[5..]).unwrap();
+
+        assert!(archive.segments().unwrap().len() > 1);
+        assert_eq!(archive.first_index().unwrap(), Some(1));
+        assert_eq!(archive.last_index().unwrap(), Some(20));
+        assert_eq!(archive.read(1, 21).unwrap(), entries);
+        assert_eq!(archive.read(7, 13).unwrap(), entries[6..12]);
+        assert!(archive.read(21, 30).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_log_archive_retention() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let archive = LogArchive::open(tempdir.path()).with_limits(256, 1024);
+
+        let entries = (1..=50)
+            .map(|index| new_entry(index, 1))
+            .collect::<Vec<_>>();
+        for chunk in entries.chunks(5) {
+            archive.append(chunk).unwrap();
+        }
+
+        // The oldest segments were removed, the remaining entries are still contiguous.
+        let first_index = archive.first_index().unwrap().unwrap();
+        assert!(first_index > 1);
+        assert_eq!(archive.last_index().unwrap(), Some(50));
+        assert_eq!(
+            archive.read(1, 51).unwrap(),
+            entries[first_index as usize - 1..]
+        );
+    }
+
+    #[test]
+    fn test_log_archive_recovers_from_interrupted_append() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let archive = LogArchive::open(tempdir.path()).with_limits(256, u64::MAX);
+
+        let entries = (1..=12)
+            .map(|index| new_entry(index, 1))
+            .collect::<Vec<_>>();
+        archive.append(&entries[..4]).unwrap();
+
+        // Crashed while writing an index record.
+        let last_segment = *archive.segments().unwrap().last().unwrap();
+        let index_path = archive
+            .segment_path(last_segment)
+            .with_extension(INDEX_FILE_EXTENSION);
+        let mut index_file = OpenOptions::new().append(true).open(&index_path).unwrap();
+        index_file.write_all(&[0u8; 5]).unwrap();
+        archive.append(&entries[4..8]).unwrap();
+        assert_eq!(archive.read(1, 9).unwrap(), entries[..8]);
+
+        // Crashed after writing a new segment, but before indexing it.
+        let last_segment = *archive.segments().unwrap().last().unwrap();
+        let segment_path = archive.segment_path(last_segment);
+        fs::remove_file(segment_path.with_extension(INDEX_FILE_EXTENSION)).unwrap();
+        assert_eq!(archive.last_index().unwrap(), Some(last_segment - 1));
+
+        archive
+            .append(&entries[last_segment as usize - 1..])
+            .unwrap();
+        assert_eq!(archive.last_index().unwrap(), Some(12));
+        assert_eq!(archive.read(1, 13).unwrap(), entries);
+    }
+
+    #[test]
+    fn test_log_archive_detects_corruption() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let archive = LogArchive::open(tempdir.path());
+        archive.append(&[new_entry(1, 1)]).unwrap();
+
+        let segment_path = archive.segment_path(1);
+        let mut data = fs::read(&segment_path).unwrap();
+        let last = data.len() - 1;
+        data[last] ^= 0xff;
+        fs::write(&segment_path, data).unwrap();
+
+        assert!(archive.read(1, 2).is_err());
+    }
+}
@@ -7,7 +7,12 @@ pub mod inmemory_storage;
 #[cfg(feature = "rocksdb_storage")]
 pub mod rocksdb_storage;
 
-pub mod utils;
+#[cfg(any(feature = "heed_storage", feature = "rocksdb_storage"))]
+pub mod snapshot_files;
+
+pub mod log_archive;
+
+use std::path::{Path, PathBuf};
 
 use crate::{
     error::Result,
@@ -31,7 +36,32 @@ pub trait StableStorage: Storage {
     fn conf_state(&self) -> Result<ConfState>;
     fn set_conf_state(&mut self, conf_state: &ConfState) -> Result<()>;
     fn create_snapshot(&mut self, data: Vec<u8>, index: u64, term: u64) -> Result<()>;
-    fn apply_snapshot(&mut self, snapshot: Snapshot) -> Result<()>;
+    fn apply_snapshot(&mut self, snapshot: &Snapshot) -> Result<()>;
     fn compact(&mut self, index: u64) -> Result<()>;
     fn all_entries(&self) -> raft::Result<Vec<Entry>>;
+
+    /// Path of a file the state machine can stream a new snapshot into before
+    /// calling `create_snapshot_from_file`. `None` if snapshots can't be staged in files.
+    fn snapshot_staging_path(&self) -> Option<PathBuf> {
+        None
+    }
+
+    /// Creates a snapshot from the data staged at `path`.
+    fn create_snapshot_from_file(&mut self, path: &Path, index: u64, term: u64) -> Result<()> {
+        let data = std::fs::read(path)?;
+        std::fs::remove_file(path)?;
+        self.create_snapshot(data, index, term)
+    }
+
+    /// Path of the file holding the current snapshot data, so that it can be streamed
+    /// into the state machine. `None` if the snapshot data is not kept in a file.
+    fn snapshot_data_path(&self) -> Result<Option<PathBuf>> {
+        Ok(None)
+    }
+
+    /// Index and term of a snapshot found corrupted since the last call. It isn't sent
+    /// to followers anymore, so the node replaces it with a new snapshot.
+    fn take_corrupted_snapshot(&self) -> Option<(u64, u64)> {
+        None
+    }
 }
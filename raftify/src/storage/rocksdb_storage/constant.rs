@@ -1,4 +1,5 @@
 pub const SNAPSHOT_KEY: &[u8] = b"snapshot";
+pub const SNAPSHOT_FILE_KEY: &[u8] = b"snapshot_file";
 pub const LAST_INDEX_KEY: &[u8] = b"last_index";
 pub const HARD_STATE_KEY: &[u8] = b"hard_state";
 pub const CONF_STATE_KEY: &[u8] = b"conf_state";
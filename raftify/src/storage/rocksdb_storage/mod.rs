@@ -3,28 +3,33 @@ mod constant;
 
 use crate::raft::eraftpb::Entry;
 use crate::raft::logger::Logger;
-use crate::raft::prelude::{ConfState, HardState, Snapshot};
+use crate::raft::prelude::{ConfState, HardState, Snapshot, SnapshotMetadata};
 use crate::raft::{GetEntriesContext, RaftState, Storage};
-use crate::{Result, StableStorage};
+use crate::{DatabaseError, Error, Result, StableStorage};
 use codec::format_entry_key_string;
 use constant::{
-    CONF_STATE_KEY, HARD_STATE_KEY, LAST_INDEX_KEY, LOG_ENTRY_CF_KEY, METADATA_CF_KEY, SNAPSHOT_KEY,
+    CONF_STATE_KEY, HARD_STATE_KEY, LAST_INDEX_KEY, LOG_ENTRY_CF_KEY, METADATA_CF_KEY,
+    SNAPSHOT_FILE_KEY, SNAPSHOT_KEY,
 };
 use parking_lot::{RwLock, RwLockReadGuard, RwLockWriteGuard};
 use prost::Message;
 use raft::util::limit_size;
-use rocksdb::{ColumnFamilyDescriptor, Options, DB as RocksDB};
+use rocksdb::{ColumnFamily, ColumnFamilyDescriptor, Options, WriteBatch, DB as RocksDB};
 use std::cmp::max;
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
-use super::StorageType;
+use super::{
+    snapshot_files::{snapshot_unavailable, SnapshotFileMeta, SnapshotFiles},
+    StorageType,
+};
 
 #[derive(Clone)]
 pub struct RocksDBStorage(Arc<RwLock<RocksDBStorageCore>>);
 
 pub struct RocksDBStorageCore {
     db: RocksDB,
+    snapshot_files: SnapshotFiles,
     logger: Arc<dyn Logger>,
 }
 
@@ -98,7 +103,7 @@ impl StableStorage for RocksDBStorage {
         Ok(())
     }
 
-    fn apply_snapshot(&mut self, snapshot: Snapshot) -> Result<()> {
+    fn apply_snapshot(&mut self, snapshot: &Snapshot) -> Result<()> {
         let mut store = self.wl();
         store.apply_snapshot(snapshot)?;
         Ok(())
@@ -115,6 +120,24 @@ impl StableStorage for RocksDBStorage {
         let entries = store.all_entries()?;
         Ok(entries)
     }
+
+    fn snapshot_staging_path(&self) -> Option<PathBuf> {
+        Some(self.rl().snapshot_files.staging_path())
+    }
+
+    fn create_snapshot_from_file(&mut self, path: &Path, index: u64, term: u64) -> Result<()> {
+        let mut store = self.wl();
+        store.create_snapshot_from_file(path, index, term)
+    }
+
+    fn snapshot_data_path(&self) -> Result<Option<PathBuf>> {
+        let store = self.rl();
+        store.snapshot_data_path()
+    }
+
+    fn take_corrupted_snapshot(&self) -> Option<(u64, u64)> {
+        self.rl().snapshot_files.take_corrupted()
+    }
 }
 
 impl Storage for RocksDBStorage {
@@ -154,27 +177,41 @@ impl Storage for RocksDBStorage {
         Ok(index)
     }
 
-    fn snapshot(&self, _request_index: u64, _to: u64) -> crate::raft::Result<Snapshot> {
+    fn snapshot(&self, request_index: u64, to: u64) -> crate::raft::Result<Snapshot> {
         let store = self.rl();
-        let snapshot = store.snapshot(_request_index, _to)?;
-        Ok(snapshot)
+        store
+            .snapshot(request_index, to)
+            .map_err(|e| snapshot_unavailable(e, store.logger.as_ref()))
     }
 }
 
 impl RocksDBStorageCore {
+    fn cf_descriptors() -> Vec<ColumnFamilyDescriptor> {
+        let cf_opts = Options::default();
+        vec![
+            ColumnFamilyDescriptor::new(LOG_ENTRY_CF_KEY, cf_opts.clone()),
+            ColumnFamilyDescriptor::new(METADATA_CF_KEY, cf_opts),
+        ]
+    }
+
     pub fn create(path: PathBuf, logger: Arc<dyn Logger>) -> Result<Self> {
         let mut db_opts = Options::default();
         db_opts.create_if_missing(true);
         db_opts.create_missing_column_families(true);
 
-        let cf_opts = Options::default();
-        let cf_descriptors = vec![
-            ColumnFamilyDescriptor::new(LOG_ENTRY_CF_KEY, cf_opts.clone()),
-            ColumnFamilyDescriptor::new(METADATA_CF_KEY, cf_opts.clone()),
-        ];
+        let db = RocksDB::open_cf_descriptors(&db_opts, &path, Self::cf_descriptors())?;
+        let storage = RocksDBStorageCore {
+            db,
+            snapshot_files: SnapshotFiles::create(&path)?,
+            logger,
+        };
+
+        // Clean up the snapshot files left behind by a crash between writing a snapshot file and committing its metadata
+        storage
+            .snapshot_files
+            .remove_stale(storage.snapshot_file()?.as_ref())?;
 
-        let db = RocksDB::open_cf_descriptors(&db_opts, path, cf_descriptors).unwrap();
-        Ok(RocksDBStorageCore { db, logger })
+        Ok(storage)
     }
 
     pub fn open_readonly(path: PathBuf, logger: Arc<dyn Logger>) -> Result<Self> {
@@ -182,40 +219,47 @@ impl RocksDBStorageCore {
         db_opts.create_if_missing(true);
         db_opts.create_missing_column_families(true);
 
-        let cf_opts = Options::default();
-        let cf_descriptors = vec![
-            ColumnFamilyDescriptor::new(LOG_ENTRY_CF_KEY, cf_opts.clone()),
-            ColumnFamilyDescriptor::new(METADATA_CF_KEY, cf_opts.clone()),
-        ];
-
         let db =
-            RocksDB::open_cf_descriptors_read_only(&db_opts, path, cf_descriptors, false).unwrap();
-        Ok(RocksDBStorageCore { db, logger })
+            RocksDB::open_cf_descriptors_read_only(&db_opts, &path, Self::cf_descriptors(), false)?;
+        Ok(RocksDBStorageCore {
+            db,
+            snapshot_files: SnapshotFiles::open(&path),
+            logger,
+        })
+    }
+
+    fn cf_handle(&self, name: &str) -> Result<&ColumnFamily> {
+        self.db.cf_handle(name).ok_or_else(|| {
+            DatabaseError::Corrupted(format!("Column family `{}` is missing", name)).into()
+        })
     }
 
     #[allow(dead_code)]
-    fn replace_entries(&self, entries: &[Entry]) -> crate::raft::Result<()> {
-        let cf_handle = self.db.cf_handle(LOG_ENTRY_CF_KEY).unwrap();
+    fn replace_entries(&self, entries: &[Entry]) -> Result<()> {
+        let cf_handle = self.cf_handle(LOG_ENTRY_CF_KEY)?;
 
         let mut last_index = self.last_index()?;
         let start = format_entry_key_string(0.to_string().as_str());
         let end = format_entry_key_string(last_index.to_string().as_str());
 
-        self.db.delete_range_cf(cf_handle, start, end).unwrap();
-
+        let mut batch = WriteBatch::default();
+        batch.delete_range_cf(cf_handle, start, end);
         for entry in entries {
             last_index = std::cmp::max(entry.index, last_index);
             let index = format_entry_key_string(entry.index.to_string().as_str());
-            self.db
-                .put_cf(cf_handle, index, entry.encode_to_vec())
-                .unwrap();
+            batch.put_cf(cf_handle, index, entry.encode_to_vec());
         }
-        self.set_last_index(last_index).unwrap();
+        batch.put_cf(
+            self.cf_handle(METADATA_CF_KEY)?,
+            LAST_INDEX_KEY,
+            last_index.to_string().as_bytes(),
+        );
+        self.db.write(batch)?;
         Ok(())
     }
 
     fn append(&mut self, entries: &[Entry]) -> Result<()> {
-        let cf_handle = self.db.cf_handle(LOG_ENTRY_CF_KEY).unwrap();
+        let cf_handle = self.cf_handle(LOG_ENTRY_CF_KEY)?;
 
         if entries.is_empty() {
             return Ok(());
@@ -230,31 +274,34 @@ impl RocksDBStorageCore {
             ));
         }
 
+        let mut batch = WriteBatch::default();
         for entry in entries {
             last_index = std::cmp::max(entry.index, last_index);
             let index = format_entry_key_string(entry.index.to_string().as_str());
-            self.db
-                .put_cf(cf_handle, index, entry.encode_to_vec())
-                .unwrap();
+            batch.put_cf(cf_handle, index, entry.encode_to_vec());
         }
-        self.set_last_index(last_index)?;
+        // Written along with the entries, so that a crash can't leave them out of the log.
+        batch.put_cf(
+            self.cf_handle(METADATA_CF_KEY)?,
+            LAST_INDEX_KEY,
+            last_index.to_string().as_bytes(),
+        );
+        self.db.write(batch)?;
         Ok(())
     }
 
     fn hard_state(&self) -> Result<HardState> {
-        let cf_handle = self.db.cf_handle(METADATA_CF_KEY).unwrap();
-        let result = self.db.get_cf(cf_handle, HARD_STATE_KEY).unwrap();
-        match result {
+        let cf_handle = self.cf_handle(METADATA_CF_KEY)?;
+        match self.db.get_cf(cf_handle, HARD_STATE_KEY)? {
             Some(data) => Ok(HardState::decode(&*data)?),
             None => Ok(HardState::default()),
         }
     }
 
     fn set_hard_state(&mut self, hard_state: &HardState) -> Result<()> {
-        let cf_handle = self.db.cf_handle(METADATA_CF_KEY).unwrap();
+        let cf_handle = self.cf_handle(METADATA_CF_KEY)?;
         self.db
-            .put_cf(cf_handle, HARD_STATE_KEY, hard_state.encode_to_vec())
-            .unwrap();
+            .put_cf(cf_handle, HARD_STATE_KEY, hard_state.encode_to_vec())?;
         Ok(())
     }
 
@@ -266,40 +313,98 @@ impl RocksDBStorageCore {
     }
 
     fn conf_state(&self) -> Result<ConfState> {
-        let cf_handle = self.db.cf_handle(METADATA_CF_KEY).unwrap();
-        let result = self.db.get_cf(cf_handle, CONF_STATE_KEY).unwrap();
-        match result {
+        let cf_handle = self.cf_handle(METADATA_CF_KEY)?;
+        match self.db.get_cf(cf_handle, CONF_STATE_KEY)? {
             Some(data) => Ok(ConfState::decode(&*data)?),
             None => Ok(ConfState::default()),
         }
     }
 
     fn set_conf_state(&mut self, conf_state: &ConfState) -> Result<()> {
-        let cf_handle = self.db.cf_handle(METADATA_CF_KEY).unwrap();
+        let cf_handle = self.cf_handle(METADATA_CF_KEY)?;
         self.db
-            .put_cf(cf_handle, CONF_STATE_KEY, conf_state.encode_to_vec())
-            .unwrap();
+            .put_cf(cf_handle, CONF_STATE_KEY, conf_state.encode_to_vec())?;
         Ok(())
     }
 
-    fn set_last_index(&self, index: u64) -> Result<()> {
-        let cf_handle = self.db.cf_handle(METADATA_CF_KEY).unwrap();
-        self.db
-            .put_cf(cf_handle, LAST_INDEX_KEY, index.to_string().as_bytes())
-            .unwrap();
+    /// Writes the snapshot data to a snapshot file and keeps only the metadata in the DB.
+    fn set_snapshot(&self, snapshot: &Snapshot) -> Result<()> {
+        let metadata = snapshot.get_metadata();
+        let snapshot_file =
+            self.snapshot_files
+                .write(metadata.index, metadata.term, snapshot.get_data())?;
+        self.set_snapshot_metadata(metadata, &snapshot_file)
+    }
+
+    fn set_snapshot_metadata(
+        &self,
+        metadata: &SnapshotMetadata,
+        snapshot_file: &SnapshotFileMeta,
+    ) -> Result<()> {
+        let mut batch = WriteBatch::default();
+        self.put_snapshot_metadata(&mut batch, metadata, snapshot_file)?;
+        self.db.write(batch)?;
+
+        self.snapshot_files.remove_stale(Some(snapshot_file))?;
         Ok(())
     }
 
-    fn set_snapshot(&self, snapshot: &Snapshot) -> Result<()> {
-        let cf_handle = self.db.cf_handle(METADATA_CF_KEY).unwrap();
-        self.db
-            .put_cf(cf_handle, SNAPSHOT_KEY, snapshot.encode_to_vec())
-            .unwrap();
+    fn put_snapshot_metadata(
+        &self,
+        batch: &mut WriteBatch,
+        metadata: &SnapshotMetadata,
+        snapshot_file: &SnapshotFileMeta,
+    ) -> Result<()> {
+        let cf_handle = self.cf_handle(METADATA_CF_KEY)?;
+        let mut metadata_only = Snapshot::default();
+        metadata_only.set_metadata(metadata.clone());
+
+        batch.put_cf(cf_handle, SNAPSHOT_KEY, metadata_only.encode_to_vec());
+        batch.put_cf(
+            cf_handle,
+            SNAPSHOT_FILE_KEY,
+            bincode::serialize(snapshot_file)?,
+        );
         Ok(())
     }
 
+    fn create_snapshot_from_file(&mut self, path: &Path, index: u64, term: u64) -> Result<()> {
+        let conf_state = self.conf_state()?;
+        let mut metadata = SnapshotMetadata::default();
+        metadata.set_conf_state(conf_state);
+        metadata.index = index;
+        metadata.term = term;
+
+        let snapshot_file = self.snapshot_files.adopt(path, index, term)?;
+        self.set_snapshot_metadata(&metadata, &snapshot_file)
+    }
+
+    fn snapshot_data_path(&self) -> Result<Option<PathBuf>> {
+        match self.snapshot_file()? {
+            Some(snapshot_file) => Ok(Some(self.snapshot_files.verified_path(&snapshot_file)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn snapshot_file(&self) -> Result<Option<SnapshotFileMeta>> {
+        let cf_handle = self.cf_handle(METADATA_CF_KEY)?;
+        match self.db.get_cf(cf_handle, SNAPSHOT_FILE_KEY)? {
+            Some(value) => Ok(Some(bincode::deserialize(&value)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Returns the snapshot metadata without loading the snapshot data.
+    fn snapshot_metadata(&self) -> Result<SnapshotMetadata> {
+        let cf_handle = self.cf_handle(METADATA_CF_KEY)?;
+        match self.db.get_cf(cf_handle, SNAPSHOT_KEY)? {
+            Some(value) => Ok(Snapshot::decode(&*value)?.metadata.unwrap_or_default()),
+            None => Ok(SnapshotMetadata::default()),
+        }
+    }
+
     fn create_snapshot(&mut self, data: Vec<u8>, index: u64, term: u64) -> Result<()> {
-        let conf_state = self.conf_state().unwrap();
+        let conf_state = self.conf_state()?;
         let mut snapshot = Snapshot::default();
         snapshot.set_data(data);
 
@@ -312,7 +417,7 @@ impl RocksDBStorageCore {
         Ok(())
     }
 
-    fn apply_snapshot(&mut self, snapshot: Snapshot) -> Result<()> {
+    fn apply_snapshot(&mut self, snapshot: &Snapshot) -> Result<()> {
         let metadata = snapshot.get_metadata();
         let conf_state = metadata.get_conf_state();
 
@@ -320,54 +425,64 @@ impl RocksDBStorageCore {
         hard_state.set_term(max(hard_state.term, metadata.term));
         hard_state.set_commit(metadata.index);
 
-        self.set_hard_state(&hard_state)?;
-        self.set_conf_state(conf_state)?;
-        self.set_last_index(metadata.index)?;
-        self.set_snapshot(&snapshot)?;
+        let snapshot_file =
+            self.snapshot_files
+                .write(metadata.index, metadata.term, snapshot.get_data())?;
+
+        // Written at once, so that a crash can't leave the state and the snapshot out of step.
+        let cf_handle = self.cf_handle(METADATA_CF_KEY)?;
+        let mut batch = WriteBatch::default();
+        batch.put_cf(cf_handle, HARD_STATE_KEY, hard_state.encode_to_vec());
+        batch.put_cf(cf_handle, CONF_STATE_KEY, conf_state.encode_to_vec());
+        batch.put_cf(
+            cf_handle,
+            LAST_INDEX_KEY,
+            metadata.index.to_string().as_bytes(),
+        );
+        self.put_snapshot_metadata(&mut batch, metadata, &snapshot_file)?;
+        self.db.write(batch)?;
+
+        self.snapshot_files.remove_stale(Some(&snapshot_file))?;
         Ok(())
     }
 
     fn compact(&mut self, index: u64) -> Result<()> {
-        let cf_handle = self.db.cf_handle(LOG_ENTRY_CF_KEY).unwrap();
+        // Entries not appended yet can't be compacted
+        let last_index = self.last_index()?;
+        if index > last_index + 1 {
+            return Err(Error::Other(
+                format!("Compaction index {index} is beyond the last index {last_index}").into(),
+            ));
+        }
+
+        // Already compacted
+        if index <= self.first_index()? {
+            return Ok(());
+        }
+
+        let cf_handle = self.cf_handle(LOG_ENTRY_CF_KEY)?;
         let start = format_entry_key_string(0.to_string().as_str());
         let end = format_entry_key_string((index).to_string().as_str());
-        self.db.delete_range_cf(cf_handle, start, end).unwrap();
+        self.db.delete_range_cf(cf_handle, start, end)?;
         Ok(())
     }
 
-    fn all_entries(&self) -> crate::raft::Result<Vec<Entry>> {
+    fn all_entries(&self) -> Result<Vec<Entry>> {
+        let cf_handle = self.cf_handle(LOG_ENTRY_CF_KEY)?;
         let mut entries = Vec::new();
-        let cf_handle = self.db.cf_handle(LOG_ENTRY_CF_KEY).unwrap();
-
-        let iter = self.db.iterator_cf(cf_handle, rocksdb::IteratorMode::Start);
-
-        for item in iter {
-            match item {
-                Ok((_key, value)) => match Entry::decode(&*value) {
-                    Ok(entry) => entries.push(entry),
-                    Err(e) => {
-                        return Err(crate::raft::Error::Store(crate::raft::StorageError::Other(
-                            Box::new(e),
-                        )))
-                    }
-                },
-                Err(e) => {
-                    return Err(crate::raft::Error::Store(crate::raft::StorageError::Other(
-                        Box::new(e),
-                    )))
-                }
-            }
+
+        for item in self.db.iterator_cf(cf_handle, rocksdb::IteratorMode::Start) {
+            let (_key, value) = item?;
+            entries.push(Entry::decode(&*value)?);
         }
 
         Ok(entries)
     }
 
-    fn initial_state(&self) -> crate::raft::Result<RaftState> {
-        let hard_state = self.hard_state().unwrap();
-        let conf_state = self.conf_state().unwrap();
+    fn initial_state(&self) -> Result<RaftState> {
         Ok(RaftState {
-            hard_state,
-            conf_state,
+            hard_state: self.hard_state()?,
+            conf_state: self.conf_state()?,
         })
     }
 
@@ -377,26 +492,18 @@ impl RocksDBStorageCore {
         high: u64,
         max_size: Option<u64>,
         _context: GetEntriesContext,
-    ) -> crate::raft::Result<Vec<Entry>> {
-        let cf_handle = self.db.cf_handle(LOG_ENTRY_CF_KEY).unwrap();
+    ) -> Result<Vec<Entry>> {
+        let cf_handle = self.cf_handle(LOG_ENTRY_CF_KEY)?;
         let mut entries = Vec::new();
 
-        if low
-            < self
-                .first_index()
-                .map_err(|_| raft::Error::Store(raft::StorageError::Unavailable))?
-        {
-            return Err(raft::Error::Store(raft::StorageError::Compacted));
+        if low < self.first_index()? {
+            return Err(raft::Error::Store(raft::StorageError::Compacted).into());
         }
 
         for idx in low..high {
             let index = format_entry_key_string(idx.to_string().as_str());
-            match self.db.get_cf(cf_handle, index).unwrap() {
-                Some(value) => {
-                    let entry = Entry::decode(&*value).unwrap();
-                    entries.push(entry);
-                }
-                None => continue,
+            if let Some(value) = self.db.get_cf(cf_handle, index)? {
+                entries.push(Entry::decode(&*value)?);
             }
         }
 
@@ -405,76 +512,64 @@ impl RocksDBStorageCore {
         Ok(entries)
     }
 
-    fn term(&self, idx: u64) -> crate::raft::Result<u64> {
-        let cf_handle = self.db.cf_handle(LOG_ENTRY_CF_KEY).unwrap();
+    fn term(&self, idx: u64) -> Result<u64> {
+        let cf_handle = self.cf_handle(LOG_ENTRY_CF_KEY)?;
         let first_index = self.first_index()?;
 
-        let snapshot = self.snapshot(0, 0)?;
-        if snapshot.get_metadata().get_index() == idx {
-            return Ok(snapshot.get_metadata().get_term());
+        let snapshot_metadata = self.snapshot_metadata()?;
+        if snapshot_metadata.get_index() == idx {
+            return Ok(snapshot_metadata.get_term());
         }
 
         let index = format_entry_key_string(idx.to_string().as_str());
 
-        if let Some(value) = self.db.get_cf(cf_handle, index).unwrap() {
-            match Entry::decode(&*value) {
-                Ok(entry) => Ok(entry.term),
-                Err(_e) => Err(crate::raft::Error::Store(
-                    crate::raft::StorageError::Unavailable,
-                )),
+        match self.db.get_cf(cf_handle, index)? {
+            Some(value) => Ok(Entry::decode(&*value)?.term),
+            None if idx < first_index => {
+                Err(raft::Error::Store(raft::StorageError::Compacted).into())
             }
-        } else if idx < first_index {
-            Err(crate::raft::Error::Store(
-                crate::raft::StorageError::Compacted,
-            ))
-        } else {
-            Err(crate::raft::Error::Store(
-                crate::raft::StorageError::Unavailable,
-            ))
+            None => Err(raft::Error::Store(raft::StorageError::Unavailable).into()),
         }
     }
 
-    fn first_index(&self) -> crate::raft::Result<u64> {
-        let cf_handle = self.db.cf_handle(LOG_ENTRY_CF_KEY).unwrap();
+    fn first_index(&self) -> Result<u64> {
+        let cf_handle = self.cf_handle(LOG_ENTRY_CF_KEY)?;
         let mut iter = self.db.iterator_cf(cf_handle, rocksdb::IteratorMode::Start);
 
         match iter.next() {
             Some(first) => {
-                let (_, value) = first.unwrap();
-                let entry = Entry::decode(&*value).unwrap();
-                Ok(entry.index)
-            }
-            None => {
-                let snapshot = self.snapshot(0, 0).unwrap();
-                Ok(snapshot.get_metadata().get_index() + 1)
+                let (_, value) = first?;
+                Ok(Entry::decode(&*value)?.index)
             }
+            None => Ok(self.snapshot_metadata()?.get_index() + 1),
         }
     }
 
-    fn last_index(&self) -> crate::raft::Result<u64> {
-        let cf_handle = self.db.cf_handle(METADATA_CF_KEY).unwrap();
-        let last_index = self
-            .db
-            .get_cf(cf_handle, LAST_INDEX_KEY)
-            .unwrap()
-            .unwrap_or("0".as_bytes().to_vec());
-        let last_index = String::from_utf8(last_index).expect("Found invalid UTF-8");
-        Ok(last_index.parse().unwrap())
+    fn last_index(&self) -> Result<u64> {
+        let cf_handle = self.cf_handle(METADATA_CF_KEY)?;
+        let last_index = match self.db.get_cf(cf_handle, LAST_INDEX_KEY)? {
+            Some(last_index) => last_index,
+            None => return Ok(0),
+        };
+
+        std::str::from_utf8(&last_index)
+            .ok()
+            .and_then(|last_index| last_index.parse().ok())
+            .ok_or_else(|| DatabaseError::Corrupted("Invalid last index".to_owned()).into())
     }
 
-    fn snapshot(&self, _request_index: u64, _to: u64) -> crate::raft::Result<Snapshot> {
-        let cf_handle = self.db.cf_handle(METADATA_CF_KEY).unwrap();
+    fn snapshot(&self, _request_index: u64, _to: u64) -> Result<Snapshot> {
+        let cf_handle = self.cf_handle(METADATA_CF_KEY)?;
+        let mut snapshot = match self.db.get_cf(cf_handle, SNAPSHOT_KEY)? {
+            Some(value) => Snapshot::decode(&*value)?,
+            None => return Ok(Snapshot::default()),
+        };
 
-        match self.db.get_cf(cf_handle, SNAPSHOT_KEY) {
-            Ok(Some(value)) => {
-                let snapshot = Snapshot::decode(&*value).unwrap();
-                Ok(snapshot)
-            }
-            Ok(None) => Ok(Snapshot::default()),
-            Err(_) => Err(crate::raft::Error::Store(
-                crate::raft::StorageError::SnapshotTemporarilyUnavailable,
-            )),
+        // Snapshots written by older versions keep their data inline and have no snapshot file
+        if let Some(snapshot_file) = self.snapshot_file()? {
+            snapshot.set_data(self.snapshot_files.read(&snapshot_file)?);
         }
+        Ok(snapshot)
     }
 }
 
@@ -490,7 +585,10 @@ mod test {
         logger::Slogger,
         Config as RaftConfig, Error as RaftError, GetEntriesContext, Storage, StorageError,
     };
-    use crate::{Config, HeedStorage, StableStorage};
+    use crate::{
+        storage::snapshot_files::SNAPSHOT_DIR_NAME, Config, DatabaseError, Error, HeedStorage,
+        RocksDBStorage, StableStorage,
+    };
     use prost::Message;
 
     fn new_entry(index: u64, term: u64) -> Entry {
@@ -882,7 +980,7 @@ mod test {
 
         // Apply snapshot successfully
         let snap = new_snapshot(4, 4, nodes.clone());
-        storage.apply_snapshot(snap).unwrap();
+        storage.apply_snapshot(&snap).unwrap();
 
         // Apply snapshot fails due to StorageError::SnapshotOutOfDate
         // TODO: Support the below test case
@@ -891,4 +989,71 @@ mod test {
 
         teardown(tempdir);
     }
+
+    #[test]
+    fn test_rocksdb_storage_compact_beyond_last_index() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let logger = Arc::new(Slogger {
+            slog: build_logger(),
+        });
+
+        let ents = vec![new_entry(1, 1), new_entry(2, 2), new_entry(3, 3)];
+        let mut storage = RocksDBStorage::create(tempdir.path().to_str().unwrap(), logger).unwrap();
+        storage.append(&ents).unwrap();
+
+        assert!(storage.compact(5).is_err());
+        assert_eq!(storage.first_index(), Ok(1));
+
+        storage.compact(2).unwrap();
+        assert_eq!(storage.first_index(), Ok(2));
+    }
+
+    #[test]
+    fn test_rocksdb_storage_reports_database_errors() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let logger = Arc::new(Slogger {
+            slog: build_logger(),
+        });
+
+        // A file where the database directory should be
+        let path = tempdir.path().join("not_a_directory");
+        fs::write(&path, b"").unwrap();
+
+        let result = RocksDBStorage::create(path.to_str().unwrap(), logger);
+        assert!(matches!(
+            result,
+            Err(Error::Database(DatabaseError::RocksDB(_)))
+        ));
+    }
+
+    #[test]
+    fn test_rocksdb_storage_withholds_corrupted_snapshot() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let logger = Arc::new(Slogger {
+            slog: build_logger(),
+        });
+
+        let mut storage = RocksDBStorage::create(tempdir.path().to_str().unwrap(), logger).unwrap();
+        storage.create_snapshot(b"data".to_vec(), 4, 4).unwrap();
+
+        let snapshot_dir = tempdir.path().join(SNAPSHOT_DIR_NAME);
+        let snapshot_file = fs::read_dir(&snapshot_dir)
+            .unwrap()
+            .next()
+            .unwrap()
+            .unwrap()
+            .path();
+        fs::write(snapshot_file, b"corrupted").unwrap();
+
+        // The leader keeps running, it just doesn't send the snapshot.
+        assert_eq!(
+            storage.snapshot(0, 0),
+            Err(RaftError::Store(
+                StorageError::SnapshotTemporarilyUnavailable
+            ))
+        );
+        // The corruption is reported once.
+        assert_eq!(storage.take_corrupted_snapshot(), Some((4, 4)));
+        assert_eq!(storage.take_corrupted_snapshot(), None);
+    }
 }
@@ -0,0 +1,317 @@
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::{self, File},
+    io::{Read, Write},
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    error::{Error, Result},
+    raft::{logger::Logger, Error as RaftError, StorageError},
+};
+
+pub const SNAPSHOT_DIR_NAME: &str = "snapshots";
+const SNAPSHOT_FILE_PREFIX: &str = "snapshot-";
+const SNAPSHOT_FILE_EXTENSION: &str = "snap";
+const SNAPSHOT_STAGING_FILE_NAME: &str = "snapshot-staging.tmp";
+
+/// Describes the snapshot file that holds the data of the current snapshot.
+/// Kept in the storage's metadata next to the snapshot metadata.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SnapshotFileMeta {
+    pub index: u64,
+    pub term: u64,
+    pub size: u64,
+    pub checksum: u32,
+}
+
+/// Stores snapshot data in files versioned by term and index, so that the
+/// database only needs to hold the snapshot metadata.
+pub struct SnapshotFiles {
+    dir: PathBuf,
+    // The snapshot file that failed its integrity check, so that it isn't read over and over again.
+    corrupted: Mutex<Option<SnapshotFileMeta>>,
+    /// Corrupted snapshot not yet returned by `take_corrupted`.
+    unreported: Mutex<Option<SnapshotFileMeta>>,
+}
+
+impl SnapshotFiles {
+    /// Creates the snapshot directory in `log_dir_path` if it doesn't exist yet.
+    pub fn create(log_dir_path: &Path) -> Result<Self> {
+        let files = Self::open(log_dir_path);
+        fs::create_dir_all(&files.dir)?;
+        Ok(files)
+    }
+
+    /// Opens the snapshot directory in `log_dir_path` without touching the file system,
+    /// e.g. to read the snapshots of a storage opened read-only.
+    pub fn open(log_dir_path: &Path) -> Self {
+        Self {
+            dir: log_dir_path.join(SNAPSHOT_DIR_NAME),
+            corrupted: Mutex::new(None),
+            unreported: Mutex::new(None),
+        }
+    }
+
+    fn file_path(&self, index: u64, term: u64) -> PathBuf {
+        // Zero padded so that the file names sort in (term, index) order
+        self.dir.join(format!(
+            "{}{:020}-{:020}.{}",
+            SNAPSHOT_FILE_PREFIX, term, index, SNAPSHOT_FILE_EXTENSION
+        ))
+    }
+
+    /// Writes the snapshot data to a temporary file first and renames it afterwards,
+    /// so that a crash never leaves a partially written snapshot file behind.
+    pub fn write(&self, index: u64, term: u64, data: &[u8]) -> Result<SnapshotFileMeta> {
+        let path = self.file_path(index, term);
+        let tmp_path = path.with_extension("tmp");
+
+        let mut file = File::create(&tmp_path)?;
+        file.write_all(data)?;
+        file.sync_all()?;
+        fs::rename(&tmp_path, &path)?;
+        *self.corrupted.lock() = None;
+
+        Ok(SnapshotFileMeta {
+            index,
+            term,
+            size: data.len() as u64,
+            checksum: crc32fast::hash(data),
+        })
+    }
+
+    /// Path a snapshot can be streamed into before being moved into place by `adopt`.
+    pub fn staging_path(&self) -> PathBuf {
+        self.dir.join(SNAPSHOT_STAGING_FILE_NAME)
+    }
+
+    /// Moves a completely written and synced snapshot file into place.
+    pub fn adopt(&self, staged_path: &Path, index: u64, term: u64) -> Result<SnapshotFileMeta> {
+        let (size, checksum) = checksum_file(staged_path)?;
+        fs::rename(staged_path, self.file_path(index, term))?;
+        *self.corrupted.lock() = None;
+
+        Ok(SnapshotFileMeta {
+            index,
+            term,
+            size,
+            checksum,
+        })
+    }
+
+    pub fn read(&self, meta: &SnapshotFileMeta) -> Result<Vec<u8>> {
+        let path = self.file_path(meta.index, meta.term);
+        self.check_not_corrupted(&path, meta)?;
+        let data = fs::read(&path)?;
+        self.verify(&path, meta, data.len() as u64, crc32fast::hash(&data))?;
+        Ok(data)
+    }
+
+    /// Checks the integrity of the snapshot file without loading it into memory and returns its path.
+    pub fn verified_path(&self, meta: &SnapshotFileMeta) -> Result<PathBuf> {
+        let path = self.file_path(meta.index, meta.term);
+        self.check_not_corrupted(&path, meta)?;
+        let (size, checksum) = checksum_file(&path)?;
+        self.verify(&path, meta, size, checksum)?;
+        Ok(path)
+    }
+
+    /// Fails right away for a file that already failed its integrity check. It stays corrupted
+    /// until a new snapshot replaces it, as the file is never written in place.
+    fn check_not_corrupted(&self, path: &Path, meta: &SnapshotFileMeta) -> Result<()> {
+        if self.corrupted.lock().as_ref() == Some(meta) {
+            return Err(Error::SnapshotIntegrityError(format!(
+                "{} is corrupted",
+                path.display()
+            )));
+        }
+        Ok(())
+    }
+
+    fn verify(&self, path: &Path, meta: &SnapshotFileMeta, size: u64, checksum: u32) -> Result<()> {
+        let result = verify(path, meta, size, checksum);
+        if result.is_err() {
+            *self.corrupted.lock() = Some(meta.clone());
+            *self.unreported.lock() = Some(meta.clone());
+        }
+        result
+    }
+
+    /// Index and term of the snapshot found corrupted since the last call, if any.
+    pub fn take_corrupted(&self) -> Option<(u64, u64)> {
+        self.unreported
+            .lock()
+            .take()
+            .map(|meta| (meta.index, meta.term))
+    }
+
+    /// Removes every snapshot file, including leftover temporary files, except the one described by `current`.
+    pub fn remove_stale(&self, current: Option<&SnapshotFileMeta>) -> Result<()> {
+        let current_path = current.map(|meta| self.file_path(meta.index, meta.term));
+
+        for dir_entry in fs::read_dir(&self.dir)? {
+            let path = dir_entry?.path();
+            let is_snapshot_file = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(|name| name.starts_with(SNAPSHOT_FILE_PREFIX));
+
+            if is_snapshot_file && Some(&path) != current_path.as_ref() {
+                fs::remove_file(&path)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Maps an error reading the current snapshot for raft-rs, which aborts the node on any error
+/// but `SnapshotTemporarilyUnavailable`. A corrupted snapshot isn't offered to followers until
+/// a new snapshot replaces it, while other errors are retried as they may be transient.
+pub(crate) fn snapshot_unavailable(e: Error, logger: &dyn Logger) -> RaftError {
+    match e {
+        Error::SnapshotIntegrityError(_) => logger.error(&format!(
+            "The snapshot is corrupted, it won't be sent until a new snapshot replaces it: {}",
+            e
+        )),
+        e => logger.error(&format!(
+            "Failed to read the snapshot, retrying later: {}",
+            e
+        )),
+    }
+    RaftError::Store(StorageError::SnapshotTemporarilyUnavailable)
+}
+
+fn checksum_file(path: &Path) -> Result<(u64, u32)> {
+    let mut file = File::open(path)?;
+    let mut hasher = crc32fast::Hasher::new();
+    let mut buf = vec![0; 64 * 1024];
+    let mut size = 0;
+
+    loop {
+        let n = file.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+        size += n as u64;
+    }
+
+    Ok((size, hasher.finalize()))
+}
+
+fn verify(path: &Path, meta: &SnapshotFileMeta, size: u64, checksum: u32) -> Result<()> {
+    if size != meta.size {
+        return Err(Error::SnapshotIntegrityError(format!(
+            "{} has {} bytes, expected {}",
+            path.display(),
+            size,
+            meta.size
+        )));
+    }
+
+    if checksum != meta.checksum {
+        return Err(Error::SnapshotIntegrityError(format!(
+            "{} has checksum {:#010x}, expected {:#010x}",
+            path.display(),
+            checksum,
+            meta.checksum
+        )));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn snapshot_file_count(dir: &Path) -> usize {
+        fs::read_dir(dir.join(SNAPSHOT_DIR_NAME)).unwrap().count()
+    }
+
+    #[test]
+    fn test_snapshot_files_write_and_read() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let files = SnapshotFiles::create(tempdir.path()).unwrap();
+
+        let meta = files.write(5, 2, b"snapshot data").unwrap();
+        assert_eq!(meta.size, 13);
+        assert_eq!(files.read(&meta).unwrap(), b"snapshot data");
+    }
+
+    #[test]
+    fn test_snapshot_files_open_leaves_file_system_untouched() {
+        let tempdir = tempfile::tempdir().unwrap();
+
+        let files = SnapshotFiles::open(tempdir.path());
+        assert!(!tempdir.path().join(SNAPSHOT_DIR_NAME).exists());
+
+        // Snapshots written by a storage opened with `create` can be read back.
+        let meta = SnapshotFiles::create(tempdir.path())
+            .unwrap()
+            .write(5, 2, b"snapshot data")
+            .unwrap();
+        assert_eq!(files.read(&meta).unwrap(), b"snapshot data");
+    }
+
+    #[test]
+    fn test_snapshot_files_remove_stale() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let files = SnapshotFiles::create(tempdir.path()).unwrap();
+
+        files.write(5, 2, b"old").unwrap();
+        files.write(8, 2, b"newer").unwrap();
+        let current = files.write(10, 3, b"current").unwrap();
+        assert_eq!(snapshot_file_count(tempdir.path()), 3);
+
+        files.remove_stale(Some(&current)).unwrap();
+        assert_eq!(snapshot_file_count(tempdir.path()), 1);
+        assert_eq!(files.read(&current).unwrap(), b"current");
+    }
+
+    #[test]
+    fn test_snapshot_files_detect_corruption() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let files = SnapshotFiles::create(tempdir.path()).unwrap();
+
+        let meta = files.write(5, 2, b"snapshot data").unwrap();
+        fs::write(files.file_path(5, 2), b"snapshot dat4").unwrap();
+
+        assert!(matches!(
+            files.read(&meta),
+            Err(Error::SnapshotIntegrityError(_))
+        ));
+        assert!(matches!(
+            files.verified_path(&meta),
+            Err(Error::SnapshotIntegrityError(_))
+        ));
+
+        // The corrupted file isn't read again, while a new snapshot replacing it can be read.
+        fs::remove_file(files.file_path(5, 2)).unwrap();
+        assert!(matches!(
+            files.read(&meta),
+            Err(Error::SnapshotIntegrityError(_))
+        ));
+        let meta = files.write(7, 2, b"new snapshot data").unwrap();
+        assert_eq!(files.read(&meta).unwrap(), b"new snapshot data");
+    }
+
+    #[test]
+    fn test_snapshot_files_adopt_staged_file() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let files = SnapshotFiles::create(tempdir.path()).unwrap();
+
+        fs::write(files.staging_path(), b"streamed snapshot").unwrap();
+        let meta = files.adopt(&files.staging_path(), 7, 3).unwrap();
+
+        assert!(!files.staging_path().exists());
+        assert_eq!(meta.size, 17);
+        assert_eq!(
+            fs::read(files.verified_path(&meta).unwrap()).unwrap(),
+            b"streamed snapshot"
+        );
+    }
+}
@@ -0,0 +1,327 @@
+use parking_lot::Mutex;
+use std::{collections::HashMap, convert::Infallible, net::SocketAddr, sync::Arc};
+use tokio::{
+    sync::{mpsc, oneshot, watch, Mutex as AsyncMutex},
+    task::JoinHandle,
+};
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::{
+    async_trait,
+    body::BoxBody,
+    codegen::{http, BoxFuture, Context, Poll, Service},
+    server::NamedService,
+    transport::{Body, Channel, Server},
+};
+
+#[cfg(feature = "tls")]
+use tonic::transport::{Certificate, Identity, ServerTlsConfig};
+
+use super::{ForwardedRequest, ForwardedResponse, InboundMessages, Transport};
+use crate::{
+    config::TlsConfig,
+    create_client,
+    error::SendMessageError,
+    raft::eraftpb::Message as RaftMessage,
+    raft_service::{raft_service_client::RaftServiceClient, MessageBatch},
+    utils::snapshot_chunk::split_snapshot_message,
+    Config, Peer, Result,
+};
+
+/// Sends messages over a long-lived `MessageStream` RPC per peer, snapshots over `InstallSnapshot`
+/// and forwarded requests over their own RPCs. Serves the node's `RaftService` to receive them.
+#[derive(Clone)]
+pub struct GrpcTransport {
+    connections: Arc<Mutex<HashMap<SocketAddr, Arc<AsyncMutex<Connection>>>>>,
+    snapshot_chunk_size: usize,
+    client_tls_config: Option<TlsConfig>,
+    #[cfg_attr(not(feature = "tls"), allow(dead_code))]
+    server_tls_config: Option<TlsConfig>,
+}
+
+/// The node's `RaftService`, with the types of its RaftServer erased so that `GrpcTransport`
+/// can serve it.
+#[derive(Clone)]
+pub(crate) struct RaftGrpcService(
+    Arc<
+        dyn Fn(http::Request<Body>) -> BoxFuture<http::Response<BoxBody>, Infallible> + Send + Sync,
+    >,
+);
+
+impl RaftGrpcService {
+    pub(crate) fn new<S>(service: S) -> Self
+    where
+        S: Service<http::Request<Body>, Response = http::Response<BoxBody>, Error = Infallible>
+            + Clone
+            + Send
+            + Sync
+            + 'static,
+        S::Future: Send + 'static,
+    {
+        Self(Arc::new(move |request| {
+            // The generated services are always ready, so each call can use a fresh clone.
+            let mut service = service.clone();
+            Box::pin(service.call(request))
+        }))
+    }
+}
+
+impl Service<http::Request<Body>> for RaftGrpcService {
+    type Response = http::Response<BoxBody>;
+    type Error = Infallible;
+    type Future = BoxFuture<Self::Response, Self::Error>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<std::result::Result<(), Infallible>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, request: http::Request<Body>) -> Self::Future {
+        (self.0)(request)
+    }
+}
+
+impl NamedService for RaftGrpcService {
+    const NAME: &'static str = "raft_service.RaftService";
+}
+
+#[derive(Default)]
+struct Connection {
+    client: Option<RaftServiceClient<Channel>>,
+    // Request side of the current MessageStream RPC and the task draining its responses
+    stream: Option<MessageStream>,
+}
+
+struct MessageStream {
+    tx_batch: mpsc::Sender<MessageBatch>,
+    drain_responses: JoinHandle<()>,
+    sent_batches: u64,
+    // Number of batches the peer acknowledged, i.e. handed over to its RaftNode
+    acked_batches: watch::Receiver<u64>,
+}
+
+impl MessageStream {
+    fn is_open(&self) -> bool {
+        !self.drain_responses.is_finished()
+    }
+
+    /// Waits until the peer acknowledged every batch sent so far, or the stream closed.
+    async fn flush(&mut self) {
+        let sent_batches = self.sent_batches;
+        let _ = self
+            .acked_batches
+            .wait_for(|acked_batches| *acked_batches >= sent_batches)
+            .await;
+    }
+}
+
+impl GrpcTransport {
+    pub fn new(config: &Config) -> Self {
+        Self {
+            connections: Arc::new(Mutex::new(HashMap::new())),
+            snapshot_chunk_size: config.snapshot_chunk_size as usize,
+            client_tls_config: config.client_tls_config.clone(),
+            server_tls_config: config.server_tls_config.clone(),
+        }
+    }
+
+    fn connection(&self, addr: SocketAddr) -> Arc<AsyncMutex<Connection>> {
+        self.connections.lock().entry(addr).or_default().clone()
+    }
+}
+
+impl Connection {
+    async fn client(
+        &mut self,
+        addr: SocketAddr,
+        tls_config: Option<TlsConfig>,
+    ) -> std::result::Result<RaftServiceClient<Channel>, SendMessageError> {
+        if self.client.is_none() {
+            let client = create_client(&addr, tls_config).await.map_err(|e| {
+                SendMessageError::ConnectionError(format!(
+                    "(to {}). Error: {:?}",
+                    addr,
+                    e.to_string()
+                ))
+            })?;
+            self.client = Some(client);
+        }
+        Ok(self.client.clone().unwrap())
+    }
+
+    async fn open_stream(
+        &mut self,
+        peer: &Peer,
+    ) -> std::result::Result<MessageStream, SendMessageError> {
+        let mut client = self
+            .client(peer.addr, peer.client_tls_config.clone())
+            .await?;
+
+        // Keep at most one batch in flight, so that a slow peer pushes back on the sender through HTTP/2 flow control.
+        let (tx_batch, rx_batch) = mpsc::channel(1);
+        let response = client
+            .message_stream(ReceiverStream::new(rx_batch))
+            .await
+            .map_err(|e| {
+                SendMessageError::TransmissionError(format!("(to {}). Error: {}", peer.addr, e))
+            })?;
+
+        // The peer responds once per batch
+        let mut responses = response.into_inner();
+        let (tx_acked, acked_batches) = watch::channel(0);
+        let drain_responses = tokio::spawn(async move {
+            while let Ok(Some(_)) = responses.message().await {
+                tx_acked.send_modify(|acked_batches| *acked_batches += 1);
+            }
+        });
+
+        Ok(MessageStream {
+            tx_batch,
+            drain_responses,
+            sent_batches: 0,
+            acked_batches,
+        })
+    }
+}
+
+#[async_trait]
+impl Transport for GrpcTransport {
+    async fn send_messages(
+        &self,
+        peer: &Peer,
+        messages: Vec<RaftMessage>,
+    ) -> std::result::Result<(), SendMessageError> {
+        let connection = self.connection(peer.addr);
+        let mut connection = connection.lock().await;
+
+        let is_stream_open = matches!(&connection.stream, Some(stream) if stream.is_open());
+
+        if !is_stream_open {
+            connection.stream = Some(connection.open_stream(peer).await?);
+        }
+
+        let stream = connection.stream.as_mut().unwrap();
+        if stream
+            .tx_batch
+            .send(MessageBatch { messages })
+            .await
+            .is_err()
+        {
+            connection.stream = None;
+            return Err(SendMessageError::TransmissionError(format!(
+                "(to {}). Message stream closed",
+                peer.addr
+            )));
+        }
+        stream.sent_batches += 1;
+
+        Ok(())
+    }
+
+    async fn send_snapshot(
+        &self,
+        peer: &Peer,
+        message: RaftMessage,
+    ) -> std::result::Result<(), SendMessageError> {
+        let connection = self.connection(peer.addr);
+        let mut connection = connection.lock().await;
+        // The snapshot goes over its own RPC, so it could overtake the messages sent before it.
+        if let Some(stream) = connection.stream.as_mut() {
+            stream.flush().await;
+        }
+        let mut client = connection
+            .client(peer.addr, peer.client_tls_config.clone())
+            .await?;
+        drop(connection);
+
+        let chunks = split_snapshot_message(message, self.snapshot_chunk_size);
+        client
+            .install_snapshot(tokio_stream::iter(chunks))
+            .await
+            .map_err(|e| {
+                SendMessageError::TransmissionError(format!("(to {}). Error: {}", peer.addr, e))
+            })?;
+
+        Ok(())
+    }
+
+    async fn forward(
+        &self,
+        addr: SocketAddr,
+        request: ForwardedRequest,
+    ) -> std::result::Result<ForwardedResponse, SendMessageError> {
+        let mut client = self
+            .connection(addr)
+            .lock()
+            .await
+            .client(addr, self.client_tls_config.clone())
+            .await?;
+
+        let response = match request {
+            ForwardedRequest::RequestId(args) => client
+                .request_id(args)
+                .await
+                .map(|response| ForwardedResponse::RequestId(response.into_inner())),
+            ForwardedRequest::ChangeConfig(args) => client
+                .change_config(args)
+                .await
+                .map(|response| ForwardedResponse::ChangeConfig(response.into_inner())),
+            ForwardedRequest::PromoteLearner(args) => client
+                .promote_learner(args)
+                .await
+                .map(|response| ForwardedResponse::ChangeConfig(response.into_inner())),
+            ForwardedRequest::Propose(args) => client
+                .propose(args)
+                .await
+                .map(|response| ForwardedResponse::Propose(response.into_inner())),
+            ForwardedRequest::ReadIndex(args) => client
+                .read_index(args)
+                .await
+                .map(|response| ForwardedResponse::ReadIndex(response.into_inner())),
+        };
+
+        response.map_err(|e| {
+            SendMessageError::TransmissionError(format!("(to {}). Error: {}", addr, e))
+        })
+    }
+
+    async fn serve(
+        &self,
+        addr: SocketAddr,
+        inbound: InboundMessages,
+        rx_quit: oneshot::Receiver<()>,
+    ) -> Result<()> {
+        let mut server_builder = Server::builder();
+
+        #[cfg(feature = "tls")]
+        if let Some(tls_cfg) = &self.server_tls_config {
+            let cert_path = tls_cfg
+                .cert_path
+                .as_ref()
+                .expect("Server requires cert_path");
+            let cert = tokio::fs::read(cert_path).await?;
+            let key_path = tls_cfg.key_path.as_ref().expect("Server requires key_path");
+            let key = tokio::fs::read(key_path).await?;
+            let identity = Identity::from_pem(cert, key);
+
+            let mut tls_config = ServerTlsConfig::new().identity(identity);
+
+            // mTLS
+            if let Some(ca_cert_path) = &tls_cfg.ca_cert_path {
+                let ca_cert = tokio::fs::read(ca_cert_path).await?;
+                let ca_cert = Certificate::from_pem(ca_cert);
+                tls_config = tls_config.client_ca_root(ca_cert);
+            }
+
+            server_builder = server_builder.tls_config(tls_config)?;
+        }
+
+        // The MessageStream, InstallSnapshot and request RPCs are the other end of this transport.
+        server_builder
+            .add_service(inbound.grpc_service())
+            .serve_with_shutdown(addr, async {
+                rx_quit.await.ok();
+            })
+            .await?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,72 @@
+use parking_lot::RwLock;
+use std::{collections::HashMap, net::SocketAddr, sync::Arc};
+use tokio::sync::oneshot;
+use tonic::async_trait;
+
+use super::{ForwardedRequest, ForwardedResponse, InboundMessages, Transport};
+use crate::{error::SendMessageError, raft::eraftpb::Message as RaftMessage, Peer, Result};
+
+/// Connects nodes running in the same process through channels, without opening any sockets.
+/// All nodes of the cluster must share clones of the same `InProcessTransport`,
+/// and are addressed by their `raft_addr` as usual.
+#[derive(Clone, Default)]
+pub struct InProcessTransport {
+    nodes: Arc<RwLock<HashMap<SocketAddr, InboundMessages>>>,
+}
+
+impl InProcessTransport {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn inbound(&self, addr: SocketAddr) -> std::result::Result<InboundMessages, SendMessageError> {
+        self.nodes
+            .read()
+            .get(&addr)
+            .cloned()
+            .ok_or_else(|| SendMessageError::ConnectionError(format!("No node serves {}", addr)))
+    }
+}
+
+#[async_trait]
+impl Transport for InProcessTransport {
+    async fn send_messages(
+        &self,
+        peer: &Peer,
+        messages: Vec<RaftMessage>,
+    ) -> std::result::Result<(), SendMessageError> {
+        let inbound = self.inbound(peer.addr)?;
+        for message in messages {
+            inbound.deliver(message).await?;
+        }
+        Ok(())
+    }
+
+    async fn send_snapshot(
+        &self,
+        peer: &Peer,
+        message: RaftMessage,
+    ) -> std::result::Result<(), SendMessageError> {
+        self.inbound(peer.addr)?.deliver(message).await
+    }
+
+    async fn forward(
+        &self,
+        addr: SocketAddr,
+        request: ForwardedRequest,
+    ) -> std::result::Result<ForwardedResponse, SendMessageError> {
+        self.inbound(addr)?.handle(request).await
+    }
+
+    async fn serve(
+        &self,
+        addr: SocketAddr,
+        inbound: InboundMessages,
+        rx_quit: oneshot::Receiver<()>,
+    ) -> Result<()> {
+        self.nodes.write().insert(addr, inbound);
+        let _ = rx_quit.await;
+        self.nodes.write().remove(&addr);
+        Ok(())
+    }
+}
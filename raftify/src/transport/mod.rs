@@ -0,0 +1,152 @@
+mod grpc;
+mod in_process;
+
+pub use grpc::GrpcTransport;
+pub use in_process::InProcessTransport;
+
+pub(crate) use grpc::RaftGrpcService;
+
+use std::{future::Future, net::SocketAddr, pin::Pin, sync::Arc};
+use tokio::sync::oneshot;
+use tonic::async_trait;
+
+use crate::{
+    error::SendMessageError, raft::eraftpb::Message as RaftMessage, raft_service, Peer, Result,
+};
+
+type DeliverFuture = Pin<Box<dyn Future<Output = bool> + Send>>;
+type HandleFuture =
+    Pin<Box<dyn Future<Output = std::result::Result<ForwardedResponse, SendMessageError>> + Send>>;
+
+/// Request that a node can't serve by itself, e.g. a proposal made on a follower,
+/// forwarded to the leader.
+#[derive(Clone, Debug)]
+pub enum ForwardedRequest {
+    RequestId(raft_service::RequestIdArgs),
+    ChangeConfig(raft_service::ChangeConfigArgs),
+    PromoteLearner(raft_service::PromoteLearnerArgs),
+    Propose(raft_service::ProposeArgs),
+    ReadIndex(raft_service::ReadIndexArgs),
+}
+
+#[derive(Clone, Debug)]
+pub enum ForwardedResponse {
+    RequestId(raft_service::RequestIdResponse),
+    ChangeConfig(raft_service::ChangeConfigResponse),
+    Propose(raft_service::ProposeResponse),
+    ReadIndex(raft_service::ReadIndexResponse),
+}
+
+/// Hands the messages and requests received by a transport over to the local RaftNode.
+#[derive(Clone)]
+pub struct InboundMessages {
+    deliver: Arc<dyn Fn(RaftMessage) -> DeliverFuture + Send + Sync>,
+    handle: Arc<dyn Fn(ForwardedRequest) -> HandleFuture + Send + Sync>,
+    grpc_service: Arc<dyn Fn(InboundMessages) -> RaftGrpcService + Send + Sync>,
+}
+
+impl InboundMessages {
+    pub(crate) fn new<D, H, S>(deliver: D, handle: H, grpc_service: S) -> Self
+    where
+        D: Fn(RaftMessage) -> DeliverFuture + Send + Sync + 'static,
+        H: Fn(ForwardedRequest) -> HandleFuture + Send + Sync + 'static,
+        S: Fn(InboundMessages) -> RaftGrpcService + Send + Sync + 'static,
+    {
+        Self {
+            deliver: Arc::new(deliver),
+            handle: Arc::new(handle),
+            grpc_service: Arc::new(grpc_service),
+        }
+    }
+
+    /// Passes every delivered message through `intercept` first, e.g. to inject faults
+    /// into what the node receives. The messages it returns `None` for are dropped.
+    pub fn intercept<F, Fut>(self, intercept: F) -> Self
+    where
+        F: Fn(RaftMessage) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Option<RaftMessage>> + Send + 'static,
+    {
+        let deliver = self.deliver.clone();
+        Self {
+            deliver: Arc::new(move |message| {
+                let deliver = deliver.clone();
+                let intercepted = intercept(message);
+                Box::pin(async move {
+                    match intercepted.await {
+                        Some(message) => deliver(message).await,
+                        // Lost like a message dropped on the wire, not because the node shut down
+                        None => true,
+                    }
+                })
+            }),
+            ..self
+        }
+    }
+
+    pub async fn deliver(&self, message: RaftMessage) -> std::result::Result<(), SendMessageError> {
+        if (self.deliver)(message).await {
+            Ok(())
+        } else {
+            Err(SendMessageError::TransmissionError(
+                "The receiving RaftNode has shut down".to_owned(),
+            ))
+        }
+    }
+
+    /// Serves a request forwarded by another node, as the RaftServer would.
+    pub async fn handle(
+        &self,
+        request: ForwardedRequest,
+    ) -> std::result::Result<ForwardedResponse, SendMessageError> {
+        (self.handle)(request).await
+    }
+
+    /// The node's `RaftService` for `GrpcTransport` to serve.
+    /// The messages it receives are delivered through `self`.
+    pub(crate) fn grpc_service(&self) -> RaftGrpcService {
+        (self.grpc_service)(self.clone())
+    }
+}
+
+/// Carries raft messages and forwarded requests between the nodes of a cluster.
+/// `Raft::bootstrap` uses `GrpcTransport`, `Raft::bootstrap_with_transport` takes another one.
+#[async_trait]
+pub trait Transport: Send + Sync + 'static {
+    /// Sends the messages to the peer, preserving their order.
+    async fn send_messages(
+        &self,
+        peer: &Peer,
+        messages: Vec<RaftMessage>,
+    ) -> std::result::Result<(), SendMessageError>;
+
+    /// Sends a MsgSnapshot to the peer. Snapshots can be much larger than the other messages.
+    /// Must not be delivered ahead of the messages previously passed to `send_messages`.
+    async fn send_snapshot(
+        &self,
+        peer: &Peer,
+        message: RaftMessage,
+    ) -> std::result::Result<(), SendMessageError>;
+
+    /// Sends the request to the node at `addr`, usually the leader, and waits for its response.
+    /// The receiving node passes it to `InboundMessages::handle`.
+    async fn forward(
+        &self,
+        addr: SocketAddr,
+        request: ForwardedRequest,
+    ) -> std::result::Result<ForwardedResponse, SendMessageError>;
+
+    /// Receives the messages and requests sent to `addr` and hands them over to `inbound`
+    /// until `rx_quit` fires.
+    async fn serve(
+        &self,
+        addr: SocketAddr,
+        inbound: InboundMessages,
+        rx_quit: oneshot::Receiver<()>,
+    ) -> Result<()>;
+}
+
+/// Parses the address of the leader a request is forwarded to.
+pub(crate) fn parse_addr(addr: &str) -> std::result::Result<SocketAddr, SendMessageError> {
+    addr.parse()
+        .map_err(|_| SendMessageError::ConnectionError(format!("Invalid address {}", addr)))
+}
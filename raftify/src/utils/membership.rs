@@ -1,4 +1,9 @@
-use crate::raft::eraftpb::{ConfChange, ConfChangeSingle, ConfChangeV2};
+use crate::{
+    error::{Error, Result},
+    raft::eraftpb::{ConfChange, ConfChangeSingle, ConfChangeV2},
+    raft_service,
+    response::server_response_message::ConfChangeResponseResult,
+};
 
 pub fn to_confchange_v2(conf_change: ConfChange) -> ConfChangeV2 {
     let mut cc_v2 = ConfChangeV2::default();
@@ -11,3 +16,40 @@ pub fn to_confchange_v2(conf_change: ConfChange) -> ConfChangeV2 {
 
     cc_v2
 }
+
+/// Converts the reply of a ChangeConfig or PromoteLearner RPC forwarded to the leader.
+pub fn to_conf_change_result(
+    response: raft_service::ChangeConfigResponse,
+) -> Result<ConfChangeResponseResult> {
+    let error = match response.result_type() {
+        raft_service::ChangeConfigResultType::ChangeConfigSuccess => None,
+        raft_service::ChangeConfigResultType::ChangeConfigRejected => Some(Error::Rejected(
+            String::from_utf8_lossy(&response.error).into(),
+        )),
+        raft_service::ChangeConfigResultType::ChangeConfigTimeoutError => Some(Error::Timeout),
+        raft_service::ChangeConfigResultType::ChangeConfigNoLeader => Some(Error::NoLeader),
+        _ => Some(Error::Unknown),
+    };
+    if let Some(error) = error {
+        return Ok(ConfChangeResponseResult::Error(error));
+    }
+
+    // Removals don't report the peers
+    if response.assigned_ids.is_empty() {
+        return Ok(ConfChangeResponseResult::RemoveSuccess);
+    }
+
+    let peers = bincode::deserialize(response.peers.as_slice())?;
+    if response.removed_ids.is_empty() {
+        Ok(ConfChangeResponseResult::JoinSuccess {
+            assigned_ids: response.assigned_ids,
+            peers,
+        })
+    } else {
+        Ok(ConfChangeResponseResult::ReplaceSuccess {
+            assigned_ids: response.assigned_ids,
+            removed_ids: response.removed_ids,
+            peers,
+        })
+    }
+}
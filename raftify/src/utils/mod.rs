@@ -1,3 +1,4 @@
 pub mod macros;
 pub mod membership;
 pub mod oneshot_mutex;
+pub mod snapshot_chunk;
@@ -0,0 +1,211 @@
+use crate::{
+    error::{Error, Result},
+    raft::eraftpb::{Message as RaftMessage, MessageType},
+    raft_service::SnapshotChunk,
+};
+
+/// Splits a MsgSnapshot into chunks of at most `chunk_size` bytes of snapshot data.
+/// The first chunk carries the message itself with its snapshot data stripped out.
+pub fn split_snapshot_message(
+    mut message: RaftMessage,
+    chunk_size: usize,
+) -> impl Iterator<Item = SnapshotChunk> + Send + 'static {
+    let data = std::mem::take(&mut message.mut_snapshot().data);
+    let total_size = data.len() as u64;
+    let checksum = crc32fast::hash(&data);
+    let chunk_size = chunk_size.max(1);
+    // Always emit at least one chunk so that an empty snapshot still delivers the message
+    let chunk_count = data.len().div_ceil(chunk_size).max(1);
+    let mut message = Some(message);
+
+    (0..chunk_count).map(move |i| {
+        let start = i * chunk_size;
+        let end = (start + chunk_size).min(data.len());
+
+        SnapshotChunk {
+            message: message.take(),
+            data: data[start..end].to_vec(),
+            offset: start as u64,
+            total_size,
+            checksum,
+        }
+    })
+}
+
+/// Reassembles the chunks produced by `split_snapshot_message` into the original MsgSnapshot.
+pub struct SnapshotChunkAssembler {
+    message: Option<RaftMessage>,
+    data: Vec<u8>,
+    total_size: u64,
+    checksum: u32,
+    max_snapshot_size: u64,
+}
+
+impl SnapshotChunkAssembler {
+    /// Snapshots announcing more than `max_snapshot_size` bytes are rejected.
+    pub fn new(max_snapshot_size: u64) -> Self {
+        Self {
+            message: None,
+            data: Vec::new(),
+            total_size: 0,
+            checksum: 0,
+            max_snapshot_size,
+        }
+    }
+
+    pub fn push(&mut self, chunk: SnapshotChunk) -> Result<()> {
+        if let Some(message) = chunk.message {
+            if self.message.is_some() {
+                return Err(Error::SnapshotTransferError(
+                    "Received the snapshot message more than once".to_owned(),
+                ));
+            }
+            if message.get_msg_type() != MessageType::MsgSnapshot {
+                return Err(Error::SnapshotTransferError(format!(
+                    "Expected MsgSnapshot, got {:?}",
+                    message.get_msg_type()
+                )));
+            }
+            // The announced size comes from the peer, so nothing is allocated for it upfront.
+            if chunk.total_size > self.max_snapshot_size {
+                return Err(Error::SnapshotTransferError(format!(
+                    "Snapshot size {} exceeds the maximum of {}",
+                    chunk.total_size, self.max_snapshot_size
+                )));
+            }
+            self.message = Some(message);
+            self.total_size = chunk.total_size;
+            self.checksum = chunk.checksum;
+        } else if self.message.is_none() {
+            return Err(Error::SnapshotTransferError(
+                "First chunk does not contain the snapshot message".to_owned(),
+            ));
+        }
+
+        if chunk.offset != self.data.len() as u64 {
+            return Err(Error::SnapshotTransferError(format!(
+                "Chunk out of order, expected offset {} but got {}",
+                self.data.len(),
+                chunk.offset
+            )));
+        }
+
+        if self.data.len() as u64 + chunk.data.len() as u64 > self.total_size {
+            return Err(Error::SnapshotTransferError(format!(
+                "Snapshot data exceeds the announced size {}",
+                self.total_size
+            )));
+        }
+
+        self.data.extend_from_slice(&chunk.data);
+        Ok(())
+    }
+
+    pub fn finish(self) -> Result<RaftMessage> {
+        let mut message = self
+            .message
+            .ok_or_else(|| Error::SnapshotTransferError("No snapshot chunk received".to_owned()))?;
+
+        if self.data.len() as u64 != self.total_size {
+            return Err(Error::SnapshotTransferError(format!(
+                "Incomplete snapshot, received {} of {} bytes",
+                self.data.len(),
+                self.total_size
+            )));
+        }
+
+        let checksum = crc32fast::hash(&self.data);
+        if checksum != self.checksum {
+            return Err(Error::SnapshotTransferError(format!(
+                "Checksum mismatch, expected {:#010x} but got {:#010x}",
+                self.checksum, checksum
+            )));
+        }
+
+        message.mut_snapshot().data = self.data;
+        Ok(message)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::raft::eraftpb::Snapshot;
+
+    fn snapshot_message(data: Vec<u8>) -> RaftMessage {
+        let mut snapshot = Snapshot::default();
+        snapshot.set_data(data);
+        snapshot.mut_metadata().set_index(10);
+        snapshot.mut_metadata().set_term(2);
+
+        let mut message = RaftMessage::default();
+        message.set_msg_type(MessageType::MsgSnapshot);
+        message.set_to(2);
+        message.set_snapshot(snapshot);
+        message
+    }
+
+    fn reassemble(chunks: impl Iterator<Item = SnapshotChunk>) -> Result<RaftMessage> {
+        let mut assembler = SnapshotChunkAssembler::new(u64::MAX);
+        for chunk in chunks {
+            assembler.push(chunk)?;
+        }
+        assembler.finish()
+    }
+
+    #[test]
+    fn test_split_and_reassemble_snapshot() {
+        let data = (0..10_000u32).map(|i| i as u8).collect::<Vec<_>>();
+        let message = snapshot_message(data.clone());
+
+        let chunks = split_snapshot_message(message.clone(), 1024).collect::<Vec<_>>();
+        assert_eq!(chunks.len(), 10);
+        assert!(chunks[0].message.is_some());
+        assert!(chunks[1..].iter().all(|chunk| chunk.message.is_none()));
+
+        let reassembled = reassemble(chunks.into_iter()).unwrap();
+        assert_eq!(reassembled, message);
+        assert_eq!(reassembled.get_snapshot().get_data(), data.as_slice());
+    }
+
+    #[test]
+    fn test_reassemble_empty_snapshot() {
+        let message = snapshot_message(vec![]);
+        let chunks = split_snapshot_message(message.clone(), 1024).collect::<Vec<_>>();
+        assert_eq!(chunks.len(), 1);
+        assert_eq!(reassemble(chunks.into_iter()).unwrap(), message);
+    }
+
+    #[test]
+    fn test_reject_corrupted_snapshot() {
+        let message = snapshot_message(vec![7; 4096]);
+        let mut chunks = split_snapshot_message(message, 1024).collect::<Vec<_>>();
+        chunks[2].data[0] ^= 0xff;
+        assert!(matches!(
+            reassemble(chunks.into_iter()),
+            Err(Error::SnapshotTransferError(_))
+        ));
+    }
+
+    #[test]
+    fn test_reject_oversized_snapshot() {
+        let message = snapshot_message(vec![7; 4096]);
+        let mut assembler = SnapshotChunkAssembler::new(1024);
+        let first_chunk = split_snapshot_message(message, 1024).next().unwrap();
+        assert!(matches!(
+            assembler.push(first_chunk),
+            Err(Error::SnapshotTransferError(_))
+        ));
+    }
+
+    #[test]
+    fn test_reject_out_of_order_chunks() {
+        let message = snapshot_message(vec![7; 4096]);
+        let mut chunks = split_snapshot_message(message, 1024).collect::<Vec<_>>();
+        chunks.swap(1, 2);
+        assert!(matches!(
+            reassemble(chunks.into_iter()),
+            Err(Error::SnapshotTransferError(_))
+        ));
+    }
+}